@@ -0,0 +1,113 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use futures::{channel::mpsc, executor, try_join};
+use rumpsteak::{session, try_session, Branch, End, Message, Role, Roles, Select};
+use std::{error::Error, result};
+
+type Result<T> = result::Result<T, Box<dyn Error>>;
+
+type Sender = mpsc::UnboundedSender<Label>;
+type Receiver = mpsc::UnboundedReceiver<Label>;
+
+#[derive(Roles)]
+struct Roles(P, C);
+
+#[derive(Role)]
+#[message(Label)]
+struct P(#[route(C)] Sender);
+
+#[derive(Role)]
+#[message(Label)]
+struct C(#[route(P)] Receiver);
+
+/// A wide choice where the label which is always selected comes last, so that
+/// a branch which tries each label in turn would have to try every one.
+#[derive(Message)]
+enum Label {
+    A(i32),
+    B(u32),
+    C(i64),
+    D(u64),
+    E(i16),
+    F(u16),
+    G(i8),
+    H(u8),
+    Done,
+}
+
+#[session]
+type Producer = Select<C, ProducerChoice>;
+
+#[session]
+#[allow(dead_code)]
+enum ProducerChoice {
+    A(i32, Producer),
+    B(u32, Producer),
+    C(i64, Producer),
+    D(u64, Producer),
+    E(i16, Producer),
+    F(u16, Producer),
+    G(i8, Producer),
+    H(u8, Producer),
+    Done(LabelDone, End),
+}
+
+#[session]
+type Consumer = Branch<P, ConsumerChoice>;
+
+#[session]
+enum ConsumerChoice {
+    A(i32, Consumer),
+    B(u32, Consumer),
+    C(i64, Consumer),
+    D(u64, Consumer),
+    E(i16, Consumer),
+    F(u16, Consumer),
+    G(i8, Consumer),
+    H(u8, Consumer),
+    Done(LabelDone, End),
+}
+
+async fn producer(role: &mut P, n: usize) -> Result<()> {
+    try_session(role, |mut s: Producer<'_, _>| async {
+        for _ in 0..n {
+            s = s.select(1u8).await?;
+        }
+
+        Ok(((), s.select(LabelDone).await?))
+    })
+    .await
+}
+
+async fn consumer(role: &mut C) -> Result<u64> {
+    try_session(role, |mut s: Consumer<'_, _>| async {
+        let mut sum = 0;
+        loop {
+            s = match s.branch().await? {
+                ConsumerChoice::H(x, s) => {
+                    sum += x as u64;
+                    s
+                }
+                ConsumerChoice::Done(_, s) => return Ok((sum, s)),
+                _ => unreachable!(),
+            };
+        }
+    })
+    .await
+}
+
+fn criterion_benchmark(criterion: &mut Criterion) {
+    const N: usize = 1000;
+
+    let Roles(mut p, mut c) = Roles::default();
+    criterion.bench_function("branch", |bencher| {
+        bencher.iter(|| {
+            executor::block_on(async {
+                let (_, sum) = try_join!(producer(&mut p, N), consumer(&mut c)).unwrap();
+                assert_eq!(sum, N as u64);
+            })
+        });
+    });
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
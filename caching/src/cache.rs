@@ -12,7 +12,7 @@ use tracing::{debug, error};
 const MAX_RETRY_DELAY: u64 = 1000;
 
 #[derive(Role)]
-#[message(Box<dyn Any + marker::Send>)]
+#[message(Box<dyn Any + marker::Send + Sync>)]
 pub struct Cache {
     #[route(Client)]
     pub(crate) client: Nil,
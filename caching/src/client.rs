@@ -5,7 +5,7 @@ use std::{any::Any, marker};
 use tracing::error;
 
 #[derive(Role)]
-#[message(Box<dyn Any + marker::Send>)]
+#[message(Box<dyn Any + marker::Send + Sync>)]
 pub struct Client {
     #[route(Proxy)]
     pub(crate) proxy: Channel,
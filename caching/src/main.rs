@@ -38,8 +38,9 @@ const DEFAULT_HEADERS: &[&str] = &["Cookie", "Host"];
 
 type Result<T, E = Box<dyn Error + Send + Sync>> = result::Result<T, E>;
 
-type Channel =
-    Bidirectional<UnboundedSender<Box<dyn Any + Send>>, UnboundedReceiver<Box<dyn Any + Send>>>;
+type Message = Box<dyn Any + Send + Sync>;
+
+type Channel = Bidirectional<UnboundedSender<Message>, UnboundedReceiver<Message>>;
 
 type Request = hyper::Request<Body>;
 
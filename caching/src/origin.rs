@@ -9,7 +9,7 @@ use std::{any::Any, marker, mem};
 use tracing::error;
 
 #[derive(Role)]
-#[message(Box<dyn Any + marker::Send>)]
+#[message(Box<dyn Any + marker::Send + Sync>)]
 pub struct Origin {
     #[route(Client)]
     pub(crate) client: Nil,
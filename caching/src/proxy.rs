@@ -11,7 +11,7 @@ use std::{any::Any, marker};
 use tracing::{debug, error};
 
 #[derive(Role)]
-#[message(Box<dyn Any + marker::Send>)]
+#[message(Box<dyn Any + marker::Send + Sync>)]
 pub struct Proxy {
     #[route(Client)]
     pub(crate) client: Channel,
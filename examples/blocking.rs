@@ -0,0 +1,91 @@
+use rumpsteak::{
+    blocking::{self, Receiver, Sender},
+    channel::Bidirectional,
+    session, Branch, End, Message, Receive, Role, Roles, Select, Send,
+};
+use std::{error::Error, result, thread};
+
+type Result<T> = result::Result<T, Box<dyn Error + std::marker::Send + Sync>>;
+
+type Channel = Bidirectional<Sender<Label>, Receiver<Label>>;
+
+#[derive(Roles)]
+struct Roles(C, S);
+
+#[derive(Role)]
+#[message(Label)]
+struct C(#[route(S)] Channel);
+
+#[derive(Role)]
+#[message(Label)]
+struct S(#[route(C)] Channel);
+
+#[derive(Message)]
+enum Label {
+    Add(Add),
+    Sum(Sum),
+    Done(Done),
+}
+
+struct Add(i32);
+struct Sum(i32);
+struct Done;
+
+#[session]
+type Client = Select<S, ClientChoice>;
+
+#[session]
+#[allow(dead_code)]
+enum ClientChoice {
+    Add(Add, Receive<S, Sum, Client>),
+    Done(Done, End),
+}
+
+#[session]
+type Server = Branch<C, ServerChoice>;
+
+#[session]
+enum ServerChoice {
+    Add(Add, Send<C, Sum, Server>),
+    Done(Done, End),
+}
+
+fn client(role: &mut C, values: &[i32]) -> Result<Vec<i32>> {
+    blocking::try_session(role, |mut s: Client<'_, _>| {
+        let mut sums = Vec::with_capacity(values.len());
+        for &value in values {
+            let (Sum(sum), t) = s.select_blocking(Add(value))?.receive_blocking()?;
+            sums.push(sum);
+            s = t;
+        }
+
+        let s = s.select_blocking(Done)?;
+        Ok((sums, s))
+    })
+}
+
+fn server(role: &mut S) -> Result<()> {
+    blocking::try_session(role, |mut s: Server<'_, _>| {
+        let mut total = 0;
+        loop {
+            s = match s.branch_blocking()? {
+                ServerChoice::Add(Add(value), t) => {
+                    total += value;
+                    t.send_blocking(Sum(total))?
+                }
+                ServerChoice::Done(Done, s) => return Ok(((), s)),
+            };
+        }
+    })
+}
+
+fn main() {
+    let Roles(mut c, mut s) = Roles::default();
+    let server = thread::spawn(move || server(&mut s));
+
+    let sums = client(&mut c, &[1, 2, 3, 4]).unwrap();
+    server.join().unwrap().unwrap();
+
+    println!("running sums: {:?}", sums);
+    assert_eq!(sums, [1, 3, 6, 10]);
+}
@@ -0,0 +1,95 @@
+use futures::{channel::mpsc::Receiver, executor, try_join};
+use rumpsteak::{
+    channel::{Bidirectional, BoundedSender},
+    session, try_session, Branch, End, Message, Receive, Role, Roles, Select, Send,
+};
+use std::{error::Error, result};
+
+type Result<T> = result::Result<T, Box<dyn Error>>;
+
+/// A route which lets at most `N` messages, plus one for the sender, queue up
+/// before the sender has to wait.
+type Channel<const N: usize> = Bidirectional<BoundedSender<Label, N>, Receiver<Label>>;
+
+#[derive(Roles)]
+struct Roles(P, C);
+
+/// A producer which can run ahead of the consumer by at most a few values.
+#[derive(Role)]
+#[message(Label)]
+struct P(#[route(C)] Channel<4>);
+
+#[derive(Role)]
+#[message(Label)]
+struct C(#[route(P)] Channel<4>);
+
+#[derive(Message)]
+enum Label {
+    Value(Value),
+    Done(Done),
+    Sum(Sum),
+}
+
+struct Value(u64);
+struct Done;
+struct Sum(u64);
+
+#[session]
+type Producer = Select<C, ProducerChoice>;
+
+#[session]
+#[allow(dead_code)]
+enum ProducerChoice {
+    Value(Value, Producer),
+    Done(Done, Receive<C, Sum, End>),
+}
+
+#[session]
+type Consumer = Branch<P, ConsumerChoice>;
+
+#[session]
+enum ConsumerChoice {
+    Value(Value, Consumer),
+    Done(Done, Send<P, Sum, End>),
+}
+
+async fn producer(role: &mut P, n: u64) -> Result<u64> {
+    try_session(role, |mut s: Producer<'_, _>| async {
+        for x in 1..=n {
+            s = s.select(Value(x)).await?;
+        }
+
+        let s = s.select(Done).await?;
+        let (Sum(sum), s) = s.receive().await?;
+        Ok((sum, s))
+    })
+    .await
+}
+
+async fn consumer(role: &mut C) -> Result<()> {
+    try_session(role, |mut s: Consumer<'_, _>| async {
+        let mut sum = 0;
+        loop {
+            s = match s.branch().await? {
+                ConsumerChoice::Value(Value(x), s) => {
+                    sum += x;
+                    s
+                }
+                ConsumerChoice::Done(Done, s) => {
+                    let s = s.send(Sum(sum)).await?;
+                    return Ok(((), s));
+                }
+            };
+        }
+    })
+    .await
+}
+
+fn main() {
+    let Roles(mut p, mut c) = Roles::default();
+    executor::block_on(async {
+        let (sum, _) = try_join!(producer(&mut p, 1000), consumer(&mut c)).unwrap();
+        println!("sum: {}", sum);
+        assert_eq!(sum, 500500);
+    });
+}
@@ -0,0 +1,99 @@
+use futures::{
+    channel::mpsc::{UnboundedReceiver, UnboundedSender},
+    executor, try_join,
+};
+use rumpsteak::{
+    channel::{Bidirectional, Pair},
+    metrics::{Instrumented, Metrics},
+    session, try_session, End, Message, Receive, Role, Roles, Send,
+};
+use std::{error::Error, result};
+
+type Result<T> = result::Result<T, Box<dyn Error>>;
+
+type Channel = Bidirectional<UnboundedSender<Label>, UnboundedReceiver<Label>>;
+
+// Roles cannot be created by default, since some of their routes are
+// instrumented, so only the builder is derived.
+#[derive(Roles)]
+#[roles(builder_only)]
+struct Roles(A, B, C);
+
+// Only the routes between A and C are instrumented, which cannot be paired
+// without a registry to count their messages in.
+#[derive(Role)]
+#[message(Label)]
+struct A(#[route(B)] Channel, #[route(C)] Instrumented<Channel>);
+
+#[derive(Role)]
+#[message(Label)]
+struct B(#[route(A)] Channel, #[route(C)] Channel);
+
+#[derive(Role)]
+#[message(Label)]
+struct C(#[route(A)] Instrumented<Channel>, #[route(B)] Channel);
+
+#[derive(Message)]
+enum Label {
+    Add(Add),
+    Sum(Sum),
+}
+
+struct Add(i32);
+struct Sum(i32);
+
+#[session]
+type AdderA = Send<B, Add, Send<C, Add, Receive<C, Sum, End>>>;
+
+#[session]
+type AdderB = Receive<A, Add, Send<C, Add, End>>;
+
+#[session]
+type AdderC = Receive<A, Add, Receive<B, Add, Send<A, Sum, End>>>;
+
+async fn adder_a(role: &mut A) -> Result<i32> {
+    try_session(role, |s: AdderA<'_, _>| async {
+        let s = s.send(Add(2)).await?;
+        let s = s.send(Add(3)).await?;
+        let (Sum(z), s) = s.receive().await?;
+        Ok((z, s))
+    })
+    .await
+}
+
+async fn adder_b(role: &mut B) -> Result<()> {
+    try_session(role, |s: AdderB<'_, _>| async {
+        let (Add(x), s) = s.receive().await?;
+        Ok(((), s.send(Add(x * 2)).await?))
+    })
+    .await
+}
+
+async fn adder_c(role: &mut C) -> Result<()> {
+    try_session(role, |s: AdderC<'_, _>| async {
+        let (Add(x), s) = s.receive().await?;
+        let (Add(y), s) = s.receive().await?;
+        Ok(((), s.send(Sum(x + y)).await?))
+    })
+    .await
+}
+
+fn main() {
+    let metrics = Metrics::new();
+    let Roles(mut a, mut b, mut c) = Roles::builder()
+        .link::<C, A>(|| {
+            let (c, a) = Channel::pair();
+            (metrics.route("C", "A", c), metrics.route("A", "C", a))
+        })
+        .build()
+        .unwrap();
+
+    let (sum, ..) =
+        executor::block_on(async { try_join!(adder_a(&mut a), adder_b(&mut b), adder_c(&mut c)) })
+            .unwrap();
+
+    println!("sum = {}", sum);
+    assert_eq!(sum, 7);
+    assert_eq!(metrics.received("C", "A", "Add"), 1);
+    assert_eq!(metrics.sent("C", "A", "Sum"), 1);
+}
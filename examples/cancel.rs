@@ -0,0 +1,76 @@
+use futures::{
+    channel::mpsc::{UnboundedReceiver, UnboundedSender},
+    executor, join,
+};
+use rumpsteak::{
+    channel::Bidirectional, session, try_session, End, Message, Receive, ReceiveError, Role, Roles,
+    Send,
+};
+use std::{error::Error, result};
+
+type Result<T> = result::Result<T, Box<dyn Error>>;
+
+type Channel = Bidirectional<UnboundedSender<Label>, UnboundedReceiver<Label>>;
+
+#[derive(Roles)]
+struct Roles(C, S);
+
+#[derive(Role)]
+#[message(Label)]
+struct C(#[route(S)] Channel);
+
+#[derive(Role)]
+#[message(Label)]
+struct S(#[route(C)] Channel);
+
+#[derive(Message)]
+enum Label {
+    Add(Add),
+    Sum(Sum),
+}
+
+struct Add(i32);
+struct Sum(i32);
+
+#[session]
+type Client = Send<S, Add, Send<S, Add, Receive<S, Sum, End>>>;
+
+#[session]
+type Server = Receive<C, Add, Receive<C, Add, Send<C, Sum, End>>>;
+
+async fn client(role: &mut C, y: i32) -> Result<i32> {
+    try_session(role, |s: Client<'_, _>| async {
+        let s = s.send(Add(1)).await?;
+        if y < 0 {
+            // Failing here drops the session before it ends, which cancels it.
+            return Err("client gave up on a negative number".into());
+        }
+
+        let s = s.send(Add(y)).await?;
+        let (Sum(z), s) = s.receive().await?;
+        Ok((z, s))
+    })
+    .await
+}
+
+async fn server(role: &mut S) -> result::Result<(), ReceiveError<Label>> {
+    try_session(role, |s: Server<'_, _>| async {
+        let (Add(x), s) = s.receive().await?;
+        let (Add(y), s) = s.receive().await?;
+        let s = s.send(Sum(x + y)).await.unwrap();
+        Ok(((), s))
+    })
+    .await
+}
+
+fn main() {
+    let Roles(mut c, mut s) = Roles::default();
+    executor::block_on(async {
+        let (client, server) = join!(client(&mut c, -2), server(&mut s));
+        println!("client: {}", client.unwrap_err());
+
+        let error = server.unwrap_err();
+        println!("server: {}", error);
+        assert!(matches!(error, ReceiveError::Cancelled));
+    });
+}
@@ -0,0 +1,130 @@
+use futures::{
+    channel::mpsc::{UnboundedReceiver, UnboundedSender},
+    executor, try_join,
+};
+use rumpsteak::{
+    channel::Bidirectional, delegate::Delegate, session, try_session, End, Message, Receive, Role,
+    Roles, Send,
+};
+use std::{error::Error, result};
+
+type Result<T> = result::Result<T, Box<dyn Error>>;
+
+type Channel<L> = Bidirectional<UnboundedSender<L>, UnboundedReceiver<L>>;
+
+#[derive(Roles)]
+struct Endpoints(C, P);
+
+/// A client talking to the server endpoint `P`, unaware of which worker holds
+/// it.
+#[derive(Role)]
+#[message(Label)]
+struct C(#[route(P)] Channel<Label>);
+
+#[derive(Role)]
+#[message(Label)]
+struct P(#[route(C)] Channel<Label>);
+
+#[derive(Roles)]
+struct Workers(M, W);
+
+/// A master which accepts the client and receives its first number, but hands
+/// the rest of the endpoint's session over to `W`.
+#[derive(Role)]
+#[message(Handoff)]
+struct M(#[route(W)] Channel<Handoff>);
+
+#[derive(Role)]
+#[message(Handoff)]
+struct W(#[route(M)] Channel<Handoff>);
+
+#[derive(Message)]
+enum Label {
+    Add(Add),
+    Sum(Sum),
+}
+
+struct Add(i32);
+struct Sum(i32);
+
+#[derive(Message)]
+enum Handoff {
+    Server(Delegate<P, ServerRest<'static, P>>),
+    Partial(Partial),
+}
+
+/// The number which the master received before handing off the session.
+struct Partial(i32);
+
+#[session]
+type Client = Send<P, Add, Send<P, Add, Receive<P, Sum, End>>>;
+
+#[session]
+type Server = Receive<C, Add, ServerRest>;
+
+#[session]
+type ServerRest = Receive<C, Add, Send<C, Sum, End>>;
+
+#[session]
+type Master = Send<W, Delegate<P, ServerRest<'static, P>>, Send<W, Partial, End>>;
+
+#[session]
+type Worker = Receive<M, Delegate<P, ServerRest<'static, P>>, Receive<M, Partial, End>>;
+
+async fn client(role: &mut C) -> Result<()> {
+    try_session(role, |s: Client<'_, _>| async {
+        let s = s.send(Add(1)).await?;
+        let s = s.send(Add(2)).await?;
+        let (Sum(z), s) = s.receive().await?;
+        println!("1 + 2 = {}", z);
+        assert_eq!(z, 3);
+        Ok(((), s))
+    })
+    .await
+}
+
+async fn master(role: &mut M, endpoint: P) -> Result<()> {
+    // Only the rest of the session is handed off, so the worker can only
+    // continue it as a `ServerRest`.
+    let mut endpoint = Delegate::new(endpoint);
+    let (x, handoff) = endpoint
+        .try_hand_off(|s: Server<'_, _>| async {
+            let (Add(x), s) = s.receive().await?;
+            Result::Ok((x, s))
+        })
+        .await?;
+
+    let endpoint = endpoint.resume(handoff)?;
+    try_session(role, |s: Master<'_, _>| async {
+        let s = s.send(endpoint).await?;
+        let s = s.send(Partial(x)).await?;
+        Ok(((), s))
+    })
+    .await
+}
+
+async fn worker(role: &mut W) -> Result<()> {
+    let (mut endpoint, x) = try_session(role, |s: Worker<'_, _>| async {
+        let (endpoint, s) = s.receive().await?;
+        let (Partial(x), s) = s.receive().await?;
+        Result::Ok(((endpoint, x), s))
+    })
+    .await?;
+
+    endpoint
+        .try_session(|s: ServerRest<'_, _>| async {
+            let (Add(y), s) = s.receive().await?;
+            let s = s.send(Sum(x + y)).await?;
+            Ok(((), s))
+        })
+        .await
+}
+
+fn main() {
+    let Endpoints(mut c, p) = Endpoints::default();
+    let Workers(mut m, mut w) = Workers::default();
+
+    executor::block_on(async {
+        try_join!(client(&mut c), master(&mut m, p), worker(&mut w)).unwrap();
+    });
+}
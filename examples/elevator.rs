@@ -32,34 +32,24 @@ struct E(#[route(U)] Channel, #[route(D)] Channel);
 
 #[derive(Message)]
 enum Label {
-    Close(Close),
-    CloseDoor(CloseDoor),
-    DoorClosed(DoorClosed),
-    DoorOpened(DoorOpened),
-    DoorStopped(DoorStopped),
-    Open(Open),
-    OpenDoor(OpenDoor),
-    Reset(Reset),
-    Stop(Stop),
+    Close,
+    CloseDoor,
+    DoorClosed,
+    DoorOpened,
+    DoorStopped,
+    Open,
+    OpenDoor,
+    Reset,
+    Stop,
 }
 
-struct Close;
-struct CloseDoor;
-struct DoorClosed;
-struct DoorOpened;
-struct DoorStopped;
-struct Open;
-struct OpenDoor;
-struct Reset;
-struct Stop;
-
 #[session]
 type User = Select<E, UserChoice>;
 
 #[session]
 enum UserChoice {
-    CloseDoor(CloseDoor, User),
-    OpenDoor(OpenDoor, User),
+    CloseDoor(LabelCloseDoor, User),
+    OpenDoor(LabelOpenDoor, User),
 }
 
 #[session]
@@ -67,41 +57,42 @@ type Door = Branch<E, DoorInit>;
 
 #[session]
 enum DoorInit {
-    Close(Close, Send<E, DoorClosed, Branch<E, DoorReset>>),
-    Open(Open, Send<E, DoorOpened, Branch<E, DoorReset>>),
-    Reset(Reset, Door),
-    Stop(Stop, Door),
+    Close(LabelClose, Send<E, LabelDoorClosed, Branch<E, DoorReset>>),
+    Open(LabelOpen, Send<E, LabelDoorOpened, Branch<E, DoorReset>>),
+    Reset(LabelReset, Door),
+    Stop(LabelStop, Door),
 }
 
 #[session]
 enum DoorReset {
-    Close(Close, Branch<E, DoorReset>),
-    Open(Open, Branch<E, DoorReset>),
-    Reset(Reset, Door),
-    Stop(Stop, Branch<E, DoorReset>),
+    Close(LabelClose, Branch<E, DoorReset>),
+    Open(LabelOpen, Branch<E, DoorReset>),
+    Reset(LabelReset, Door),
+    Stop(LabelStop, Branch<E, DoorReset>),
 }
 
 #[session]
-type Elevator = Send<D, Reset, Branch<U, ElevatorClosed>>;
+type Elevator = Send<D, LabelReset, Branch<U, ElevatorClosed>>;
 
 #[session]
 enum ElevatorClosed {
-    CloseDoor(CloseDoor, Branch<U, ElevatorClosed>),
-    OpenDoor(OpenDoor, ElevatorOpening),
+    CloseDoor(LabelCloseDoor, Branch<U, ElevatorClosed>),
+    OpenDoor(LabelOpenDoor, ElevatorOpening),
 }
 
 #[session]
-type ElevatorOpening = Send<D, Open, Receive<D, DoorOpened, ElevatorOpened>>;
+type ElevatorOpening = Send<D, LabelOpen, Receive<D, LabelDoorOpened, ElevatorOpened>>;
 
 #[session]
-type ElevatorOpened = Send<D, Reset, Send<D, Close, Send<D, Stop, Branch<D, ElevatorStopping>>>>;
+type ElevatorOpened =
+    Send<D, LabelReset, Send<D, LabelClose, Send<D, LabelStop, Branch<D, ElevatorStopping>>>>;
 
 #[session]
 #[allow(clippy::enum_variant_names)]
 enum ElevatorStopping {
-    DoorStopped(DoorStopped, ElevatorOpening),
-    DoorOpened(DoorOpened, ElevatorOpened),
-    DoorClosed(DoorClosed, Elevator),
+    DoorStopped(LabelDoorStopped, ElevatorOpening),
+    DoorOpened(LabelDoorOpened, ElevatorOpened),
+    DoorClosed(LabelDoorClosed, Elevator),
 }
 
 enum Never {}
@@ -116,10 +107,10 @@ async fn user(role: &mut U) -> Result<Never> {
         loop {
             s = if rng.gen() {
                 println!("user: close");
-                s.select(CloseDoor).await?
+                s.select(LabelCloseDoor).await?
             } else {
                 println!("user: open");
-                s.select(OpenDoor).await?
+                s.select(LabelOpenDoor).await?
             };
 
             sleep(&mut rng).await;
@@ -133,25 +124,25 @@ async fn door(role: &mut D) -> Result<Never> {
         async fn reset<'d>(mut s: Branch<'d, D, E, DoorReset<'d, D>>) -> Result<Door<'d, D>> {
             loop {
                 s = match s.branch().await? {
-                    DoorReset::Close(Close, s)
-                    | DoorReset::Open(Open, s)
-                    | DoorReset::Stop(Stop, s) => s,
-                    DoorReset::Reset(Reset, s) => break Ok(s),
+                    DoorReset::Close(LabelClose, s)
+                    | DoorReset::Open(LabelOpen, s)
+                    | DoorReset::Stop(LabelStop, s) => s,
+                    DoorReset::Reset(LabelReset, s) => break Ok(s),
                 }
             }
         }
 
         loop {
             s = match s.branch().await? {
-                DoorInit::Close(Close, s) => {
+                DoorInit::Close(LabelClose, s) => {
                     println!("door: close");
-                    reset(s.send(DoorClosed).await?).await?
+                    reset(s.send(LabelDoorClosed).await?).await?
                 }
-                DoorInit::Open(Open, s) => {
+                DoorInit::Open(LabelOpen, s) => {
                     println!("door: open");
-                    reset(s.send(DoorOpened).await?).await?
+                    reset(s.send(LabelDoorOpened).await?).await?
                 }
-                DoorInit::Reset(Reset, s) | DoorInit::Stop(Stop, s) => s,
+                DoorInit::Reset(LabelReset, s) | DoorInit::Stop(LabelStop, s) => s,
             };
         }
     })
@@ -164,14 +155,14 @@ async fn elevator(role: &mut E) -> Result<Never> {
         mut rng: impl Rng + 'e,
     ) -> LocalBoxFuture<'e, Result<(Never, End<'e, E>)>> {
         async move {
-            let s = s.send(Reset).await?;
+            let s = s.send(LabelReset).await?;
             sleep(&mut rng).await;
 
-            let s = s.send(Close).await?.send(Stop).await?;
+            let s = s.send(LabelClose).await?.send(LabelStop).await?;
             match s.branch().await? {
-                ElevatorStopping::DoorStopped(DoorStopped, s) => opening(s, rng).await,
-                ElevatorStopping::DoorOpened(DoorOpened, s) => opened(s, rng).await,
-                ElevatorStopping::DoorClosed(DoorClosed, s) => elevator(s, rng).await,
+                ElevatorStopping::DoorStopped(LabelDoorStopped, s) => opening(s, rng).await,
+                ElevatorStopping::DoorOpened(LabelDoorOpened, s) => opened(s, rng).await,
+                ElevatorStopping::DoorClosed(LabelDoorClosed, s) => elevator(s, rng).await,
             }
         }
         .boxed_local()
@@ -182,7 +173,7 @@ async fn elevator(role: &mut E) -> Result<Never> {
         rng: impl Rng + 'e,
     ) -> LocalBoxFuture<'e, Result<(Never, End<'e, E>)>> {
         async move {
-            let (DoorOpened, s) = s.send(Open).await?.receive().await?;
+            let (LabelDoorOpened, s) = s.send(LabelOpen).await?.receive().await?;
             opened(s, rng).await
         }
         .boxed_local()
@@ -194,8 +185,8 @@ async fn elevator(role: &mut E) -> Result<Never> {
     ) -> LocalBoxFuture<'e, Result<(Never, End<'e, E>)>> {
         async move {
             match s.branch().await? {
-                ElevatorClosed::CloseDoor(CloseDoor, s) => closed(s, rng).await,
-                ElevatorClosed::OpenDoor(OpenDoor, s) => opening(s, rng).await,
+                ElevatorClosed::CloseDoor(LabelCloseDoor, s) => closed(s, rng).await,
+                ElevatorClosed::OpenDoor(LabelOpenDoor, s) => opening(s, rng).await,
             }
         }
         .boxed_local()
@@ -206,7 +197,7 @@ async fn elevator(role: &mut E) -> Result<Never> {
         rng: impl Rng + 'e,
     ) -> LocalBoxFuture<'e, Result<(Never, End<'e, E>)>> {
         async move {
-            let s = s.send(Reset).await?;
+            let s = s.send(LabelReset).await?;
             closed(s, rng).await
         }
         .boxed_local()
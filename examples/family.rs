@@ -0,0 +1,100 @@
+use futures::{
+    channel::mpsc::{UnboundedReceiver, UnboundedSender},
+    executor,
+    future::try_join_all,
+    try_join,
+};
+use rumpsteak::{
+    channel::Bidirectional, session, try_session, End, Gather, Indexed, Message, Next, Prev,
+    Receive, Role, Roles, Scatter, Send,
+};
+use std::{env, error::Error, result};
+
+type Result<T> = result::Result<T, Box<dyn Error>>;
+
+type Channel = Bidirectional<UnboundedSender<Value>, UnboundedReceiver<Value>>;
+
+#[derive(Roles)]
+struct Roles {
+    k: K,
+    w: Vec<W>,
+}
+
+/// A coordinator which scatters a value to each worker and gathers their
+/// results once the workers have summed their values around a ring.
+#[derive(Role)]
+#[message(Value)]
+struct K {
+    #[family(W)]
+    w: Vec<Channel>,
+}
+
+#[derive(Role)]
+#[message(Value)]
+struct W {
+    #[route(K)]
+    k: Channel,
+    #[family(W)]
+    w: Vec<Channel>,
+    #[index]
+    index: usize,
+}
+
+#[derive(Message)]
+struct Value(u64);
+
+#[session]
+type Coordinator = Scatter<W, Value, Gather<W, Value, End>>;
+
+#[session]
+type First = Receive<K, Value, Send<Next<W>, Value, Receive<Prev<W>, Value, Send<K, Value, End>>>>;
+
+#[session]
+type Rest = Receive<K, Value, Receive<Prev<W>, Value, Send<Next<W>, Value, Send<K, Value, End>>>>;
+
+async fn coordinator(role: &mut K) -> Result<Vec<u64>> {
+    try_session(role, |s: Coordinator<'_, _>| async {
+        let s = s.send(|index| Value(index as u64 + 1)).await?;
+        let (values, s) = s.receive().await?;
+        Ok((values.into_iter().map(|Value(x)| x).collect(), s))
+    })
+    .await
+}
+
+async fn worker(role: &mut W) -> Result<()> {
+    if role.index() == 0 {
+        return try_session(role, |s: First<'_, _>| async {
+            let (Value(x), s) = s.receive().await?;
+            let s = s.send(Value(x)).await?;
+            let (Value(total), s) = s.receive().await?;
+            let s = s.send(Value(total)).await?;
+            Ok(((), s))
+        })
+        .await;
+    }
+
+    try_session(role, |s: Rest<'_, _>| async {
+        let (Value(x), s) = s.receive().await?;
+        let (Value(y), s) = s.receive().await?;
+        let s = s.send(Value(x + y)).await?;
+        let s = s.send(Value(x + y)).await?;
+        Ok(((), s))
+    })
+    .await
+}
+
+fn main() {
+    let n = env::args()
+        .nth(1)
+        .map_or(Ok(5), |n| n.parse::<usize>())
+        .unwrap();
+
+    let Roles { mut k, mut w } = Roles::new(n).unwrap();
+    executor::block_on(async {
+        let workers = try_join_all(w.iter_mut().map(worker));
+        let (values, _) = try_join!(coordinator(&mut k), workers).unwrap();
+
+        println!("sums around the ring of {} workers: {:?}", n, values);
+        assert_eq!(values[0] as usize, n * (n + 1) / 2);
+    });
+}
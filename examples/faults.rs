@@ -0,0 +1,103 @@
+use rumpsteak::{
+    channel::{Bidirectional, Pair},
+    fault::{Fault, FaultKind, Faulty, Fired, Log, Script},
+    session,
+    sim::{self, Simulation},
+    try_session, End, Message, Receive, Role, Send,
+};
+use std::{error::Error, result};
+
+type Result<T> = result::Result<T, Box<dyn Error>>;
+
+type Channel = Bidirectional<sim::Sender<Label>, sim::Receiver<Label>>;
+
+#[derive(Role)]
+#[message(Label)]
+struct C(#[route(S)] Faulty<Channel, Label, Script<Label>>);
+
+#[derive(Role)]
+#[message(Label)]
+struct S(#[route(C)] Channel);
+
+#[derive(Message)]
+enum Label {
+    Request(Request),
+    Response(Response),
+}
+
+#[derive(Clone, Copy)]
+struct Request(u64);
+
+struct Response(u64);
+
+#[session]
+type Client = Send<S, Request, Receive<S, Response, End>>;
+
+#[session]
+type Server = Receive<C, Request, Send<C, Response, End>>;
+
+async fn client(role: &mut C) -> Result<u64> {
+    try_session(role, |s: Client<'_, _>| async {
+        let s = s.send(Request(21)).await?;
+        let (Response(x), s) = s.receive().await?;
+        Ok((x, s))
+    })
+    .await
+}
+
+async fn server(role: &mut S) -> Result<()> {
+    try_session(role, |s: Server<'_, _>| async {
+        let (Request(x), s) = s.receive_timeout(sim::sleep(100)).await?;
+        let s = s.send(Response(x * 2)).await?;
+        Ok(((), s))
+    })
+    .await
+}
+
+/// Runs the protocol with `fault` injected into the client's request, giving
+/// back the faults which fired.
+fn simulate(name: &str, fault: Option<Fault<Label>>) -> Log {
+    let mut simulation = Simulation::new(0);
+    let (left, right) = simulation.enter(Channel::pair);
+
+    let script = match fault {
+        Some(fault) => Script::new().at(0, fault),
+        None => Script::new(),
+    };
+    let route = Faulty::new(left, script);
+    let log = route.log();
+
+    let (mut c, mut s) = (C(route), S(right));
+    simulation.spawn(async move {
+        let (client, server) = futures::join!(client(&mut c), server(&mut s));
+        println!("{}:", name);
+        println!("  client: {:?}", client.map_err(|error| error.to_string()));
+        println!("  server: {:?}", server.map_err(|error| error.to_string()));
+    });
+
+    simulation.run().unwrap();
+    log
+}
+
+fn main() {
+    let log = simulate("none", None);
+    assert!(log.fired().is_empty());
+
+    let log = simulate("drop", Some(Fault::Drop));
+    assert_eq!(log.count(FaultKind::Drop), 1);
+
+    let copy = Label::Request(Request(21));
+    let log = simulate("duplicate", Some(Fault::Duplicate(copy)));
+    assert_eq!(log.count(FaultKind::Duplicate), 1);
+
+    let label = Label::Response(Response(0));
+    let log = simulate("corrupt", Some(Fault::Corrupt(label)));
+    assert_eq!(log.count(FaultKind::Corrupt), 1);
+
+    let log = simulate("disconnect", Some(Fault::Disconnect));
+    let fired = Fired {
+        index: 0,
+        kind: FaultKind::Disconnect,
+    };
+    assert_eq!(log.fired(), [fired]);
+}
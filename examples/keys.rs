@@ -0,0 +1,109 @@
+use futures::{
+    channel::mpsc::{UnboundedReceiver, UnboundedSender},
+    executor, try_join,
+};
+use rumpsteak::{
+    channel::Bidirectional, session, try_session, Branch, End, Message, Receive, Role, Roles,
+    Select, Send,
+};
+use std::{error::Error, result};
+
+type Result<T> = result::Result<T, Box<dyn Error>>;
+
+type Channel = Bidirectional<UnboundedSender<Label>, UnboundedReceiver<Label>>;
+
+#[derive(Roles)]
+struct Roles(C, S);
+
+#[derive(Role)]
+#[message(Label)]
+struct C(#[route(S)] Channel);
+
+#[derive(Role)]
+#[message(Label)]
+struct S(#[route(C)] Channel);
+
+/// The request and both replies carry a `u32`, so each is identified by a key
+/// of its own.
+#[derive(Message)]
+enum Label {
+    #[key(Withdraw)]
+    Withdraw(u32),
+    #[key(Accepted)]
+    Accepted(u32),
+    #[key(Rejected)]
+    Rejected(u32),
+}
+
+/// The key of a request to withdraw an amount.
+struct Withdraw;
+
+/// The key of a withdrawal which went through, carrying the new balance.
+struct Accepted;
+
+/// The key of a withdrawal which was refused, carrying the shortfall.
+struct Rejected;
+
+#[session]
+type Client = Send<S, Withdraw, Branch<S, Reply>>;
+
+#[session]
+enum Reply {
+    #[key(Accepted)]
+    Accepted(u32, End),
+    #[key(Rejected)]
+    Rejected(u32, End),
+}
+
+#[session]
+type Server = Receive<C, Withdraw, Select<C, Decision>>;
+
+#[session]
+#[allow(dead_code)]
+enum Decision {
+    #[key(Accepted)]
+    Accepted(u32, End),
+    #[key(Rejected)]
+    Rejected(u32, End),
+}
+
+async fn client(role: &mut C, amount: u32) -> Result<()> {
+    try_session(role, |s: Client<'_, _>| async {
+        let s = s.send(amount).await?;
+        let s = match s.branch().await? {
+            Reply::Accepted(balance, s) => {
+                println!("withdrew {}, leaving {}", amount, balance);
+                s
+            }
+            Reply::Rejected(shortfall, s) => {
+                println!("could not withdraw {}, short by {}", amount, shortfall);
+                s
+            }
+        };
+
+        Ok(((), s))
+    })
+    .await
+}
+
+async fn server(role: &mut S, balance: u32) -> Result<()> {
+    try_session(role, |s: Server<'_, _>| async {
+        let (amount, s) = s.receive().await?;
+        let s = match balance.checked_sub(amount) {
+            Some(balance) => s.select_keyed::<Accepted>(balance).await?,
+            None => s.select_keyed::<Rejected>(amount - balance).await?,
+        };
+
+        Ok(((), s))
+    })
+    .await
+}
+
+fn main() {
+    for amount in [30, 70] {
+        let Roles(mut c, mut s) = Roles::default();
+        executor::block_on(async {
+            try_join!(client(&mut c, amount), server(&mut s, 50)).unwrap();
+        });
+    }
+}
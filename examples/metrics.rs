@@ -0,0 +1,97 @@
+use futures::{
+    channel::mpsc::{UnboundedReceiver, UnboundedSender},
+    try_join,
+};
+use rumpsteak::{
+    channel::{Bidirectional, Pair},
+    metrics::{Instrumented, Metrics},
+    session, try_session, End, Message, Receive, Role, Send,
+};
+use std::{error::Error, result, time::Duration};
+use tokio::time;
+
+type Result<T> = result::Result<T, Box<dyn Error>>;
+
+type Channel = Instrumented<Bidirectional<UnboundedSender<Label>, UnboundedReceiver<Label>>>;
+
+#[derive(Role)]
+#[message(Label)]
+struct A(#[route(C)] Channel);
+
+#[derive(Role)]
+#[message(Label)]
+struct B(#[route(C)] Channel);
+
+#[derive(Role)]
+#[message(Label)]
+struct C(#[route(A)] Channel, #[route(B)] Channel);
+
+#[derive(Message)]
+enum Label {
+    Add(Add),
+    Sum(Sum),
+}
+
+struct Add(i32);
+struct Sum(i32);
+
+#[session]
+type AdderA = Send<C, Add, Receive<C, Sum, End>>;
+
+#[session]
+type AdderB = Send<C, Add, Receive<C, Sum, End>>;
+
+#[session]
+type AdderC = Receive<A, Add, Receive<B, Add, Send<A, Sum, Send<B, Sum, End>>>>;
+
+async fn adder_a(role: &mut A) -> Result<()> {
+    try_session(role, |s: AdderA<'_, _>| async {
+        let s = s.send(Add(2)).await?;
+        let (Sum(z), s) = s.receive().await?;
+        assert_eq!(z, 5);
+        Ok(((), s))
+    })
+    .await
+}
+
+async fn adder_b(role: &mut B) -> Result<()> {
+    try_session(role, |s: AdderB<'_, _>| async {
+        let s = s.send(Add(3)).await?;
+        let (Sum(z), s) = s.receive().await?;
+        assert_eq!(z, 5);
+        Ok(((), s))
+    })
+    .await
+}
+
+async fn adder_c(role: &mut C) -> Result<()> {
+    try_session(role, |s: AdderC<'_, _>| async {
+        let (Add(x), s) = s.receive().await?;
+        let (Add(y), s) = s.receive().await?;
+
+        // Adding is made slow on purpose, so that it shows up as the step which
+        // the other roles are blocked on.
+        time::sleep(Duration::from_millis(5)).await;
+        let s = s.send(Sum(x + y)).await?;
+        Ok(((), s.send(Sum(x + y)).await?))
+    })
+    .await
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    let metrics = Metrics::new();
+    for _ in 0..3 {
+        let (ac, ca) = Pair::pair();
+        let (bc, cb) = Pair::pair();
+        let mut a = A(metrics.route("A", "C", ac));
+        let mut b = B(metrics.route("B", "C", bc));
+        let mut c = C(metrics.route("C", "A", ca), metrics.route("C", "B", cb));
+        try_join!(adder_a(&mut a), adder_b(&mut b), adder_c(&mut c)).unwrap();
+    }
+
+    print!("{}", metrics.export());
+    assert_eq!(metrics.sent("C", "A", "Sum"), 3);
+    assert_eq!(metrics.received("A", "C", "Sum"), 3);
+    assert!(metrics.blocked("A", "C", "Sum") >= Duration::from_millis(15));
+}
@@ -0,0 +1,89 @@
+use futures::{
+    channel::mpsc::{UnboundedReceiver, UnboundedSender},
+    executor, join, SinkExt, StreamExt,
+};
+use rumpsteak::{
+    channel::{Bidirectional, Pair},
+    monitor::{Monitor, Monitored, Rejected},
+    serialize::{serialize, Type},
+    session, try_session, End, Message, Receive, ReceiveError, Role, Send,
+};
+use std::{error::Error, result};
+
+type Result<T> = result::Result<T, Box<dyn Error>>;
+
+type Channel = Bidirectional<UnboundedSender<Label>, UnboundedReceiver<Label>>;
+
+#[derive(Role)]
+#[message(Label)]
+struct C(#[route(S)] Monitored<Channel, Label>);
+
+/// A server implemented without Rumpsteak.
+struct S;
+
+#[derive(Message)]
+enum Label {
+    Add(Add),
+    Sum(Sum),
+    Bye(Bye),
+}
+
+struct Add(i32);
+struct Sum(i32);
+struct Bye;
+
+fn label(label: &Label) -> Type {
+    match label {
+        Label::Add(_) => Type::new::<Add>(),
+        Label::Sum(_) => Type::new::<Sum>(),
+        Label::Bye(_) => Type::new::<Bye>(),
+    }
+}
+
+#[session]
+type Client = Send<S, Add, Send<S, Add, Receive<S, Sum, End>>>;
+
+async fn client(role: &mut C) -> Result<i32> {
+    try_session(role, |s: Client<'_, _>| async {
+        let s = s.send(Add(1)).await?;
+        let s = s.send(Add(2)).await?;
+        let (Sum(z), s) = s.receive().await?;
+        Ok((z, s))
+    })
+    .await
+}
+
+async fn server(mut channel: Channel) -> Result<()> {
+    let (x, y) = match (channel.next().await, channel.next().await) {
+        (Some(Label::Add(Add(x))), Some(Label::Add(Add(y)))) => (x, y),
+        _ => return Err("expected two additions".into()),
+    };
+
+    println!("server received {} and {}, but replies with bye", x, y);
+    channel.send(Label::Bye(Bye)).await?;
+    Ok(())
+}
+
+fn main() {
+    let monitor = Monitor::new(serialize::<Client<'static, C>>(), label);
+    let (left, right) = Pair::pair();
+    let mut c = C(monitor.route(Type::new::<S>(), left));
+
+    let error = executor::block_on(async {
+        let (output, _) = join!(client(&mut c), server(right));
+        output.unwrap_err()
+    });
+
+    // The client is told about the violation by its receive failing, and gets
+    // back the message which was rejected.
+    let rejected = match error.downcast::<ReceiveError<Label>>().map(|error| *error) {
+        Ok(ReceiveError::Route(error)) => error.downcast::<Rejected<Label>>().unwrap(),
+        _ => panic!("expected the receive to be rejected"),
+    };
+
+    println!("violation: {}", rejected.violation());
+    assert!(matches!(rejected.message(), Label::Bye(_)));
+
+    let violation = monitor.violation().unwrap();
+    assert_eq!(violation.state(), 2);
+}
@@ -0,0 +1,126 @@
+use futures::{future::try_join_all, try_join, TryFutureExt};
+use rumpsteak::{
+    net::{
+        mux::{self, Id, Mux, MuxRoute},
+        Bincode,
+    },
+    session, try_session, End, Message, Receive, Role, Send,
+};
+use serde::{Deserialize, Serialize};
+use std::{error::Error, future::Future, net::SocketAddr, result};
+use tokio::net::{TcpListener, TcpStream};
+
+type Result<T> = result::Result<T, Box<dyn Error>>;
+
+type Route = MuxRoute<Label>;
+
+const A_ID: Id = 0;
+const B_ID: Id = 1;
+const C_ID: Id = 2;
+
+#[derive(Role)]
+#[message(Label)]
+struct A(#[route(B)] Route, #[route(C)] Route);
+
+#[derive(Role)]
+#[message(Label)]
+struct B(#[route(A)] Route, #[route(C)] Route);
+
+#[derive(Role)]
+#[message(Label)]
+struct C(#[route(A)] Route, #[route(B)] Route);
+
+#[derive(Message, Serialize, Deserialize)]
+enum Label {
+    Add(Add),
+    Sum(Sum),
+}
+
+#[derive(Serialize, Deserialize)]
+struct Add(i32);
+
+#[derive(Serialize, Deserialize)]
+struct Sum(i32);
+
+#[session]
+type AdderA = Send<B, Add, Receive<B, Add, Send<C, Add, Receive<C, Sum, End>>>>;
+
+#[session]
+type AdderB = Receive<A, Add, Send<A, Add, Send<C, Add, Receive<C, Sum, End>>>>;
+
+#[session]
+type AdderC = Receive<A, Add, Receive<B, Add, Send<A, Sum, Send<B, Sum, End>>>>;
+
+async fn adder_a(mut role: A) -> Result<()> {
+    try_session(&mut role, |s: AdderA<'_, _>| async {
+        let x = 2;
+        let s = s.send(Add(x)).await?;
+        let (Add(y), s) = s.receive().await?;
+        let s = s.send(Add(y)).await?;
+        let (Sum(z), s) = s.receive().await?;
+        println!("{} + {} = {}", x, y, z);
+        assert_eq!(z, 5);
+        Ok(((), s))
+    })
+    .await
+}
+
+async fn adder_b(mut role: B) -> Result<()> {
+    try_session(&mut role, |s: AdderB<'_, _>| async {
+        let (Add(y), s) = s.receive().await?;
+        let x = 3;
+        let s = s.send(Add(x)).await?;
+        let s = s.send(Add(y)).await?;
+        let (Sum(z), s) = s.receive().await?;
+        println!("{} + {} = {}", x, y, z);
+        assert_eq!(z, 5);
+        Ok(((), s))
+    })
+    .await
+}
+
+async fn adder_c(mut role: C) -> Result<()> {
+    try_session(&mut role, |s: AdderC<'_, _>| async {
+        let (Add(x), s) = s.receive().await?;
+        let (Add(y), s) = s.receive().await?;
+        let z = x + y;
+        let s = s.send(Sum(z)).await?;
+        Ok(((), s.send(Sum(z)).await?))
+    })
+    .await
+}
+
+/// Connects a role to the hub and runs its session. The role is dropped once
+/// its session ends, which closes its routes and lets the connection finish.
+async fn connect<R, F: Future<Output = Result<()>>>(
+    addr: SocketAddr,
+    id: Id,
+    role: impl FnOnce(&mut Mux<Label>) -> R,
+    session: impl FnOnce(R) -> F,
+) -> Result<()> {
+    let stream = TcpStream::connect(addr).await?;
+    let mut mux = Mux::new(id);
+    let role = role(&mut mux);
+    try_join!(mux.run(stream, Bincode).err_into(), session(role))?;
+    Ok(())
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    // Each role would usually run in its own process, with a single connection
+    // to the hub however many peers it has.
+    let hub = async {
+        let accept = (0..3).map(|_| listener.accept().map_ok(|(stream, _)| stream));
+        let streams = try_join_all(accept).await?;
+        mux::hub(streams).await?;
+        Ok::<_, Box<dyn Error>>(())
+    };
+
+    let a = connect(addr, A_ID, |m| A(m.route(B_ID), m.route(C_ID)), adder_a);
+    let b = connect(addr, B_ID, |m| B(m.route(A_ID), m.route(C_ID)), adder_b);
+    let c = connect(addr, C_ID, |m| C(m.route(A_ID), m.route(B_ID)), adder_c);
+    try_join!(hub, a, b, c).unwrap();
+}
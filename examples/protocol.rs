@@ -0,0 +1,121 @@
+use futures::{channel::mpsc::Receiver, executor, try_join};
+use rumpsteak::{
+    channel::{Bidirectional, BoundedSender},
+    protocol, try_session,
+};
+use std::{error::Error, result};
+
+type Result<T> = result::Result<T, Box<dyn Error>>;
+
+// The logger L never makes the choice, but is told which branch was taken, so
+// it can still be projected onto.
+protocol! {
+    global protocol Auth(role C, role S, role L) {
+        SetPw(p: i32) from C to S;
+        rec Login {
+            Password(x: i32) from C to S;
+            choice at S {
+                Success() from S to C;
+                Granted(attempts: u32) from S to L;
+            } or {
+                Failure() from S to C;
+                Denied() from S to L;
+                continue Login;
+            }
+        }
+    }
+}
+
+// Each protocol is emitted into a module of its own, so several can be defined
+// side by side, and is documented like one.
+protocol! {
+    /// A rally whose routes hold a single message at a time.
+    #[channel(Bidirectional<BoundedSender<Label, 1>, Receiver<Label>>)]
+    global protocol Rally(role P, role Q) {
+        Serve(n: u32) from P to Q;
+        Return(n: u32) from Q to P;
+    }
+}
+
+async fn client(role: &mut auth::C, password: i32) -> Result<()> {
+    try_session(role, |s: auth::AuthC<'_, _>| async {
+        let mut s = s.send(auth::SetPw(password)).await?;
+        let mut guess = password - 2;
+        loop {
+            s = match s.send(auth::Password(guess)).await?.branch().await? {
+                auth::AuthC2::Success(_, s) => return Ok(((), s)),
+                auth::AuthC2::Failure(_, s) => s,
+            };
+            guess += 1;
+        }
+    })
+    .await
+}
+
+async fn server(role: &mut auth::S) -> Result<()> {
+    try_session(role, |s: auth::AuthS<'_, _>| async {
+        let (auth::SetPw(password), mut s) = s.receive().await?;
+        let mut attempts = 0;
+        loop {
+            let (auth::Password(guess), t) = s.receive().await?;
+            attempts += 1;
+            if guess == password {
+                let s = t.select(auth::Success).await?;
+                return Ok(((), s.send(auth::Granted(attempts)).await?));
+            }
+
+            s = t.select(auth::Failure).await?.send(auth::Denied).await?;
+        }
+    })
+    .await
+}
+
+async fn serve(role: &mut rally::P, n: u32) -> Result<u32> {
+    try_session(role, |s: rally::RallyP<'_, _>| async {
+        let s = s.send(rally::Serve(n)).await?;
+        let (rally::Return(n), s) = s.receive().await?;
+        Ok((n, s))
+    })
+    .await
+}
+
+async fn ret(role: &mut rally::Q) -> Result<()> {
+    try_session(role, |s: rally::RallyQ<'_, _>| async {
+        let (rally::Serve(n), s) = s.receive().await?;
+        Ok(((), s.send(rally::Return(n + 1)).await?))
+    })
+    .await
+}
+
+async fn logger(role: &mut auth::L) -> Result<()> {
+    try_session(role, |mut s: auth::AuthL<'_, _>| async {
+        loop {
+            s = match s.branch().await? {
+                auth::AuthL1::Granted(auth::Granted(attempts), s) => {
+                    println!("granted after {} attempts", attempts);
+                    return Ok(((), s));
+                }
+                auth::AuthL1::Denied(_, s) => {
+                    println!("denied");
+                    s
+                }
+            };
+        }
+    })
+    .await
+}
+
+fn main() {
+    let auth::Roles {
+        mut c,
+        mut s,
+        mut l,
+    } = auth::Roles::default();
+    executor::block_on(async {
+        try_join!(client(&mut c, 42), server(&mut s), logger(&mut l)).unwrap();
+    });
+
+    let rally::Roles { mut p, mut q } = rally::Roles::builder().build().unwrap();
+    let (n, _) = executor::block_on(async { try_join!(serve(&mut p, 1), ret(&mut q)) }).unwrap();
+    assert_eq!(n, 2);
+}
@@ -0,0 +1,106 @@
+use futures::channel::mpsc::{UnboundedReceiver, UnboundedSender};
+use rumpsteak::{
+    channel::{Bidirectional, Nil},
+    session, try_session, End, Message, Race, Receive, Role, Roles, Send,
+};
+use std::{error::Error, result, time::Duration};
+use tokio::{time, try_join};
+
+type Result<T> = result::Result<T, Box<dyn Error>>;
+
+type Channel = Bidirectional<UnboundedSender<Label>, UnboundedReceiver<Label>>;
+
+#[derive(Roles)]
+struct Roles(K, A, B);
+
+/// A coordinator which gives the same job to two workers and takes the answer
+/// of whichever finishes first.
+#[derive(Role)]
+#[message(Label)]
+struct K(#[route(A)] Channel, #[route(B)] Channel);
+
+#[derive(Role)]
+#[message(Label)]
+struct A(#[route(K)] Channel, #[route(B)] Nil);
+
+#[derive(Role)]
+#[message(Label)]
+struct B(#[route(K)] Channel, #[route(A)] Nil);
+
+#[derive(Message)]
+enum Label {
+    Job(Job),
+    Done(Done),
+}
+
+struct Job(u64);
+struct Done(u64);
+
+#[session]
+type Coordinator = Send<A, Job, Send<B, Job, Race<Reply>>>;
+
+#[session]
+enum Reply {
+    #[role(A)]
+    A(Done, Receive<B, Done, End>),
+    #[role(B)]
+    B(Done, Receive<A, Done, End>),
+}
+
+#[session]
+type WorkerA = Receive<K, Job, Send<K, Done, End>>;
+
+#[session]
+type WorkerB = Receive<K, Job, Send<K, Done, End>>;
+
+async fn coordinator(role: &mut K) -> Result<&'static str> {
+    try_session(role, |s: Coordinator<'_, _>| async {
+        let s = s.send(Job(21)).await?;
+        let s = s.send(Job(21)).await?;
+        let (winner, s) = match s.branch().await? {
+            Reply::A(Done(x), s) => {
+                let (Done(y), s) = s.receive().await?;
+                assert_eq!(x, y);
+                ("A", s)
+            }
+            Reply::B(Done(x), s) => {
+                let (Done(y), s) = s.receive().await?;
+                assert_eq!(x, y);
+                ("B", s)
+            }
+        };
+
+        Ok((winner, s))
+    })
+    .await
+}
+
+async fn worker_a(role: &mut A) -> Result<()> {
+    try_session(role, |s: WorkerA<'_, _>| async {
+        let (Job(x), s) = s.receive().await?;
+        time::sleep(Duration::from_millis(100)).await;
+        let s = s.send(Done(x * 2)).await?;
+        Ok(((), s))
+    })
+    .await
+}
+
+async fn worker_b(role: &mut B) -> Result<()> {
+    try_session(role, |s: WorkerB<'_, _>| async {
+        let (Job(x), s) = s.receive().await?;
+        time::sleep(Duration::from_millis(10)).await;
+        let s = s.send(Done(x * 2)).await?;
+        Ok(((), s))
+    })
+    .await
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    let Roles(mut k, mut a, mut b) = Roles::default();
+    let (winner, _, _) =
+        try_join!(coordinator(&mut k), worker_a(&mut a), worker_b(&mut b)).unwrap();
+
+    println!("{} answered first", winner);
+    assert_eq!(winner, "B");
+}
@@ -0,0 +1,96 @@
+use futures::{
+    channel::mpsc::{UnboundedReceiver, UnboundedSender},
+    executor, try_join, Sink, Stream,
+};
+use rumpsteak::{
+    channel::{Bidirectional, Cancel, Pair},
+    record::{Recorder, Replay},
+    session, try_session, End, Message, Receive, Role, Send,
+};
+use serde::{Deserialize, Serialize};
+use std::{error::Error, result};
+
+type Result<T> = result::Result<T, Box<dyn Error>>;
+
+type Channel = Bidirectional<UnboundedSender<Label>, UnboundedReceiver<Label>>;
+
+#[derive(Role)]
+#[message(Label)]
+struct C(#[route(S<Channel>)] Channel);
+
+#[derive(Role)]
+#[message(Label)]
+struct S<T: Cancel>(#[route(C)] T);
+
+#[derive(Message, Serialize, Deserialize)]
+enum Label {
+    Add(Add),
+    Sum(Sum),
+}
+
+#[derive(Serialize, Deserialize)]
+struct Add(i32);
+
+#[derive(Serialize, Deserialize)]
+struct Sum(i32);
+
+#[session]
+type Client<Q> = Send<Q, Add, Send<Q, Add, Receive<Q, Sum, End>>>;
+
+#[session]
+type Server = Receive<C, Add, Receive<C, Add, Send<C, Sum, End>>>;
+
+async fn client(role: &mut C) -> Result<i32> {
+    try_session(role, |s: Client<'_, _, S<Channel>>| async {
+        let s = s.send(Add(2)).await?;
+        let s = s.send(Add(3)).await?;
+        let (Sum(z), s) = s.receive().await?;
+        Ok((z, s))
+    })
+    .await
+}
+
+async fn server<T>(role: &mut S<T>, op: fn(i32, i32) -> i32) -> Result<()>
+where
+    T: Sink<Label> + Stream<Item = Label> + Cancel + Unpin,
+    T::Error: Error + 'static,
+{
+    try_session(role, |s: Server<'_, _>| async {
+        let (Add(x), s) = s.receive().await?;
+        let (Add(y), s) = s.receive().await?;
+        let s = s.send(Sum(op(x, y))).await?;
+        Ok(((), s))
+    })
+    .await
+}
+
+fn main() {
+    let file = tempfile::NamedTempFile::new().unwrap();
+
+    // Record the traffic of the server while it runs alongside the client.
+    let recorder = Recorder::create(file.path()).unwrap();
+    let (left, right) = Channel::pair();
+    let mut c = C(left);
+    let mut s = S(recorder.route("C", right));
+    let (sum, _) =
+        executor::block_on(async { try_join!(client(&mut c), server(&mut s, |x, y| x + y)) })
+            .unwrap();
+    recorder.flush().unwrap();
+    println!("recorded sum = {}", sum);
+
+    print!("{}", std::fs::read_to_string(file.path()).unwrap());
+
+    // Replay the recording to the server alone, without a client.
+    let replay = Replay::<Label>::open(file.path()).unwrap();
+    let mut s = S(replay.route("C"));
+    executor::block_on(server(&mut s, |x, y| x + y)).unwrap();
+    replay.verify().unwrap();
+    println!("replayed server matches the recording");
+
+    // A server which behaves differently is stopped where it diverges.
+    let replay = Replay::<Label>::open(file.path()).unwrap();
+    let mut s = S(replay.route("C"));
+    let error = executor::block_on(server(&mut s, |x, y| x * y)).unwrap_err();
+    println!("{}", error);
+    assert_eq!(replay.verify().unwrap_err().index, 2);
+}
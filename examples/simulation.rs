@@ -0,0 +1,99 @@
+use rumpsteak::{
+    channel::Bidirectional,
+    session,
+    sim::{self, Simulation},
+    try_session, End, Message, Receive, Role, Roles, Send,
+};
+use std::{env, error::Error, result};
+
+type Result<T> = result::Result<T, Box<dyn Error>>;
+
+type Channel = Bidirectional<sim::Sender<Label>, sim::Receiver<Label>>;
+
+#[derive(Roles)]
+struct Roles(A, B, C);
+
+#[derive(Role)]
+#[message(Label)]
+struct A(#[route(B)] Channel, #[route(C)] Channel);
+
+#[derive(Role)]
+#[message(Label)]
+struct B(#[route(A)] Channel, #[route(C)] Channel);
+
+#[derive(Role)]
+#[message(Label)]
+struct C(#[route(A)] Channel, #[route(B)] Channel);
+
+#[derive(Message)]
+enum Label {
+    Add(Add),
+    Sum(Sum),
+}
+
+struct Add(i32);
+struct Sum(i32);
+
+#[session]
+type AdderA = Send<B, Add, Send<C, Add, Receive<C, Sum, End>>>;
+
+#[session]
+type AdderB = Receive<A, Add, Send<C, Add, Receive<C, Sum, End>>>;
+
+#[session]
+type AdderC = Receive<A, Add, Receive<B, Add, Send<A, Sum, Send<B, Sum, End>>>>;
+
+async fn adder_a(role: &mut A) -> Result<()> {
+    try_session(role, |s: AdderA<'_, _>| async {
+        let s = s.send(Add(2)).await?;
+        let s = s.send(Add(2)).await?;
+        let (Sum(z), s) = s.receive().await?;
+        println!("A received {} at time {}", z, sim::now());
+        Ok(((), s))
+    })
+    .await
+}
+
+async fn adder_b(role: &mut B) -> Result<()> {
+    try_session(role, |s: AdderB<'_, _>| async {
+        let (Add(x), s) = s.receive().await?;
+        let s = s.send(Add(x + 1)).await?;
+        let (Sum(z), s) = s.receive().await?;
+        println!("B received {} at time {}", z, sim::now());
+        Ok(((), s))
+    })
+    .await
+}
+
+async fn adder_c(role: &mut C) -> Result<()> {
+    try_session(role, |s: AdderC<'_, _>| async {
+        let (Add(x), s) = s.receive().await?;
+        let (Add(y), s) = s.receive().await?;
+        let s = s.send(Sum(x + y)).await?;
+        Ok(((), s.send(Sum(x + y)).await?))
+    })
+    .await
+}
+
+fn simulate(seed: u64) -> Result<u64> {
+    let mut simulation = Simulation::new(seed);
+    let Roles(mut a, mut b, mut c) = simulation.enter(Roles::default);
+    simulation.spawn(async move { adder_a(&mut a).await.unwrap() });
+    simulation.spawn(async move { adder_b(&mut b).await.unwrap() });
+    simulation.spawn(async move { adder_c(&mut c).await.unwrap() });
+    Ok(simulation.run()?)
+}
+
+fn main() {
+    // A seed can be given to replay a single run exactly.
+    let seeds = match env::args().nth(1) {
+        Some(seed) => seed.parse().unwrap()..=seed.parse().unwrap(),
+        None => 0..=4,
+    };
+
+    for seed in seeds {
+        println!("seed {}", seed);
+        let time = simulate(seed).unwrap();
+        println!("finished at time {}", time);
+    }
+}
@@ -0,0 +1,106 @@
+use futures::{
+    channel::mpsc::{UnboundedReceiver, UnboundedSender},
+    executor, try_join, SinkExt, StreamExt,
+};
+use rumpsteak::{
+    channel::{Bidirectional, Pair, ReceiveHalf, SendHalf},
+    session, try_session, End, Message, Receive, Role, Roles, Send,
+};
+use std::{error::Error, result};
+
+type Result<T> = result::Result<T, Box<dyn Error>>;
+
+type Sender = UnboundedSender<Label>;
+type Receiver = UnboundedReceiver<Label>;
+type Channel = Bidirectional<Sender, Receiver>;
+
+#[derive(Roles)]
+struct Roles(P, C);
+
+#[derive(Role)]
+#[message(Label)]
+struct P(#[route(C)] SendHalf<Sender>);
+
+#[derive(Role)]
+#[message(Label)]
+struct C(#[route(P)] ReceiveHalf<Receiver>);
+
+#[derive(Message, Debug)]
+enum Label {
+    Value(Value),
+}
+
+#[derive(Debug)]
+struct Value(i32);
+
+#[session]
+type Producer = Send<C, Value, Send<C, Value, End>>;
+
+#[session]
+type Consumer = Receive<P, Value, Receive<P, Value, End>>;
+
+async fn producer(role: &mut P) -> Result<()> {
+    try_session(role, |s: Producer<'_, _>| async {
+        let s = s.send(Value(1)).await?;
+        Ok(((), s.send(Value(2)).await?))
+    })
+    .await
+}
+
+async fn consumer(role: &mut C) -> Result<i32> {
+    try_session(role, |s: Consumer<'_, _>| async {
+        let (Value(x), s) = s.receive().await?;
+        let (Value(y), s) = s.receive().await?;
+        Ok((x + y, s))
+    })
+    .await
+}
+
+fn main() {
+    // Routes which only go one way can be paired directly from their halves.
+    let Roles(mut p, mut c) = Roles::default();
+    let (_, sum) =
+        executor::block_on(async { try_join!(producer(&mut p), consumer(&mut c)) }).unwrap();
+    println!("paired halves: sum = {}", sum);
+
+    // A route can be split so that its receiving half is read elsewhere, while
+    // the session only needs the sending half.
+    let (left, right) = Channel::pair();
+    let (left_sender, left_receiver) = left.split();
+    let (mut right_sender, right_receiver) = right.split();
+    let mut p = P(left_sender);
+    let mut c = C(right_receiver);
+
+    let reader = async {
+        let mut left_receiver = left_receiver;
+        let message = left_receiver.next().await;
+        println!("background reader: {:?}", message);
+        left_receiver
+    };
+    let writer = async {
+        right_sender.send(Label::Value(Value(3))).await.unwrap();
+    };
+
+    let (left_receiver, _, session) = executor::block_on(async {
+        let session = async { try_join!(producer(&mut p), consumer(&mut c)) };
+        futures::join!(reader, writer, session)
+    });
+    let (_, sum) = session.unwrap();
+    println!("split halves: sum = {}", sum);
+
+    // The halves are put back together once both are done with.
+    let C(right_receiver) = c;
+    let result = Channel::reunite(right_sender, left_receiver);
+    let (right_sender, left_receiver) = match result {
+        Ok(_) => panic!("reunited halves of different routes"),
+        Err(error) => {
+            println!("{}", error);
+            (error.0, error.1)
+        }
+    };
+
+    let P(left_sender) = p;
+    Channel::reunite(left_sender, left_receiver).unwrap();
+    Channel::reunite(right_sender, right_receiver).unwrap();
+    println!("reunited both routes");
+}
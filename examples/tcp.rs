@@ -0,0 +1,78 @@
+use futures::try_join;
+use rumpsteak::{
+    net::{
+        tcp::{self, TcpRoute},
+        Bincode,
+    },
+    session, try_session, End, Message, Receive, Role, Send,
+};
+use serde::{Deserialize, Serialize};
+use std::{error::Error, result};
+use tokio::net::TcpListener;
+
+type Result<T> = result::Result<T, Box<dyn Error>>;
+
+type Route = TcpRoute<Label, Bincode>;
+
+#[derive(Role)]
+#[message(Label)]
+struct C(#[route(S)] Route);
+
+#[derive(Role)]
+#[message(Label)]
+struct S(#[route(C)] Route);
+
+#[derive(Message, Serialize, Deserialize)]
+enum Label {
+    Add(Add),
+    Sum(Sum),
+}
+
+#[derive(Serialize, Deserialize)]
+struct Add(i32);
+
+#[derive(Serialize, Deserialize)]
+struct Sum(i32);
+
+#[session]
+type Client = Send<S, Add, Send<S, Add, Receive<S, Sum, End>>>;
+
+#[session]
+type Server = Receive<C, Add, Receive<C, Add, Send<C, Sum, End>>>;
+
+async fn client(role: &mut C) -> Result<i32> {
+    try_session(role, |s: Client<'_, _>| async {
+        let s = s.send(Add(1)).await?;
+        let s = s.send(Add(2)).await?;
+        let (Sum(z), s) = s.receive().await?;
+        Ok((z, s))
+    })
+    .await
+}
+
+async fn server(role: &mut S) -> Result<()> {
+    try_session(role, |s: Server<'_, _>| async {
+        let (Add(x), s) = s.receive().await?;
+        let (Add(y), s) = s.receive().await?;
+        let s = s.send(Sum(x + y)).await?;
+        Ok(((), s))
+    })
+    .await
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    // Each role would usually run in its own process, with only its own route.
+    let (mut c, mut s) = try_join!(
+        async { Ok::<_, Box<dyn Error>>(C(tcp::connect(addr, Bincode).await?)) },
+        async { Ok(S(tcp::accept(&listener, Bincode).await?)) },
+    )
+    .unwrap();
+
+    let (sum, _) = try_join!(client(&mut c), server(&mut s)).unwrap();
+    println!("sum over {}: {}", addr, sum);
+    assert_eq!(sum, 3);
+}
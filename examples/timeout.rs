@@ -0,0 +1,79 @@
+use futures::channel::mpsc::{UnboundedReceiver, UnboundedSender};
+use rumpsteak::{
+    channel::Bidirectional, session, try_session, End, Message, Receive, Role, Roles, Send, Timed,
+    Timeout,
+};
+use std::{error::Error, result, time::Duration};
+use tokio::{time, try_join};
+
+type Result<T> = result::Result<T, Box<dyn Error>>;
+
+type Channel = Bidirectional<UnboundedSender<Label>, UnboundedReceiver<Label>>;
+
+#[derive(Roles)]
+struct Roles(C, S);
+
+#[derive(Role)]
+#[message(Label)]
+struct C(#[route(S)] Channel);
+
+#[derive(Role)]
+#[message(Label)]
+struct S(#[route(C)] Channel);
+
+#[derive(Message)]
+enum Label {
+    Request(Request),
+    Response(Response),
+}
+
+struct Request(u64);
+struct Response(u64);
+
+#[session]
+type Client = Send<S, Request, Timeout<S, ClientChoice>>;
+
+#[session]
+#[timeout(Receive<S, Response, End>)]
+enum ClientChoice {
+    Response(Response, End),
+}
+
+#[session]
+type Server = Receive<C, Request, Send<C, Response, End>>;
+
+async fn client(role: &mut C, delay: u64) -> Result<u64> {
+    try_session(role, |s: Client<'_, _>| async {
+        let s = s.send(Request(delay)).await?;
+        let (Response(x), s) = match s.branch(time::sleep(Duration::from_millis(10))).await? {
+            Timed::Received(ClientChoice::Response(response, s)) => (response, s),
+            Timed::Elapsed(s) => {
+                println!("no response after 10ms, still waiting");
+                s.receive().await?
+            }
+        };
+
+        Ok((x, s))
+    })
+    .await
+}
+
+async fn server(role: &mut S) -> Result<()> {
+    try_session(role, |s: Server<'_, _>| async {
+        let (Request(delay), s) = s.receive().await?;
+        time::sleep(Duration::from_millis(delay)).await;
+        let s = s.send(Response(delay * 2)).await?;
+        Ok(((), s))
+    })
+    .await
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    for delay in [0, 50] {
+        let Roles(mut c, mut s) = Roles::default();
+        let (output, _) = try_join!(client(&mut c, delay), server(&mut s)).unwrap();
+        println!("delay = {}ms, output = {}", delay, output);
+        assert_eq!(output, delay * 2);
+    }
+}
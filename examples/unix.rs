@@ -0,0 +1,92 @@
+use rumpsteak::{
+    net::{
+        unix::{self, UnixRoute},
+        Bincode,
+    },
+    session, try_session, End, Message, Receive, Role, Send,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    env,
+    error::Error,
+    process::{self, Command},
+    result,
+};
+use tokio::net::UnixListener;
+
+type Result<T> = result::Result<T, Box<dyn Error>>;
+
+type Route = UnixRoute<Label, Bincode>;
+
+#[derive(Role)]
+#[message(Label)]
+struct C(#[route(S)] Route);
+
+#[derive(Role)]
+#[message(Label)]
+struct S(#[route(C)] Route);
+
+#[derive(Message, Serialize, Deserialize)]
+enum Label {
+    Add(Add),
+    Sum(Sum),
+}
+
+#[derive(Serialize, Deserialize)]
+struct Add(i32);
+
+#[derive(Serialize, Deserialize)]
+struct Sum(i32);
+
+#[session]
+type Client = Send<S, Add, Send<S, Add, Receive<S, Sum, End>>>;
+
+#[session]
+type Server = Receive<C, Add, Receive<C, Add, Send<C, Sum, End>>>;
+
+async fn client(role: &mut C) -> Result<i32> {
+    try_session(role, |s: Client<'_, _>| async {
+        let s = s.send(Add(process::id() as i32 % 100)).await?;
+        let s = s.send(Add(1)).await?;
+        let (Sum(z), s) = s.receive().await?;
+        Ok((z, s))
+    })
+    .await
+}
+
+async fn server(role: &mut S) -> Result<i32> {
+    try_session(role, |s: Server<'_, _>| async {
+        let (Add(x), s) = s.receive().await?;
+        let (Add(y), s) = s.receive().await?;
+        let s = s.send(Sum(x + y)).await?;
+        Ok((x + y, s))
+    })
+    .await
+}
+
+/// Runs the server in this process and the client in a child process, which
+/// is this same executable given the path of the socket to connect to.
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    if let Some(path) = env::args().nth(1) {
+        let mut c = C(unix::connect(path, Bincode).await.unwrap());
+        let sum = client(&mut c).await.unwrap();
+        println!("client {}: {}", process::id(), sum);
+        return;
+    }
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("rumpsteak.sock");
+    let listener = UnixListener::bind(&path).unwrap();
+
+    let mut child = Command::new(env::current_exe().unwrap())
+        .arg(&path)
+        .spawn()
+        .unwrap();
+
+    let mut s = S(unix::accept(&listener, Bincode).await.unwrap());
+    let sum = server(&mut s).await.unwrap();
+    println!("server {}: {}", process::id(), sum);
+
+    assert!(child.wait().unwrap().success());
+}
@@ -0,0 +1,96 @@
+use futures::try_join;
+use rumpsteak::{
+    net::{
+        tcp::{self, TcpRoute},
+        Cbor, Codec, Json,
+    },
+    session, try_session, End, Message, Receive, Role, Send, Wire,
+};
+use serde::{Deserialize, Serialize};
+use std::{error::Error, result, str};
+use tokio::net::TcpListener;
+
+type Result<T> = result::Result<T, Box<dyn Error>>;
+
+type Route = TcpRoute<Label, Cbor>;
+
+#[derive(Role)]
+#[message(Label)]
+struct C(#[route(S)] Route);
+
+#[derive(Role)]
+#[message(Label)]
+struct S(#[route(C)] Route);
+
+/// Each label is encoded as its discriminant followed by its payload, so the
+/// encoding stays the same if the variants are reordered.
+#[derive(Debug, Message, Wire)]
+enum Label {
+    #[discriminant(2)]
+    Sum(Sum),
+    #[discriminant(1)]
+    Add(Add),
+    #[discriminant(3)]
+    Done,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Add(i32);
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Sum {
+    total: i32,
+}
+
+#[session]
+type Client = Send<S, Add, Send<S, Add, Receive<S, Sum, Send<S, LabelDone, End>>>>;
+
+#[session]
+type Server = Receive<C, Add, Receive<C, Add, Send<C, Sum, Receive<C, LabelDone, End>>>>;
+
+async fn client(role: &mut C) -> Result<i32> {
+    try_session(role, |s: Client<'_, _>| async {
+        let s = s.send(Add(1)).await?;
+        let s = s.send(Add(2)).await?;
+        let (Sum { total }, s) = s.receive().await?;
+        Ok((total, s.send(LabelDone).await?))
+    })
+    .await
+}
+
+async fn server(role: &mut S) -> Result<()> {
+    try_session(role, |s: Server<'_, _>| async {
+        let (Add(x), s) = s.receive().await?;
+        let (Add(y), s) = s.receive().await?;
+        let s = s.send(Sum { total: x + y }).await?;
+        let (LabelDone, s) = s.receive().await?;
+        Ok(((), s))
+    })
+    .await
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    for label in [
+        Label::Add(Add(1)),
+        Label::Sum(Sum { total: 3 }),
+        Label::Done,
+    ] {
+        let mut buffer = Vec::new();
+        Json.encode(&label, &mut buffer).unwrap();
+        println!("{:?} is {}", label, str::from_utf8(&buffer).unwrap());
+    }
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let (mut c, mut s) = try_join!(
+        async { Ok::<_, Box<dyn Error>>(C(tcp::connect(addr, Cbor).await?)) },
+        async { Ok(S(tcp::accept(&listener, Cbor).await?)) },
+    )
+    .unwrap();
+
+    let (sum, _) = try_join!(client(&mut c), server(&mut s)).unwrap();
+    println!("sum over CBOR: {}", sum);
+    assert_eq!(sum, 3);
+}
@@ -317,6 +317,10 @@ impl<N, E> Message<N, E> {
     pub fn from_label(label: N) -> Self {
         Self::new(label, Default::default(), Default::default())
     }
+
+    pub fn label(&self) -> &N {
+        &self.label
+    }
 }
 
 impl<N: Display, E: Display> Display for Message<N, E> {
@@ -340,23 +344,25 @@ impl<N: Display, E: Display> Display for Message<N, E> {
     }
 }
 
-#[derive(Clone, Debug)]
-struct Choices<R> {
-    role: R,
-    action: Action,
+#[derive(Clone, Copy, Debug)]
+enum State {
+    Choices(Action),
+    End,
 }
 
+/// A transition out of a state, which carries its own role since an input
+/// state may receive from several roles.
 #[derive(Clone, Debug)]
-enum State<R> {
-    Choices(Choices<R>),
-    End,
+struct Edge<R, N, E> {
+    role: R,
+    message: Message<N, E>,
 }
 
 #[derive(Clone, Copy, Default, PartialEq, Eq)]
 pub struct StateIndex(NodeIndex);
 
 impl StateIndex {
-    pub(crate) fn index(self) -> usize {
+    pub fn index(self) -> usize {
         self.0.index()
     }
 }
@@ -427,7 +433,7 @@ impl<R: Display, N: Display, E: Display> Display for TransitionRef<'_, R, N, E>
 pub enum AddTransitionError {
     #[error("cannot perform self-communication")]
     SelfCommunication,
-    #[error("cannot communicate with different roles from the same state")]
+    #[error("cannot send to different roles from the same state")]
     MultipleRoles,
     #[error("cannot both send and receive from the same state")]
     MultipleActions,
@@ -436,7 +442,7 @@ pub enum AddTransitionError {
 #[derive(Clone, Debug)]
 pub struct Fsm<R, N, E> {
     role: R,
-    graph: Graph<State<R>, Message<N, E>>,
+    graph: Graph<State, Edge<R, N, E>>,
 }
 
 impl<R, N, E> Fsm<R, N, E> {
@@ -462,14 +468,11 @@ impl<R, N, E> Fsm<R, N, E> {
     ) -> impl Iterator<Item = (StateIndex, StateIndex, TransitionRef<R, N, E>)> {
         self.graph.edge_references().map(move |edge| {
             let (source, target) = (StateIndex(edge.source()), StateIndex(edge.target()));
-            match &self.graph[edge.source()] {
-                State::Choices(choices) => {
-                    let transition =
-                        TransitionRef::new(&choices.role, choices.action, edge.weight());
-                    (source, target, transition)
-                }
-                _ => unreachable!(),
-            }
+            (
+                source,
+                target,
+                self.transition(edge.source(), edge.weight()),
+            )
         })
     }
 
@@ -477,16 +480,23 @@ impl<R, N, E> Fsm<R, N, E> {
         &self,
         StateIndex(index): StateIndex,
     ) -> impl Iterator<Item = (StateIndex, TransitionRef<R, N, E>)> {
-        self.graph
-            .edges(index)
-            .map(move |edge| match &self.graph[index] {
-                State::Choices(choices) => {
-                    let transition =
-                        TransitionRef::new(&choices.role, choices.action, edge.weight());
-                    (StateIndex(edge.target()), transition)
-                }
-                _ => unreachable!(),
-            })
+        self.graph.edges(index).map(move |edge| {
+            (
+                StateIndex(edge.target()),
+                self.transition(index, edge.weight()),
+            )
+        })
+    }
+
+    fn transition<'a>(
+        &self,
+        from: NodeIndex,
+        edge: &'a Edge<R, N, E>,
+    ) -> TransitionRef<'a, R, N, E> {
+        match self.graph[from] {
+            State::Choices(action) => TransitionRef::new(&edge.role, action, &edge.message),
+            State::End => unreachable!(),
+        }
     }
 
     pub fn add_state(&mut self) -> StateIndex {
@@ -506,26 +516,30 @@ impl<R, N, E> Fsm<R, N, E> {
             return Err(AddTransitionError::SelfCommunication);
         }
 
-        let choices = Choices {
-            role: transition.role,
-            action: transition.action,
-        };
-
-        let state = &mut self.graph[from.0];
-        match state {
-            State::End => *state = State::Choices(choices),
-            State::Choices(expected) => {
-                if choices.role != expected.role {
-                    return Err(AddTransitionError::MultipleRoles);
+        match self.graph[from.0] {
+            State::End => self.graph[from.0] = State::Choices(transition.action),
+            State::Choices(action) => {
+                if transition.action != action {
+                    return Err(AddTransitionError::MultipleActions);
                 }
 
-                if choices.action != expected.action {
-                    return Err(AddTransitionError::MultipleActions);
+                // Receiving from several roles waits for whichever sends first,
+                // but a choice to send must be made with a single role.
+                let mut edges = self.graph.edges(from.0);
+                if action == Action::Output
+                    && edges.any(|edge| edge.weight().role != transition.role)
+                {
+                    return Err(AddTransitionError::MultipleRoles);
                 }
             }
         }
 
-        self.graph.add_edge(from.0, to.0, transition.message);
+        let edge = Edge {
+            role: transition.role,
+            message: transition.message,
+        };
+
+        self.graph.add_edge(from.0, to.0, edge);
         Ok(())
     }
 
@@ -537,21 +551,18 @@ impl<R, N, E> Fsm<R, N, E> {
     {
         let mut role = None;
         let graph = self.graph.map(
-            |_, state| match state {
-                State::Choices(choice) => {
-                    match role {
-                        Some(role) => assert_eq!(role, &choice.role),
-                        None => role = Some(&choice.role),
-                    }
-
-                    State::Choices(Choices {
-                        role: Nil,
-                        action: choice.action,
-                    })
+            |_, state| *state,
+            |_, edge| {
+                match role {
+                    Some(role) => assert_eq!(role, &edge.role),
+                    None => role = Some(&edge.role),
+                }
+
+                Edge {
+                    role: Nil,
+                    message: edge.message.clone(),
                 }
-                State::End => State::End,
             },
-            |_, edge| edge.clone(),
         );
 
         Fsm { role: Nil, graph }
@@ -565,16 +576,16 @@ impl<R, N, E> Fsm<R, N, E> {
     {
         let graph = self.graph.map(
             |_, state| match state {
-                State::Choices(choice) => {
-                    assert_eq!(role, choice.role);
-                    State::Choices(Choices {
-                        role: self.role.clone(),
-                        action: choice.action.dual(),
-                    })
-                }
+                State::Choices(action) => State::Choices(action.dual()),
                 State::End => State::End,
             },
-            |_, edge| edge.clone(),
+            |_, edge| {
+                assert_eq!(role, edge.role);
+                Edge {
+                    role: self.role.clone(),
+                    message: edge.message.clone(),
+                }
+            },
         );
 
         Fsm { role, graph }
@@ -611,15 +622,79 @@ impl<'a, R: Eq + Hash, N: Eq + Hash> Normalizer<'a, R, N> {
         Fsm {
             role: Self::role(roles, &input.role),
             graph: input.graph.map(
-                |_, state| match state {
-                    State::End => State::End,
-                    State::Choices(choices) => State::Choices(Choices {
-                        role: Self::role(roles, &choices.role),
-                        action: choices.action,
-                    }),
+                |_, state| *state,
+                |_, edge| Edge {
+                    role: Self::role(roles, &edge.role),
+                    message: Message::from_label(Self::label(labels, &edge.message.label)),
                 },
-                |_, message| Message::from_label(Self::label(labels, &message.label)),
             ),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::Infallible;
+
+    type Fsm = super::Fsm<&'static str, &'static str, Infallible>;
+
+    fn transition(
+        role: &'static str,
+        action: Action,
+        label: &'static str,
+    ) -> Transition<&'static str, &'static str, Infallible> {
+        Transition::new(role, action, Message::from_label(label))
+    }
+
+    /// An FSM for `A` which receives from each of `senders` in a single state.
+    fn race(senders: &[(&'static str, &'static str)]) -> Fsm {
+        let mut fsm = Fsm::new("A");
+        let (from, to) = (fsm.add_state(), fsm.add_state());
+        for &(role, label) in senders {
+            fsm.add_transition(from, to, transition(role, Action::Input, label))
+                .unwrap();
+        }
+
+        fsm
+    }
+
+    #[test]
+    fn input_from_several_roles() {
+        let fsm = race(&[("B", "x"), ("C", "y")]);
+        let mut transitions = fsm.transitions_from(Default::default());
+        let roles =
+            [transitions.next(), transitions.next()].map(|transition| *transition.unwrap().1.role);
+        assert!(roles.contains(&"B") && roles.contains(&"C"));
+        assert!(transitions.next().is_none());
+    }
+
+    #[test]
+    fn output_to_several_roles() {
+        let mut fsm = Fsm::new("A");
+        let (from, to) = (fsm.add_state(), fsm.add_state());
+        fsm.add_transition(from, to, transition("B", Action::Output, "x"))
+            .unwrap();
+        let result = fsm.add_transition(from, to, transition("C", Action::Output, "y"));
+        assert!(matches!(result, Err(AddTransitionError::MultipleRoles)));
+    }
+
+    #[test]
+    fn input_and_output_from_several_roles() {
+        let mut fsm = race(&[("B", "x")]);
+        let (from, to) = (Default::default(), fsm.add_state());
+        let result = fsm.add_transition(from, to, transition("C", Action::Output, "y"));
+        assert!(matches!(result, Err(AddTransitionError::MultipleActions)));
+    }
+
+    #[test]
+    fn subtype_input_from_several_roles() {
+        let both = race(&[("B", "x"), ("C", "y")]);
+        let only = race(&[("B", "x")]);
+        let swapped = race(&[("B", "y"), ("C", "x")]);
+        assert!(subtype::is_subtype(&both, &both, 1));
+        assert!(subtype::is_subtype(&both, &only, 1));
+        assert!(!subtype::is_subtype(&only, &both, 1));
+        assert!(!subtype::is_subtype(&swapped, &both, 1));
+    }
+}
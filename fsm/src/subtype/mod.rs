@@ -142,6 +142,8 @@ impl<'a, R: Eq, N: Eq> SubtypeVisitor<'a, R, N> {
                         self.unroll::<_, false>(transitions, quantifiers)
                     }
                     (Action::Input, Action::Input) => {
+                        // Inputs may come from several roles, so each transition
+                        // is matched along with its role rather than per state.
                         let quantifiers = Pair::new(Quantifier::Any, Quantifier::All);
                         self.unroll::<_, true>(transitions, quantifiers)
                     }
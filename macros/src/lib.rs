@@ -2,34 +2,79 @@ use proc_macro::TokenStream;
 
 mod message;
 mod parse;
+mod protocol;
 mod role;
 mod roles;
 mod session;
+mod wire;
 
-#[proc_macro_derive(Message)]
+/// Derives `Message` for a struct, which is its own only label, or for an enum
+/// with a label for each variant.
+///
+/// A variant with exactly one unnamed field, such as `Add(Add)`, is labelled by
+/// the type of that field. Any other variant of an enum which is not generic is
+/// given a label named after the enum and the variant, with the same fields,
+/// such as `LabelSum { x: i32 }` for `Label::Sum { x: i32 }` or `LabelDone` for
+/// `Label::Done`, while every variant of a generic enum must have exactly one
+/// unnamed field. A variant's key, given with `#[key(...)]`, applies to its
+/// label.
+///
+/// The message is also `Labelled`, where the discriminant of each variant is
+/// its position unless given with `#[discriminant(...)]`.
+#[proc_macro_derive(Message, attributes(discriminant, key))]
 pub fn message(input: TokenStream) -> TokenStream {
     message::message(input.into())
         .unwrap_or_else(|err| err.to_compile_error())
         .into()
 }
 
-#[proc_macro_derive(Role, attributes(message, route))]
+/// Defines a protocol from its global type, written in the style of Scribble,
+/// by projecting it onto each role. This emits a module named after the
+/// protocol, such as `auth` for `Auth`, with the `Roles`, each role, the
+/// `Label` message with a struct for each label, and the session type of each
+/// role, named after the protocol and the role, such as `AuthC`. The module
+/// and the fields of `Roles` are raw identifiers where their names would be
+/// keywords, such as `r#loop` for `Loop`, and no role or label may share a
+/// name with another item in the module.
+///
+/// The routes between roles are unbounded channels, unless their type is given
+/// with `#[channel(...)]` before the protocol, where it may name `Label`. Such
+/// routes may not be pairable, so the `Roles` then only have a builder.
+#[proc_macro]
+pub fn protocol(input: TokenStream) -> TokenStream {
+    protocol::protocol(input.into())
+        .unwrap_or_else(|err| err.to_compile_error())
+        .into()
+}
+
+#[proc_macro_derive(Role, attributes(family, index, message, route))]
 pub fn role(input: TokenStream) -> TokenStream {
     role::role(input.into())
         .unwrap_or_else(|err| err.to_compile_error())
         .into()
 }
 
-#[proc_macro_derive(Roles)]
+#[proc_macro_derive(Roles, attributes(roles))]
 pub fn roles(input: TokenStream) -> TokenStream {
     roles::roles(input.into())
         .unwrap_or_else(|err| err.to_compile_error())
         .into()
 }
 
+/// Gives a session type the lifetime and role it runs with, along with each
+/// session type it continues as, which is always its last argument. The
+/// continuation of a `Timeout` once its deadline passes is given on its choices
+/// with `#[timeout(...)]`.
 #[proc_macro_attribute]
 pub fn session(attr: TokenStream, input: TokenStream) -> TokenStream {
     session::session(attr.into(), input.into())
         .unwrap_or_else(|err| err.to_compile_error())
         .into()
 }
+
+#[proc_macro_derive(Wire, attributes(discriminant))]
+pub fn wire(input: TokenStream) -> TokenStream {
+    wire::wire(input.into())
+        .unwrap_or_else(|err| err.to_compile_error())
+        .into()
+}
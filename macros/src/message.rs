@@ -1,6 +1,7 @@
+use crate::parse;
 use proc_macro2::TokenStream;
-use quote::quote;
-use syn::{parse2, Data, DeriveInput, Error, Fields, Result};
+use quote::{format_ident, quote, ToTokens};
+use syn::{parse2, Data, DeriveInput, Error, Fields, Result, Type};
 
 pub fn message(input: TokenStream) -> Result<TokenStream> {
     let input = parse2::<DeriveInput>(input)?;
@@ -19,6 +20,26 @@ pub fn message(input: TokenStream) -> Result<TokenStream> {
                     ::core::result::Result::Ok(self)
                 }
             }
+
+            impl #impl_generics ::rumpsteak::Labelled for #ident #ty_generics #where_clause {
+                type Discriminant = u32;
+
+                fn discriminant(&self) -> u32 {
+                    0
+                }
+
+                fn name(&self) -> &'static str {
+                    ::core::stringify!(#ident)
+                }
+            }
+
+            impl #impl_generics ::rumpsteak::Keyed<Self> for #ident #ty_generics #where_clause {
+                type Label = Self;
+
+                fn discriminant() -> u32 {
+                    0
+                }
+            }
         });
     }
 
@@ -27,39 +48,129 @@ pub fn message(input: TokenStream) -> Result<TokenStream> {
         _ => Err(Error::new_spanned(&input, "expected a struct or enum")),
     }?;
 
-    let mut output = TokenStream::new();
-    for variant in variants {
-        let variant_ident = &variant.ident;
-        let fields = match &variant.fields {
-            Fields::Unnamed(fields) => Ok(&fields.unnamed),
-            _ => Err(Error::new_spanned(&variant.fields, "expected tuple fields")),
-        }?;
-
-        let mut fields_iter = fields.iter();
-        let field = match (fields_iter.next(), fields_iter.next()) {
-            (Some(field), None) => Ok(field),
-            _ => {
-                let message = "expected exactly one field per variant";
-                Err(Error::new_spanned(&fields, message))
-            }
-        }?;
-
-        let ty = &field.ty;
-        output.extend(quote! {
-            impl #impl_generics ::rumpsteak::Message<#ty> for #ident #ty_generics #where_clause {
+    let discriminants = parse::discriminants(variants)?;
+    let message = |discriminant: u32,
+                   ty: &TokenStream,
+                   key: &TokenStream,
+                   upcast,
+                   (pattern, value)| {
+        quote! {
+            impl #impl_generics ::rumpsteak::Message<#ty, #key> for #ident #ty_generics #where_clause {
                 fn upcast(label: #ty) -> Self {
-                    Self::#variant_ident(label)
+                    #upcast
                 }
 
                 fn downcast(self) -> ::core::result::Result<#ty, Self> {
                     match self {
-                        Self::#variant_ident(label) => ::core::result::Result::Ok(label),
+                        #pattern => ::core::result::Result::Ok(#value),
                         _ => ::core::result::Result::Err(self),
                     }
                 }
             }
+
+            impl #impl_generics ::rumpsteak::Keyed<#key> for #ident #ty_generics #where_clause {
+                type Label = #ty;
+
+                fn discriminant() -> u32 {
+                    #discriminant
+                }
+            }
+        }
+    };
+
+    // A variant with exactly one unnamed field is labelled by the type of that
+    // field. Any other variant of a message which is not generic is given a
+    // label of its own, named after the message and the variant with the same
+    // fields, so that session types can name it without clashing with the
+    // labels of other messages.
+    let generic = !input.generics.params.is_empty();
+    let mut output = TokenStream::new();
+    for (variant, &discriminant) in variants.iter().zip(&discriminants) {
+        let variant_ident = &variant.ident;
+
+        // A key given with `#[key(...)]` identifies the label in place of its
+        // type, so that several variants can carry the same type.
+        let key = parse::optional_attribute::<Type>(&variant.attrs, "key")?;
+        let key = key.map(|key| key.to_token_stream());
+
+        let field = match &variant.fields {
+            Fields::Unnamed(fields) if fields.unnamed.len() == 1 => Some(&fields.unnamed[0].ty),
+            _ => None,
+        };
+
+        if let Some(ty) = field {
+            let ty = ty.to_token_stream();
+            let upcast = quote!(Self::#variant_ident(label));
+            let downcast = (quote!(Self::#variant_ident(label)), quote!(label));
+            let key = key.unwrap_or_else(|| ty.clone());
+            output.extend(message(discriminant, &ty, &key, upcast, downcast));
+            continue;
+        }
+
+        if generic {
+            let message = "expected exactly one field per variant of a generic message";
+            return Err(Error::new_spanned(&variant.fields, message));
+        }
+
+        let vis = &input.vis;
+        let label = format_ident!("{}{}", ident, variant_ident);
+        let (definition, bindings) = match &variant.fields {
+            Fields::Named(fields) => {
+                let idents = fields.named.iter().map(|field| &field.ident);
+                let idents = idents.collect::<Vec<_>>();
+                let tys = fields.named.iter().map(|field| &field.ty);
+                (
+                    quote!({ #(#vis #idents: #tys),* }),
+                    quote!({ #(#idents),* }),
+                )
+            }
+            Fields::Unnamed(fields) => {
+                let idents = (0..fields.unnamed.len()).map(|i| format_ident!("field_{}", i));
+                let tys = fields.unnamed.iter().map(|field| &field.ty);
+                (quote!((#(#vis #tys),*);), quote!((#(#idents),*)))
+            }
+            Fields::Unit => (quote!(;), quote!()),
+        };
+
+        let doc = format!("The label of `{}::{}`.", ident, variant_ident);
+        output.extend(quote! {
+            #[doc = #doc]
+            #vis struct #label #definition
         });
+
+        let upcast = quote! {
+            let #label #bindings = label;
+            Self::#variant_ident #bindings
+        };
+        let downcast = (
+            quote!(Self::#variant_ident #bindings),
+            quote!(#label #bindings),
+        );
+
+        let ty = label.to_token_stream();
+        let key = key.unwrap_or_else(|| ty.clone());
+        output.extend(message(discriminant, &ty, &key, upcast, downcast));
     }
 
+    let variant_idents = variants.iter().map(|variant| &variant.ident);
+    let variant_idents = variant_idents.collect::<Vec<_>>();
+    output.extend(quote! {
+        impl #impl_generics ::rumpsteak::Labelled for #ident #ty_generics #where_clause {
+            type Discriminant = u32;
+
+            fn discriminant(&self) -> u32 {
+                match *self {
+                    #(Self::#variant_idents { .. } => #discriminants,)*
+                }
+            }
+
+            fn name(&self) -> &'static str {
+                match *self {
+                    #(Self::#variant_idents { .. } => ::core::stringify!(#variant_idents),)*
+                }
+            }
+        }
+    });
+
     Ok(output)
 }
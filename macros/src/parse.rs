@@ -1,5 +1,9 @@
 use proc_macro2::Span;
-use syn::{parse::Parse, Attribute, Error, Result};
+use std::collections::HashSet;
+use syn::{
+    parse::Parse, punctuated::Punctuated, spanned::Spanned, Attribute, Error, GenericArgument,
+    LitInt, PathArguments, Result, Type, Variant,
+};
 
 pub fn optional_attribute<T: Parse>(attrs: &[Attribute], ident: &str) -> Result<Option<T>> {
     let mut output = None;
@@ -25,3 +29,46 @@ pub fn attribute<T: Parse>(attrs: &[Attribute], ident: &str, span: Span) -> Resu
     optional_attribute(attrs, ident)?
         .ok_or_else(|| Error::new(span, format_args!("expected #[{}(...)] attribute", ident)))
 }
+
+/// The type of the elements of `ty` if it is a `Vec`.
+pub fn vec_element(ty: &Type) -> Option<&Type> {
+    let segment = match ty {
+        Type::Path(ty) => ty.path.segments.last()?,
+        _ => return None,
+    };
+
+    if segment.ident != "Vec" {
+        return None;
+    }
+
+    match &segment.arguments {
+        PathArguments::AngleBracketed(args) if args.args.len() == 1 => match &args.args[0] {
+            GenericArgument::Type(ty) => Some(ty),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// The discriminant of each variant, which is its position unless given with
+/// `#[discriminant(...)]`, so that variants can be reordered without changing
+/// how their labels are told apart.
+pub fn discriminants<P>(variants: &Punctuated<Variant, P>) -> Result<Vec<u32>> {
+    let mut discriminants = Vec::with_capacity(variants.len());
+    let mut seen = HashSet::with_capacity(variants.len());
+    for (i, variant) in variants.iter().enumerate() {
+        let discriminant = match optional_attribute::<LitInt>(&variant.attrs, "discriminant")? {
+            Some(discriminant) => discriminant.base10_parse::<u32>()?,
+            None => i as u32,
+        };
+
+        if !seen.insert(discriminant) {
+            let message = format!("duplicate discriminant {}", discriminant);
+            return Err(Error::new(variant.span(), message));
+        }
+
+        discriminants.push(discriminant);
+    }
+
+    Ok(discriminants)
+}
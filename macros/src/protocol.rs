@@ -0,0 +1,854 @@
+use crate::parse;
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote, ToTokens};
+use std::fmt::Display;
+use syn::{
+    braced, parenthesized,
+    parse::{Parse, ParseStream},
+    parse2, parse_quote,
+    punctuated::Punctuated,
+    token::Brace,
+    Attribute, Error, Ident, Result, Token, Type, Visibility,
+};
+
+mod kw {
+    syn::custom_keyword!(global);
+    syn::custom_keyword!(protocol);
+    syn::custom_keyword!(role);
+    syn::custom_keyword!(from);
+    syn::custom_keyword!(to);
+    syn::custom_keyword!(choice);
+    syn::custom_keyword!(at);
+    syn::custom_keyword!(or);
+    syn::custom_keyword!(rec);
+}
+
+/// A global protocol, written in the style of Scribble, whose routes may be
+/// given a type with `#[channel(...)]`. Its doc comments are given to the
+/// module it is emitted into.
+struct Protocol {
+    docs: Vec<Attribute>,
+    channel: Option<Type>,
+    vis: Visibility,
+    ident: Ident,
+    roles: Vec<Ident>,
+    body: Vec<Statement>,
+}
+
+enum Statement {
+    Message(Message),
+    Choice(Choice),
+    Rec(Rec),
+    Continue(Continue),
+}
+
+/// A message such as `Add(i32) from C to S;`, where each type of the payload
+/// may also be named, as in `Add(x: i32)`.
+struct Message {
+    label: Ident,
+    payload: Vec<Type>,
+    from: Ident,
+    to: Ident,
+    semi: Token![;],
+}
+
+/// A choice such as `choice at S { ... } or { ... }`.
+struct Choice {
+    choice: kw::choice,
+    role: Ident,
+    branches: Vec<Block>,
+}
+
+struct Block {
+    brace: Brace,
+    statements: Vec<Statement>,
+}
+
+struct Rec {
+    label: Ident,
+    body: Vec<Statement>,
+}
+
+struct Continue {
+    token: Token![continue],
+    label: Ident,
+    semi: Token![;],
+}
+
+impl Parse for Protocol {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let mut attrs = input.call(Attribute::parse_outer)?;
+        let channel = parse::optional_attribute(&attrs, "channel")?;
+        attrs.retain(|attr| !attr.path.is_ident("channel"));
+        if let Some(attr) = attrs.iter().find(|attr| !attr.path.is_ident("doc")) {
+            return Err(Error::new_spanned(
+                attr,
+                "expected #[channel(...)] or doc attribute",
+            ));
+        }
+
+        let vis = input.parse()?;
+        input.parse::<Option<kw::global>>()?;
+        input.parse::<kw::protocol>()?;
+        let ident = input.parse()?;
+
+        let content;
+        parenthesized!(content in input);
+        let roles = Punctuated::<_, Token![,]>::parse_terminated_with(&content, |input| {
+            input.parse::<kw::role>()?;
+            input.parse::<Ident>()
+        })?;
+
+        let content;
+        braced!(content in input);
+        Ok(Self {
+            docs: attrs,
+            channel,
+            vis,
+            ident,
+            roles: roles.into_iter().collect(),
+            body: statements(&content)?,
+        })
+    }
+}
+
+fn statements(input: ParseStream) -> Result<Vec<Statement>> {
+    let mut statements = Vec::new();
+    while !input.is_empty() {
+        statements.push(input.parse()?);
+    }
+
+    Ok(statements)
+}
+
+impl Parse for Statement {
+    fn parse(input: ParseStream) -> Result<Self> {
+        if input.peek(kw::choice) {
+            let choice = input.parse()?;
+            input.parse::<kw::at>()?;
+            let role = input.parse()?;
+
+            let mut branches = vec![input.parse()?];
+            while input.peek(kw::or) {
+                input.parse::<kw::or>()?;
+                branches.push(input.parse()?);
+            }
+
+            return Ok(Self::Choice(Choice {
+                choice,
+                role,
+                branches,
+            }));
+        }
+
+        if input.peek(kw::rec) {
+            input.parse::<kw::rec>()?;
+            let label = input.parse()?;
+            let content;
+            braced!(content in input);
+            let body = statements(&content)?;
+            return Ok(Self::Rec(Rec { label, body }));
+        }
+
+        if input.peek(Token![continue]) {
+            return Ok(Self::Continue(Continue {
+                token: input.parse()?,
+                label: input.parse()?,
+                semi: input.parse()?,
+            }));
+        }
+
+        let label = input.parse()?;
+        let content;
+        parenthesized!(content in input);
+        let payload = Punctuated::<_, Token![,]>::parse_terminated_with(&content, |input| {
+            if input.peek(Ident) && input.peek2(Token![:]) && !input.peek2(Token![::]) {
+                input.parse::<Ident>()?;
+                input.parse::<Token![:]>()?;
+            }
+
+            input.parse::<Type>()
+        })?;
+
+        input.parse::<kw::from>()?;
+        let from = input.parse()?;
+        input.parse::<kw::to>()?;
+        Ok(Self::Message(Message {
+            label,
+            payload: payload.into_iter().collect(),
+            from,
+            to: input.parse()?,
+            semi: input.parse()?,
+        }))
+    }
+}
+
+impl Parse for Block {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let content;
+        let brace = braced!(content in input);
+        Ok(Self {
+            brace,
+            statements: statements(&content)?,
+        })
+    }
+}
+
+impl Message {
+    fn error(&self, message: impl Display) -> Error {
+        let (label, semi) = (&self.label, &self.semi);
+        Error::new_spanned(quote!(#label #semi), message)
+    }
+}
+
+impl Choice {
+    fn error(&self, message: impl Display) -> Error {
+        let (choice, role) = (&self.choice, &self.role);
+        Error::new_spanned(quote!(#choice #role), message)
+    }
+}
+
+impl Statement {
+    /// An error which spans the whole statement, or just the head of a choice
+    /// or recursion.
+    fn error(&self, message: impl Display) -> Error {
+        match self {
+            Self::Message(statement) => statement.error(message),
+            Self::Choice(choice) => choice.error(message),
+            Self::Rec(rec) => Error::new_spanned(&rec.label, message),
+            Self::Continue(Continue { token, semi, .. }) => {
+                Error::new_spanned(quote!(#token #semi), message)
+            }
+        }
+    }
+}
+
+/// The items which are generated in the module of every protocol, besides its
+/// roles, labels and session types.
+const ITEMS: [&str; 4] = ["Channel", "Label", "Roles", "RolesBuilder"];
+
+/// Keywords which cannot be raw identifiers, so cannot name a field or module.
+const UNRAW: [&str; 3] = ["crate", "self", "super"];
+
+/// Checks that the protocol is well-formed, collecting the labels of its
+/// messages along the way, so that projection only has to reject choices which
+/// some role cannot follow.
+struct Checker<'a> {
+    roles: &'a [Ident],
+    recs: Vec<&'a Ident>,
+    labels: Vec<&'a Message>,
+}
+
+impl<'a> Checker<'a> {
+    /// Checks that the roles and labels can name the items generated for them
+    /// without clashing with each other or with the rest of the module.
+    fn names(&self, protocol: &Ident) -> Result<()> {
+        let module = snake(protocol);
+        if UNRAW.contains(&module.to_string().as_str()) {
+            let message = format!(
+                "protocol `{}` cannot name a module, since `{}` is a keyword",
+                protocol, module
+            );
+            return Err(Error::new_spanned(protocol, message));
+        }
+
+        let mut fields = Vec::<(Ident, &Ident)>::with_capacity(self.roles.len());
+        for role in self.roles {
+            if self.generated(protocol, role) {
+                let message = format!("role `{}` clashes with a generated item", role);
+                return Err(Error::new_spanned(role, message));
+            }
+
+            let field = snake(role);
+            if UNRAW.contains(&field.to_string().as_str()) {
+                let message = format!(
+                    "role `{}` cannot name a field, since `{}` is a keyword",
+                    role, field
+                );
+                return Err(Error::new_spanned(role, message));
+            }
+
+            if let Some((_, other)) = fields.iter().find(|(other, _)| *other == field) {
+                let message = format!(
+                    "role `{}` has the same field name as role `{}`",
+                    role, other
+                );
+                return Err(Error::new_spanned(role, message));
+            }
+
+            fields.push((field, role));
+        }
+
+        for message in &self.labels {
+            let label = &message.label;
+            if self.generated(protocol, label) {
+                let error = format!("label `{}` clashes with a generated item", label);
+                return Err(message.error(error));
+            }
+
+            if self.roles.contains(label) {
+                return Err(message.error(format_args!("label `{}` is also a role", label)));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether `ident` names one of the items generated in every module, or a
+    /// session type, such as `AuthC` or `AuthC2`.
+    fn generated(&self, protocol: &Ident, ident: &Ident) -> bool {
+        if ITEMS.iter().any(|item| ident == item) {
+            return true;
+        }
+
+        let name = ident.to_string();
+        self.roles.iter().any(|role| {
+            let session = name.strip_prefix(&format!("{}{}", protocol, role));
+            session.is_some_and(|count| count.chars().all(|c| c.is_ascii_digit()))
+        })
+    }
+
+    fn role(&self, role: &Ident) -> Result<()> {
+        match self.roles.contains(role) {
+            true => Ok(()),
+            false => Err(Error::new_spanned(role, "expected a role of the protocol")),
+        }
+    }
+
+    fn statements(&mut self, statements: &'a [Statement]) -> Result<()> {
+        for (i, statement) in statements.iter().enumerate() {
+            if let (Statement::Continue(_), Some(next)) = (statement, statements.get(i + 1)) {
+                return Err(next.error("statement is unreachable after `continue`"));
+            }
+
+            self.statement(statement)?;
+        }
+
+        Ok(())
+    }
+
+    fn statement(&mut self, statement: &'a Statement) -> Result<()> {
+        match statement {
+            Statement::Message(message) => self.message(message),
+            Statement::Choice(choice) => self.choice(choice),
+            Statement::Rec(rec) => {
+                self.recs.push(&rec.label);
+                self.statements(&rec.body)?;
+                self.recs.pop();
+                Ok(())
+            }
+            Statement::Continue(Continue { label, .. }) => match self.recs.contains(&label) {
+                true => Ok(()),
+                false => Err(statement.error(format_args!("no enclosing `rec {}`", label))),
+            },
+        }
+    }
+
+    fn message(&mut self, message: &'a Message) -> Result<()> {
+        self.role(&message.from)?;
+        self.role(&message.to)?;
+        if message.from == message.to {
+            return Err(message.error("a role cannot send a message to itself"));
+        }
+
+        let payload = |message: &Message| {
+            let payload = message.payload.iter();
+            payload
+                .map(|ty| ty.to_token_stream().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        match self
+            .labels
+            .iter()
+            .find(|other| other.label == message.label)
+        {
+            Some(other) if payload(other) != payload(message) => Err(message.error(format_args!(
+                "label `{}` is already used with a different payload",
+                message.label
+            ))),
+            Some(_) => Ok(()),
+            None => {
+                self.labels.push(message);
+                Ok(())
+            }
+        }
+    }
+
+    fn choice(&mut self, choice: &'a Choice) -> Result<()> {
+        self.role(&choice.role)?;
+
+        // Each branch must start with the chooser telling the same peer which
+        // branch it chose, since this is how that peer finds out.
+        let mut first = Vec::<&Message>::with_capacity(choice.branches.len());
+        for branch in &choice.branches {
+            let message = match branch.statements.first() {
+                Some(Statement::Message(message)) if message.from == choice.role => message,
+                Some(statement) => {
+                    let message = format!("expected a message from {}", choice.role);
+                    return Err(statement.error(message));
+                }
+                None => {
+                    let message = format!("expected a message from {}", choice.role);
+                    return Err(Error::new(branch.brace.span, message));
+                }
+            };
+
+            if let Some(other) = first.first() {
+                if message.to != other.to {
+                    let message = format!(
+                        "expected every branch to start with a message to {}",
+                        other.to
+                    );
+                    return Err(branch.statements[0].error(message));
+                }
+            }
+
+            if first.iter().any(|other| other.label == message.label) {
+                let message = format!("label `{}` starts another branch", message.label);
+                return Err(branch.statements[0].error(message));
+            }
+
+            first.push(message);
+            self.statements(&branch.statements)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A global type, where the statements which follow a choice or recursion are
+/// moved into each of its ends.
+#[derive(Clone)]
+enum Global<'a> {
+    Message(&'a Message, Box<Global<'a>>),
+    Choice(&'a Choice, Vec<Global<'a>>),
+    Rec(&'a Ident, Box<Global<'a>>),
+    Continue(&'a Ident),
+    End,
+}
+
+fn global<'a>(statements: &'a [Statement], end: Global<'a>) -> Global<'a> {
+    let (statement, statements) = match statements.split_first() {
+        Some(statements) => statements,
+        None => return end,
+    };
+
+    if let Statement::Continue(Continue { label, .. }) = statement {
+        return Global::Continue(label);
+    }
+
+    let next = global(statements, end);
+    match statement {
+        Statement::Message(message) => Global::Message(message, Box::new(next)),
+        Statement::Choice(choice) => {
+            let branches = choice.branches.iter();
+            let branches = branches.map(|branch| global(&branch.statements, next.clone()));
+            Global::Choice(choice, branches.collect())
+        }
+        Statement::Rec(rec) => Global::Rec(&rec.label, Box::new(global(&rec.body, next))),
+        Statement::Continue(_) => unreachable!(),
+    }
+}
+
+/// A local type, being the view of the protocol from a single role.
+#[derive(Clone, PartialEq)]
+enum Local<'a> {
+    Send(&'a Ident, &'a Ident, Box<Local<'a>>),
+    Receive(&'a Ident, &'a Ident, Box<Local<'a>>),
+    Select(&'a Ident, Vec<(&'a Ident, Local<'a>)>),
+    Branch(&'a Ident, Vec<(&'a Ident, Local<'a>)>),
+    Rec(&'a Ident, Box<Local<'a>>),
+    Var(&'a Ident),
+    End,
+}
+
+impl<'a> Local<'a> {
+    fn uses(&self, var: &Ident) -> bool {
+        match self {
+            Self::Send(.., next) | Self::Receive(.., next) => next.uses(var),
+            Self::Select(_, choices) | Self::Branch(_, choices) => {
+                choices.iter().any(|(_, next)| next.uses(var))
+            }
+            Self::Rec(label, body) => *label != var && body.uses(var),
+            Self::Var(label) => *label == var,
+            Self::End => false,
+        }
+    }
+
+    /// Whether every use of `var` is within a choice, whose enum can then be
+    /// what makes the session type recursive. Other recursive session types
+    /// must be structs instead of aliases.
+    fn guarded(&self, var: &Ident) -> bool {
+        match self {
+            Self::Send(.., next) | Self::Receive(.., next) => next.guarded(var),
+            Self::Select(..) | Self::Branch(..) => true,
+            Self::Rec(label, body) => *label == var || body.guarded(var),
+            Self::Var(label) => *label != var,
+            Self::End => true,
+        }
+    }
+
+    /// The labels which the role may receive next from a single peer, along
+    /// with the continuation after each.
+    fn choices(self) -> Option<(&'a Ident, Vec<(&'a Ident, Local<'a>)>)> {
+        match self {
+            Self::Receive(peer, label, next) => Some((peer, vec![(label, *next)])),
+            Self::Branch(peer, choices) => Some((peer, choices)),
+            _ => None,
+        }
+    }
+
+    /// Merges the projections of two branches of a choice which the role did
+    /// not make, which is only possible if the role either behaves the same in
+    /// both or is told which was chosen by the label it receives.
+    fn merge(self, other: Self) -> Option<Self> {
+        if self == other {
+            return Some(self);
+        }
+
+        if let (Self::Rec(label, body), Self::Rec(other_label, other_body)) = (&self, &other) {
+            if label == other_label {
+                let body = (**body).clone().merge((**other_body).clone())?;
+                return Some(Self::Rec(label, Box::new(body)));
+            }
+        }
+
+        let (peer, mut choices) = self.choices()?;
+        let (other_peer, other_choices) = other.choices()?;
+        if peer != other_peer {
+            return None;
+        }
+
+        for (label, next) in other_choices {
+            match choices.iter_mut().find(|(other, _)| *other == label) {
+                Some((_, other)) => *other = other.clone().merge(next)?,
+                None => choices.push((label, next)),
+            }
+        }
+
+        Some(match choices.len() {
+            1 => {
+                let (label, next) = choices.pop().unwrap();
+                Self::Receive(peer, label, Box::new(next))
+            }
+            _ => Self::Branch(peer, choices),
+        })
+    }
+}
+
+fn project<'a>(global: &Global<'a>, role: &Ident) -> Result<Local<'a>> {
+    Ok(match global {
+        Global::Message(message, next) => {
+            let next = Box::new(project(next, role)?);
+            if message.from == *role {
+                Local::Send(&message.to, &message.label, next)
+            } else if message.to == *role {
+                Local::Receive(&message.from, &message.label, next)
+            } else {
+                *next
+            }
+        }
+        Global::Choice(choice, branches) => {
+            let branches = branches.iter().map(|branch| project(branch, role));
+            let branches = branches.collect::<Result<Vec<_>>>()?;
+
+            if choice.role == *role {
+                let mut peer = None;
+                let mut choices = Vec::with_capacity(branches.len());
+                for branch in branches {
+                    match branch {
+                        Local::Send(to, label, next) => {
+                            peer = Some(to);
+                            choices.push((label, *next));
+                        }
+                        _ => unreachable!(),
+                    }
+                }
+
+                return Ok(Local::Select(peer.unwrap(), choices));
+            }
+
+            let mut branches = branches.into_iter();
+            let first = branches.next().unwrap();
+            branches.try_fold(first, |merged, branch| {
+                merged.merge(branch).ok_or_else(|| {
+                    choice.error(format_args!(
+                        "cannot project onto {}, which acts differently in each branch \
+                         without being told which was chosen",
+                        role
+                    ))
+                })
+            })?
+        }
+        Global::Rec(label, body) => match project(body, role)? {
+            Local::Var(var) if var == *label => Local::End,
+            body if !body.uses(label) => body,
+            body => Local::Rec(label, Box::new(body)),
+        },
+        Global::Continue(label) => Local::Var(label),
+        Global::End => Local::End,
+    })
+}
+
+/// Defines the session types of a single role, where the session type of the
+/// whole protocol is named after the protocol and the role, and every other
+/// session type is numbered after it.
+struct Definitions<'a> {
+    vis: &'a Visibility,
+    name: Ident,
+    count: usize,
+    vars: Vec<(&'a Ident, Ident)>,
+    output: TokenStream,
+}
+
+impl<'a> Definitions<'a> {
+    fn next_name(&mut self) -> Ident {
+        self.count += 1;
+        format_ident!("{}{}", self.name, self.count)
+    }
+
+    fn define(&mut self, local: &Local<'a>) {
+        let name = self.name.clone();
+        if let Local::Rec(label, body) = local {
+            self.rec(name, label, body);
+            return;
+        }
+
+        let ty = self.ty(local);
+        let vis = self.vis;
+        self.output.extend(quote! {
+            #[::rumpsteak::session]
+            #vis type #name = #ty;
+        });
+    }
+
+    fn rec(&mut self, name: Ident, label: &'a Ident, body: &Local<'a>) {
+        self.vars.push((label, name.clone()));
+        let ty = self.ty(body);
+        self.vars.pop();
+
+        let vis = self.vis;
+        self.output.extend(match body.guarded(label) {
+            true => quote! {
+                #[::rumpsteak::session]
+                #vis type #name = #ty;
+            },
+            false => quote! {
+                #[::rumpsteak::session]
+                #vis struct #name(#vis #ty);
+            },
+        });
+    }
+
+    fn choices(&mut self, choices: &[(&'a Ident, Local<'a>)], select: bool) -> Ident {
+        let name = self.next_name();
+        let variants = choices.iter().map(|(label, next)| {
+            let next = self.ty(next);
+            quote!(#label(#label, #next))
+        });
+        let variants = variants.collect::<Vec<_>>();
+
+        // The variants of a choice which is selected are never constructed.
+        let allow = select.then(|| quote!(#[allow(dead_code)]));
+        let vis = self.vis;
+        self.output.extend(quote! {
+            #[::rumpsteak::session]
+            #allow
+            #vis enum #name {
+                #(#variants),*
+            }
+        });
+
+        name
+    }
+
+    fn ty(&mut self, local: &Local<'a>) -> TokenStream {
+        match local {
+            Local::Send(peer, label, next) => {
+                let next = self.ty(next);
+                quote!(::rumpsteak::Send<#peer, #label, #next>)
+            }
+            Local::Receive(peer, label, next) => {
+                let next = self.ty(next);
+                quote!(::rumpsteak::Receive<#peer, #label, #next>)
+            }
+            Local::Select(peer, choices) => {
+                let name = self.choices(choices, true);
+                quote!(::rumpsteak::Select<#peer, #name>)
+            }
+            Local::Branch(peer, choices) => {
+                let name = self.choices(choices, false);
+                quote!(::rumpsteak::Branch<#peer, #name>)
+            }
+            Local::Rec(label, body) => {
+                let name = self.next_name();
+                self.rec(name.clone(), label, body);
+                name.into_token_stream()
+            }
+            Local::Var(label) => {
+                let mut vars = self.vars.iter().rev();
+                let (_, name) = vars.find(|(var, _)| var == label).unwrap();
+                name.to_token_stream()
+            }
+            Local::End => quote!(::rumpsteak::End),
+        }
+    }
+}
+
+/// The name of the field for a role, such as `buyer_a` for `BuyerA`, or of the
+/// module for a protocol, which is a raw identifier if it is a keyword, such as
+/// `r#loop` for `Loop`.
+fn snake(ident: &Ident) -> Ident {
+    let name = ident.to_string();
+    let chars = name.chars().collect::<Vec<_>>();
+    let mut output = String::with_capacity(name.len());
+    for (i, &c) in chars.iter().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            let previous = chars[i - 1];
+            let next = chars.get(i + 1).copied();
+            if previous.is_lowercase()
+                || previous.is_numeric()
+                || (previous.is_uppercase() && next.is_some_and(char::is_lowercase))
+            {
+                output.push('_');
+            }
+        }
+
+        output.extend(c.to_lowercase());
+    }
+
+    match KEYWORDS.contains(&output.as_str()) {
+        true => Ident::new_raw(&output, ident.span()),
+        false => Ident::new(&output, ident.span()),
+    }
+}
+
+/// The keywords of every edition, including those reserved for later use,
+/// which can only name a field or module as raw identifiers.
+const KEYWORDS: [&str; 48] = [
+    "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "do", "dyn",
+    "else", "enum", "extern", "false", "final", "fn", "for", "gen", "if", "impl", "in", "let",
+    "loop", "macro", "match", "mod", "move", "mut", "override", "priv", "pub", "ref", "return",
+    "static", "struct", "trait", "true", "try", "type", "typeof", "unsafe", "unsized", "use",
+    "virtual", "where", "while", "yield",
+];
+
+pub fn protocol(input: TokenStream) -> Result<TokenStream> {
+    let protocol = parse2::<Protocol>(input)?;
+    let Protocol {
+        docs,
+        channel,
+        vis,
+        ident,
+        roles,
+        body,
+    } = &protocol;
+
+    if roles.is_empty() {
+        return Err(Error::new_spanned(ident, "expected at least one role"));
+    }
+
+    for (i, role) in roles.iter().enumerate() {
+        if roles[..i].contains(role) {
+            return Err(Error::new_spanned(role, "duplicate role"));
+        }
+    }
+
+    let mut checker = Checker {
+        roles,
+        recs: Vec::new(),
+        labels: Vec::new(),
+    };
+    checker.statements(body)?;
+    checker.names(ident)?;
+
+    // Every item is public within the module, which has the visibility of the
+    // protocol.
+    let public: Visibility = parse_quote!(pub);
+    let global = global(body, Global::End);
+    let mut sessions = TokenStream::new();
+    for role in roles {
+        let local = project(&global, role)?;
+        let mut definitions = Definitions {
+            vis: &public,
+            name: format_ident!("{}{}", ident, role),
+            count: 0,
+            vars: Vec::new(),
+            output: TokenStream::new(),
+        };
+
+        definitions.define(&local);
+        sessions.extend(definitions.output);
+    }
+
+    let fields = roles.iter().map(snake).collect::<Vec<_>>();
+    let definitions = roles.iter().map(|role| {
+        let peers = roles.iter().zip(&fields).filter(|(peer, _)| *peer != role);
+        let routes = peers.map(|(peer, field)| quote!(#[route(#peer)] pub #field: Channel));
+        quote! {
+            #[derive(::rumpsteak::Role)]
+            #[message(Label)]
+            pub struct #role {
+                #(#routes),*
+            }
+        }
+    });
+
+    let labels = checker.labels.iter().map(|message| &message.label);
+    let labels = labels.collect::<Vec<_>>();
+    let structs = checker.labels.iter().map(|message| {
+        let (label, payload) = (&message.label, &message.payload);
+        match payload.is_empty() {
+            true => quote!(pub struct #label;),
+            false => quote!(pub struct #label(#(pub #payload),*);),
+        }
+    });
+
+    // Routes are unbounded channels unless given otherwise, in which case they
+    // may not be pairable, so the roles can only be built with their routes.
+    let mpsc = quote!(::rumpsteak::__private::futures::channel::mpsc);
+    let (channel, builder_only) = match channel {
+        Some(channel) => (
+            channel.to_token_stream(),
+            Some(quote!(#[roles(builder_only)])),
+        ),
+        None => (
+            quote! {
+                ::rumpsteak::channel::Bidirectional<
+                    #mpsc::UnboundedSender<Label>,
+                    #mpsc::UnboundedReceiver<Label>,
+                >
+            },
+            None,
+        ),
+    };
+
+    let module = snake(ident);
+    Ok(quote! {
+        #(#docs)*
+        #vis mod #module {
+            use super::*;
+
+            pub type Channel = #channel;
+
+            #[derive(::rumpsteak::Roles)]
+            #builder_only
+            #[allow(dead_code)]
+            pub struct Roles {
+                #(pub #fields: #roles),*
+            }
+
+            #(#definitions)*
+
+            #[derive(::rumpsteak::Message)]
+            pub enum Label {
+                #(#labels(#labels)),*
+            }
+
+            #(#structs)*
+
+            #sessions
+        }
+    })
+}
@@ -1,7 +1,39 @@
 use crate::parse;
 use proc_macro2::TokenStream;
-use quote::{quote, ToTokens};
-use syn::{parse2, spanned::Spanned, Data, DeriveInput, Error, Index, Result, Type};
+use quote::{format_ident, quote, ToTokens};
+use syn::{
+    parse::Nothing, parse2, parse_quote, spanned::Spanned, Data, DeriveInput, Error, Field, Fields,
+    Index, Result, Type,
+};
+
+enum Kind {
+    Route(Type),
+    Family(Type),
+    Index,
+}
+
+fn kind(field: &Field) -> Result<Kind> {
+    let route = parse::optional_attribute::<Type>(&field.attrs, "route")?;
+    let family = parse::optional_attribute::<Type>(&field.attrs, "family")?;
+    let index = field.attrs.iter().find(|attr| attr.path.is_ident("index"));
+
+    match (route, family, index) {
+        (Some(route), None, None) => Ok(Kind::Route(route)),
+        (None, Some(family), None) => Ok(Kind::Family(family)),
+        (None, None, Some(index)) => {
+            let Nothing = parse2(index.tokens.clone())?;
+            Ok(Kind::Index)
+        }
+        (None, None, None) => {
+            let message = "expected #[route(...)], #[family(...)] or #[index] attribute";
+            Err(Error::new(field.span(), message))
+        }
+        _ => {
+            let message = "expected only one of #[route(...)], #[family(...)] or #[index]";
+            Err(Error::new(field.span(), message))
+        }
+    }
+}
 
 pub fn role(input: TokenStream) -> Result<TokenStream> {
     let input = parse2::<DeriveInput>(input)?;
@@ -11,36 +43,144 @@ pub fn role(input: TokenStream) -> Result<TokenStream> {
     let ident = &input.ident;
     let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
 
-    let mut output = quote! {
-        impl ::rumpsteak::Role for #ident {
-            type Message = #message;
-        }
-    };
-
     let fields = match &input.data {
         Data::Struct(input) => Ok(&input.fields),
         _ => Err(Error::new_spanned(&input, "expected a struct")),
     }?;
 
+    let field_names = matches!(fields, Fields::Named(_));
+    let mut kinds = Vec::with_capacity(fields.len());
+    let mut index = None;
     for (i, field) in fields.iter().enumerate() {
-        let route = parse::attribute::<Type>(&field.attrs, "route", field.span())?;
-
-        let field_ty = &field.ty;
         let field_ident = match &field.ident {
             Some(ident) => ident.to_token_stream(),
             None => Index::from(i).to_token_stream(),
         };
 
+        let kind = kind(field)?;
+        if let Kind::Index = kind {
+            if index.is_some() {
+                return Err(Error::new(field.span(), "duplicate #[index] attribute"));
+            }
+
+            index = Some(field_ident.clone());
+        }
+
+        kinds.push((field, field_ident, kind));
+    }
+
+    let cancels = kinds
+        .iter()
+        .filter_map(|(_, field_ident, kind)| match kind {
+            Kind::Route(_) => Some(quote! {
+                ::rumpsteak::channel::Cancel::cancel(&mut self.#field_ident);
+            }),
+            Kind::Family(_) => Some(quote! {
+                for route in &mut self.#field_ident {
+                    ::rumpsteak::channel::Cancel::cancel(route);
+                }
+            }),
+            Kind::Index => None,
+        });
+
+    let mut output = quote! {
+        impl #impl_generics ::rumpsteak::Role for #ident #ty_generics #where_clause {
+            type Message = #message;
+
+            fn cancel(&mut self) {
+                #(#cancels)*
+            }
+        }
+    };
+
+    if let Some(index) = &index {
+        // The routes are given in the order of their fields, or by name for a
+        // struct with named fields, so that `#[derive(Roles)]` can create the
+        // member without knowing the name of its index field.
+        let mut routes = kinds
+            .iter()
+            .filter(|(_, _, kind)| !matches!(kind, Kind::Index))
+            .map(|(field, field_ident, _)| (field_ident, &field.ty))
+            .collect::<Vec<_>>();
+        if field_names {
+            routes.sort_by_key(|(field_ident, _)| field_ident.to_string());
+        }
+
+        let (route_idents, route_tys) = routes.into_iter().unzip::<_, _, Vec<_>, Vec<_>>();
+        let bindings = (0..route_idents.len()).map(|i| format_ident!("route_{}", i));
+        let bindings = bindings.collect::<Vec<_>>();
         output.extend(quote! {
-            impl #impl_generics ::rumpsteak::Route<#route> for #ident #ty_generics #where_clause {
-                type Route = #field_ty;
+            impl #impl_generics ::rumpsteak::Indexed for #ident #ty_generics #where_clause {
+                fn index(&self) -> usize {
+                    self.#index
+                }
+            }
 
-                fn route(&mut self) -> &mut Self::Route {
-                    &mut self.#field_ident
+            impl #impl_generics ::rumpsteak::Member for #ident #ty_generics #where_clause {
+                type Routes = (#(#route_tys,)*);
+
+                fn member(index: usize, (#(#bindings,)*): Self::Routes) -> Self {
+                    Self {
+                        #(#route_idents: #bindings,)*
+                        #index: index,
+                    }
                 }
             }
         });
     }
 
+    for (field, field_ident, kind) in &kinds {
+        let field_ty = &field.ty;
+        match kind {
+            Kind::Route(route) => output.extend(quote! {
+                impl #impl_generics ::rumpsteak::Route<#route> for #ident #ty_generics #where_clause {
+                    type Route = #field_ty;
+
+                    fn route(&mut self) -> &mut Self::Route {
+                        &mut self.#field_ident
+                    }
+                }
+            }),
+            Kind::Family(family) => {
+                let route_ty = parse::vec_element(field_ty)
+                    .ok_or_else(|| Error::new_spanned(field_ty, "expected a Vec of routes"))?;
+
+                // A member of its own family has no route to itself, so the
+                // routes skip over its own index.
+                let is_member = *family == parse_quote!(#ident) || *family == parse_quote!(Self);
+                let (size, offset) = match (is_member, &index) {
+                    (true, Some(index)) => (
+                        quote!(self.#field_ident.len() + 1),
+                        quote! {
+                            assert_ne!(index, self.#index, "cannot route to self");
+                            let index = if index < self.#index { index } else { index - 1 };
+                        },
+                    ),
+                    (true, None) => {
+                        let message = "expected #[index] field for a member of its own family";
+                        return Err(Error::new_spanned(family, message));
+                    }
+                    (false, _) => (quote!(self.#field_ident.len()), quote!()),
+                };
+
+                output.extend(quote! {
+                    impl #impl_generics ::rumpsteak::Routes<#family> for #ident #ty_generics #where_clause {
+                        type Route = #route_ty;
+
+                        fn size(&self) -> usize {
+                            #size
+                        }
+
+                        fn route_to(&mut self, index: usize) -> &mut Self::Route {
+                            #offset
+                            &mut self.#field_ident[index]
+                        }
+                    }
+                });
+            }
+            Kind::Index => {}
+        }
+    }
+
     Ok(output)
 }
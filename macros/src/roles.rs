@@ -1,6 +1,18 @@
+use crate::parse;
 use proc_macro2::TokenStream;
 use quote::{format_ident, quote, ToTokens};
-use syn::{parse2, Data, DeriveInput, Error, Index, Result};
+use syn::{
+    parse2, parse_quote, Data, DeriveInput, Error, Fields, GenericParam, Generics, Ident, Index,
+    PathArguments, Result, Token, Type,
+};
+
+/// A field of the roles struct, which is either a single role or a family of
+/// roles whose size is given at runtime.
+struct Role<'a> {
+    ident: TokenStream,
+    ty: &'a Type,
+    size: Option<Ident>,
+}
 
 pub fn roles(input: TokenStream) -> Result<TokenStream> {
     let input = parse2::<DeriveInput>(input)?;
@@ -13,41 +25,412 @@ pub fn roles(input: TokenStream) -> Result<TokenStream> {
         _ => Err(Error::new_spanned(&input, "expected a struct")),
     }?;
 
-    let pairs = (0..fields.len()).flat_map(|i| {
-        (i + 1..fields.len()).map(move |j| {
-            let left = format_ident!("role_{}_{}", i, j);
-            let right = format_ident!("role_{}_{}", j, i);
-            quote! { let (#left, #right) = ::rumpsteak::channel::Pair::pair(); }
-        })
-    });
+    let builder_only = match parse::optional_attribute::<Ident>(&input.attrs, "roles")? {
+        Some(arg) if arg == "builder_only" => true,
+        Some(arg) => return Err(Error::new_spanned(arg, "expected #[roles(builder_only)]")),
+        None => false,
+    };
 
     let roles = fields.iter().enumerate().map(|(i, field)| {
-        let fields = fields.iter().enumerate().filter(|(j, _)| i != *j);
-        let role = fields.enumerate().map(|(index, (j, field))| {
-            let field_ident = match &field.ident {
-                Some(ident) => ident.to_token_stream(),
-                None => Index::from(index).to_token_stream(),
+        let ident = match &field.ident {
+            Some(ident) => ident.to_token_stream(),
+            None => Index::from(i).to_token_stream(),
+        };
+
+        match parse::vec_element(&field.ty) {
+            Some(ty) => {
+                let size = match &field.ident {
+                    Some(ident) => format_ident!("{}", ident),
+                    None => format_ident!("size_{}", i),
+                };
+
+                Role {
+                    ident,
+                    ty,
+                    size: Some(size),
+                }
+            }
+            None => Role {
+                ident,
+                ty: &field.ty,
+                size: None,
+            },
+        }
+    });
+    let roles = roles.collect::<Vec<_>>();
+
+    // Each pair of roles is linked, as is each family with itself, since its
+    // members have routes to each other.
+    let mut links = Vec::new();
+    for (i, role) in roles.iter().enumerate() {
+        if role.size.is_some() {
+            links.push((i, i));
+        }
+
+        for j in i + 1..roles.len() {
+            links.push((i, j));
+        }
+    }
+
+    let named = matches!(fields, Fields::Named(_));
+    let mut output = builder(&input, &roles, &links, named);
+    if builder_only {
+        return Ok(output);
+    }
+
+    // Every link must be pairable for the roles to be created without the
+    // builder, which is checked here rather than where they are created.
+    let mut where_clause = where_clause.cloned().unwrap_or_else(|| parse_quote!(where));
+    for &(i, j) in &links {
+        let (left, right) = (route(&roles, i, j), route(&roles, j, i));
+        where_clause.predicates.push(parse_quote! {
+            ::rumpsteak::channel::Unlinked: ::rumpsteak::channel::Link<#left, #right>
+        });
+    }
+
+    let sizes = roles.iter().filter_map(|role| role.size.as_ref());
+    let sizes = sizes.collect::<Vec<_>>();
+    output.extend(match sizes.is_empty() {
+        true => quote! {
+            impl #impl_generics ::core::default::Default for #ident #ty_generics #where_clause {
+                fn default() -> Self {
+                    // Without a family, every route is paired, so none can
+                    // fail to link.
+                    match Self::builder().build() {
+                        ::core::result::Result::Ok(roles) => roles,
+                        ::core::result::Result::Err(error) => ::core::unreachable!("{}", error),
+                    }
+                }
+            }
+        },
+        false => quote! {
+            impl #impl_generics #ident #ty_generics #where_clause {
+                /// Creates every role, where each family is given its size.
+                /// Fails if a family has fewer than two members, since a lone
+                /// member would route to itself as its own neighbour.
+                pub fn new(
+                    #(#sizes: usize),*
+                ) -> ::core::result::Result<Self, ::rumpsteak::channel::LinkError> {
+                    Self::builder(#(#sizes),*).build()
+                }
+            }
+        },
+    });
+
+    Ok(output)
+}
+
+/// Generates a builder where the routes of each link can be given by a
+/// factory, while the rest are paired.
+fn builder(
+    input: &DeriveInput,
+    roles: &[Role<'_>],
+    links: &[(usize, usize)],
+    named: bool,
+) -> TokenStream {
+    let ident = &input.ident;
+    let vis = &input.vis;
+    let builder = format_ident!("{}Builder", ident);
+
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let args = generic_args(&input.generics);
+
+    let params = (0..links.len())
+        .map(|k| format_ident!("T{}", k))
+        .collect::<Vec<_>>();
+    let fields = (0..links.len())
+        .map(|k| format_ident!("link_{}", k))
+        .collect::<Vec<_>>();
+    let sizes = roles.iter().filter_map(|role| role.size.as_ref());
+    let sizes = sizes.collect::<Vec<_>>();
+
+    let mut builder_generics = input.generics.clone();
+    builder_generics.params.extend(
+        params
+            .iter()
+            .map(|param| -> GenericParam { parse_quote!(#param) }),
+    );
+    let (builder_impl_generics, builder_ty_generics, _) = builder_generics.split_for_impl();
+
+    let unlinked = links.iter().map(|_| quote!(::rumpsteak::channel::Unlinked));
+    let bounds = links.iter().zip(&params).map(|(&(i, j), param)| {
+        let (left, right) = (route(roles, i, j), route(roles, j, i));
+        quote!(#param: ::rumpsteak::channel::Link<#left, #right>)
+    });
+
+    let pairs = links.iter().zip(&fields).map(|(&(i, j), field)| {
+        let left = format_ident!("role_{}_{}", i, j);
+        let right = format_ident!("role_{}_{}", j, i);
+        if i == j {
+            let size = &roles[i].size;
+            return quote! {
+                let #left = ::rumpsteak::channel::link_within(#size, &mut #field)?;
+                let mut #left = #left.into_iter();
             };
+        }
+
+        match (&roles[i].size, &roles[j].size) {
+            (None, None) => quote! {
+                let (#left, #right) = ::rumpsteak::channel::Link::link(&mut #field)?;
+            },
+            (None, Some(size)) => quote! {
+                let (#left, #right) = ::rumpsteak::channel::link_family(#size, &mut #field)?;
+                let mut #right = #right.into_iter();
+            },
+            (Some(size), None) => quote! {
+                let (#left, #right) = ::rumpsteak::channel::link_family(#size, &mut #field)?;
+                let mut #left = #left.into_iter();
+            },
+            (Some(left_size), Some(right_size)) => quote! {
+                let (#left, #right) =
+                    ::rumpsteak::channel::link_families(#left_size, #right_size, &mut #field)?;
+                let (mut #left, mut #right) = (#left.into_iter(), #right.into_iter());
+            },
+        }
+    });
 
-            let ident = format_ident!("role_{}_{}", i, j);
-            quote! { #field_ident: #ident }
+    let values = values(roles, named);
+
+    // The routes of a pair can be linked with the roles in either order, but
+    // only once, since its place in the builder must still be unlinked.
+    let connects = links.iter().enumerate().map(|(k, &(i, j))| {
+        let (left, right) = (roles[i].ty, roles[j].ty);
+        let (left_route, right_route) = (route(roles, i, j), route(roles, j, i));
+
+        let mut generics = input.generics.clone();
+        let others = params.iter().enumerate().filter(|&(l, _)| l != k);
+        let others = others.map(|(_, param)| -> GenericParam { parse_quote!(#param) });
+        generics.params.extend(others);
+        let (impl_generics, _, where_clause) = generics.split_for_impl();
+
+        let link = |l: usize, linked: TokenStream| match l == k {
+            true => linked,
+            false => params[l].to_token_stream(),
+        };
+        let input = (0..links.len()).map(|l| link(l, quote!(::rumpsteak::channel::Unlinked)));
+        let input = quote!(#builder<#(#args,)* #(#input),*>);
+        let output = (0..links.len()).map(|l| {
+            let linked = quote!(::rumpsteak::channel::Linked<#left_route, #right_route>);
+            link(l, linked)
         });
+        let output = quote!(#builder<#(#args,)* #(#output),*>);
 
-        let ident = match &field.ident {
-            Some(ident) => ident.to_token_stream(),
-            None => Index::from(i).to_token_stream(),
+        // Each pair of roles between the two is linked by its own call to the
+        // factory.
+        let count = match (i == j, &roles[i].size, &roles[j].size) {
+            (true, size, _) => quote!(#size * #size.saturating_sub(1) / 2),
+            (false, None, None) => quote!(1),
+            (false, Some(size), None) | (false, None, Some(size)) => quote!(#size),
+            (false, Some(left_size), Some(right_size)) => quote!(#left_size * #right_size),
+        };
+
+        let field = &fields[k];
+        let others = fields.iter().filter(|&other| other != field);
+        let others = others.collect::<Vec<_>>();
+        let value = |link: TokenStream| {
+            quote! {
+                let #builder { #(#others,)* #(#sizes,)* .. } = self;
+                let #field = #link;
+                #builder {
+                    #(#fields,)*
+                    #(#sizes,)*
+                    phantom: ::core::marker::PhantomData,
+                }
+            }
         };
 
-        let ty = &field.ty;
-        quote! { #ident: #ty { #(#role),* } }
+        let linked = value(quote! {
+            ::rumpsteak::channel::Linked::new(#count, factory)
+        });
+        let mut connect = quote! {
+            impl #impl_generics ::rumpsteak::channel::Connect<#left, #right> for #input #where_clause {
+                type Left = #left_route;
+                type Right = #right_route;
+                type Output = #output;
+
+                fn connect(
+                    self,
+                    factory: impl ::core::ops::FnMut() -> (Self::Left, Self::Right),
+                ) -> Self::Output {
+                    #linked
+                }
+            }
+        };
+
+        if i != j {
+            let swapped = value(quote! {
+                ::rumpsteak::channel::Linked::new(#count, move || {
+                    let (right, left) = factory();
+                    (left, right)
+                })
+            });
+            connect.extend(quote! {
+                impl #impl_generics ::rumpsteak::channel::Connect<#right, #left> for #input #where_clause {
+                    type Left = #right_route;
+                    type Right = #left_route;
+                    type Output = #output;
+
+                    fn connect(
+                        self,
+                        mut factory: impl ::core::ops::FnMut() -> (Self::Left, Self::Right),
+                    ) -> Self::Output {
+                        #swapped
+                    }
+                }
+            });
+        }
+
+        connect
     });
 
-    Ok(quote! {
-        impl #impl_generics ::core::default::Default for #ident #ty_generics #where_clause {
-            fn default() -> Self {
+    let doc = format!(
+        "Builds `{}` with the routes between some roles given.",
+        ident
+    );
+    quote! {
+        #[doc = #doc]
+        #vis struct #builder #builder_impl_generics #where_clause {
+            #(#fields: #params,)*
+            #(#sizes: usize,)*
+            phantom: ::core::marker::PhantomData<fn() -> #ident #ty_generics>,
+        }
+
+        impl #impl_generics #ident #ty_generics #where_clause {
+            /// Starts building every role, where each family is given its size
+            /// and the routes between any pair of roles can be given by a
+            /// factory.
+            #vis fn builder(#(#sizes: usize),*) -> #builder<#(#args,)* #(#unlinked),*> {
+                #builder {
+                    #(#fields: ::rumpsteak::channel::Unlinked,)*
+                    #(#sizes,)*
+                    phantom: ::core::marker::PhantomData,
+                }
+            }
+        }
+
+        impl #builder_impl_generics #builder #builder_ty_generics #where_clause {
+            /// Creates the routes between `Q` and `R` with `factory`, instead of
+            /// pairing them. The factory is called once for each pair of roles,
+            /// so once for each member of a family.
+            pub fn link<Q, R>(
+                self,
+                factory: impl ::core::ops::FnMut() -> (
+                    <Self as ::rumpsteak::channel::Connect<Q, R>>::Left,
+                    <Self as ::rumpsteak::channel::Connect<Q, R>>::Right,
+                ),
+            ) -> <Self as ::rumpsteak::channel::Connect<Q, R>>::Output
+            where
+                Self: ::rumpsteak::channel::Connect<Q, R>,
+            {
+                ::rumpsteak::channel::Connect::connect(self, factory)
+            }
+
+            /// Creates every role, pairing the routes between any roles which
+            /// were not linked. Fails if a family has fewer than two members,
+            /// or if a link runs out of routes.
+            pub fn build(
+                self,
+            ) -> ::core::result::Result<#ident #ty_generics, ::rumpsteak::channel::LinkError>
+            where
+                #(#bounds),*
+            {
+                let #builder { #(mut #fields,)* #(#sizes,)* .. } = self;
                 #(#pairs)*
-                Self { #(#roles),* }
+                ::core::result::Result::Ok(#ident { #(#values),* })
+            }
+        }
+
+        #(#connects)*
+    }
+}
+
+/// Creates each role from the routes to its peers, where the route from role
+/// `i` to role `j` is named `role_i_j`, and is an iterator over the routes of
+/// each member if role `i` is a family.
+fn values(roles: &[Role<'_>], named: bool) -> Vec<TokenStream> {
+    let values = roles.iter().enumerate().map(|(i, role)| {
+        // Members of a family have routes to the rest of their own family, but
+        // a single role has no route to itself.
+        let peers = roles.iter().enumerate();
+        let peers = peers.filter(|(j, _)| i != *j || role.size.is_some());
+        let mut fields = peers
+            .enumerate()
+            .map(|(index, (j, peer))| {
+                let field_ident = match named {
+                    true => peer.ident.clone(),
+                    false => Index::from(index).to_token_stream(),
+                };
+
+                let ident = format_ident!("role_{}_{}", i, j);
+                match &role.size {
+                    Some(_) => (field_ident, quote!(#ident.next().unwrap())),
+                    None => (field_ident, quote!(#ident)),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let ident = &role.ident;
+        let ty = role.ty;
+        match &role.size {
+            Some(size) => {
+                // Members are created through `Member`, which takes their
+                // routes ordered by field name, or by position otherwise.
+                if named {
+                    fields.sort_by_key(|(field_ident, _)| field_ident.to_string());
+                }
+
+                let routes = fields.iter().map(|(_, value)| value);
+                quote! {
+                    #ident: (0..#size)
+                        .map(|index| <#ty as ::rumpsteak::Member>::member(index, (#(#routes,)*)))
+                        .collect()
+                }
+            }
+            None => {
+                let fields = fields
+                    .iter()
+                    .map(|(field_ident, value)| quote!(#field_ident: #value));
+                let path = expr_path(ty);
+                quote! { #ident: #path { #(#fields),* } }
             }
         }
-    })
+    });
+
+    values.collect()
+}
+
+/// The type of the route from role `i` to role `j`, or to a single member of
+/// `j` if it is a family.
+fn route(roles: &[Role<'_>], i: usize, j: usize) -> TokenStream {
+    let (left, right) = (roles[i].ty, roles[j].ty);
+    match roles[j].size {
+        Some(_) => quote!(<#left as ::rumpsteak::Routes<#right>>::Route),
+        None => quote!(<#left as ::rumpsteak::Route<#right>>::Route),
+    }
+}
+
+/// The arguments which name the generic parameters of the roles struct.
+fn generic_args(generics: &Generics) -> Vec<TokenStream> {
+    let args = generics.params.iter().map(|param| match param {
+        GenericParam::Type(param) => param.ident.to_token_stream(),
+        GenericParam::Lifetime(param) => param.lifetime.to_token_stream(),
+        GenericParam::Const(param) => param.ident.to_token_stream(),
+    });
+
+    args.collect()
+}
+
+/// The path of a role's type as used in a struct expression, where generic
+/// arguments need a turbofish.
+fn expr_path(ty: &Type) -> TokenStream {
+    let mut ty = ty.clone();
+    if let Type::Path(path) = &mut ty {
+        for segment in &mut path.path.segments {
+            if let PathArguments::AngleBracketed(args) = &mut segment.arguments {
+                args.colon2_token = Some(<Token![::]>::default());
+            }
+        }
+    }
+
+    ty.into_token_stream()
 }
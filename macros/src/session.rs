@@ -1,9 +1,11 @@
+use crate::parse;
 use proc_macro2::TokenStream;
 use quote::{quote, ToTokens};
 use std::{collections::HashSet, mem};
 use syn::{
     parse::Nothing, parse2, parse_quote, punctuated::Punctuated, Error, Fields, GenericArgument,
-    GenericParam, Ident, Index, Item, ItemEnum, ItemStruct, ItemType, PathArguments, Result, Type,
+    GenericParam, Generics, Ident, Index, Item, ItemEnum, ItemStruct, ItemType, PathArguments,
+    Result, Type,
 };
 
 fn idents_set<P>(params: &Punctuated<GenericParam, P>) -> HashSet<Ident> {
@@ -69,6 +71,30 @@ fn augment_type(mut ty: &mut Type, exclude: &HashSet<Ident>) {
     }
 }
 
+fn erase(ident: &Ident, generics: &Generics) -> TokenStream {
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    let mut where_clause = where_clause.cloned().unwrap_or_else(|| parse_quote!(where));
+
+    let mut args = Vec::with_capacity(generics.params.len());
+    for param in &generics.params {
+        args.push(match param {
+            GenericParam::Lifetime(_) => quote!('static),
+            GenericParam::Type(param) => {
+                let ident = &param.ident;
+                where_clause.predicates.push(parse_quote!(#ident: 'static));
+                ident.to_token_stream()
+            }
+            GenericParam::Const(param) => param.ident.to_token_stream(),
+        });
+    }
+
+    quote! {
+        impl #impl_generics ::rumpsteak::delegate::Erase for #ident #ty_generics #where_clause {
+            type Static = #ident<#(#args),*>;
+        }
+    }
+}
+
 fn session_type(mut input: ItemType) -> TokenStream {
     let exclude = idents_set(&input.generics.params);
     punctuated_prepend(
@@ -136,6 +162,7 @@ fn session_struct(mut input: ItemStruct) -> Result<TokenStream> {
         });
     }
 
+    output.extend(erase(ident, &input.generics));
     Ok(quote!(#input #output))
 }
 
@@ -148,6 +175,14 @@ fn session_enum(mut input: ItemEnum) -> Result<TokenStream> {
     let ident = &input.ident;
     let exclude = idents_set(&input.generics.params);
 
+    // The continuation of a timeout once its deadline passes is given on its
+    // choices, so that it can be augmented like the continuation of a choice.
+    let mut elapsed = parse::optional_attribute::<Type>(&input.attrs, "timeout")?;
+    input.attrs.retain(|attr| !attr.path.is_ident("timeout"));
+    if let Some(ty) = &mut elapsed {
+        augment_type(ty, &exclude);
+    }
+
     let mut generics = input.generics.clone();
     punctuated_prepend(
         &mut generics.params,
@@ -164,9 +199,17 @@ fn session_enum(mut input: ItemEnum) -> Result<TokenStream> {
 
     let mut idents = Vec::with_capacity(input.variants.len());
     let mut labels = Vec::with_capacity(input.variants.len());
+    let mut keys = Vec::with_capacity(input.variants.len());
     let mut tys = Vec::with_capacity(input.variants.len());
+    let mut senders = Vec::with_capacity(input.variants.len());
 
     for variant in &mut input.variants {
+        senders.push(parse::optional_attribute::<Type>(&variant.attrs, "role")?);
+        let key = parse::optional_attribute::<Type>(&variant.attrs, "key")?;
+        variant
+            .attrs
+            .retain(|attr| !attr.path.is_ident("role") && !attr.path.is_ident("key"));
+
         idents.push(&variant.ident);
         let fields = match &mut variant.fields {
             Fields::Unnamed(fields) => Ok(&mut fields.unnamed),
@@ -182,16 +225,57 @@ fn session_enum(mut input: ItemEnum) -> Result<TokenStream> {
 
         let label = &fields.next().unwrap().ty;
         labels.push(label);
+        keys.push(match key {
+            Some(key) => key.into_token_stream(),
+            None => label.to_token_stream(),
+        });
 
         let ty = &mut fields.next().unwrap().ty;
         augment_type(ty, &exclude);
         tys.push(&*ty);
     }
 
+    if senders.iter().any(Option::is_some) {
+        let senders = senders.into_iter().collect::<Option<Vec<_>>>();
+        let senders = senders.ok_or_else(|| {
+            let message = "expected #[role(...)] on either every variant or none";
+            Error::new_spanned(ident, message)
+        })?;
+
+        if elapsed.is_some() {
+            let message = "expected #[timeout(...)] only on choices without #[role(...)]";
+            return Err(Error::new_spanned(ident, message));
+        }
+
+        punctuated_prepend(
+            &mut input.generics.params,
+            parse_quote!('__r, __R: ::rumpsteak::Role),
+        );
+
+        let output = race(
+            ident,
+            &input.generics,
+            &idents,
+            &labels,
+            &keys,
+            &tys,
+            &senders,
+        );
+        return Ok(quote!(#input #output));
+    }
+
     let mut output = TokenStream::new();
-    for (label, ty) in labels.iter().zip(&tys) {
+    for (key, ty) in keys.iter().zip(&tys) {
         output.extend(quote! {
-            impl #impl_generics ::rumpsteak::Choice<'__r, #label> for #ident #ty_generics #where_clause {
+            impl #impl_generics ::rumpsteak::Choice<'__r, #key> for #ident #ty_generics #where_clause {
+                type Session = #ty;
+            }
+        });
+    }
+
+    if let Some(ty) = &elapsed {
+        output.extend(quote! {
+            impl #impl_generics ::rumpsteak::Elapse<'__r> for #ident #ty_generics #where_clause {
                 type Session = #ty;
             }
         });
@@ -211,42 +295,213 @@ fn session_enum(mut input: ItemEnum) -> Result<TokenStream> {
         output.extend(quote! {
             impl #impl_generics ::rumpsteak::serialize::SerializeChoices for #ident #ty_generics #where_clause {
                 fn serialize_choices(mut s: ::rumpsteak::serialize::ChoicesSerializer<'_>) {
-                    #(s.serialize_choice::<#labels, #tys>();)*
+                    #(s.serialize_choice::<#keys, #tys>();)*
                 }
             }
         });
     }
 
+    output.extend(erase(ident, &input.generics));
+
     let mut generics = input.generics.clone();
     generics.make_where_clause().predicates.push(parse_quote! {
-        __R::Message: #(::rumpsteak::Message<#labels> +)*
+        __R::Message: #(::rumpsteak::Message<#labels, #keys> + ::rumpsteak::Keyed<#keys> +)*
     });
 
+    let guards = vec![TokenStream::new(); idents.len()];
+    let dispatch = dispatch(&idents, &labels, &keys, &guards);
     let (_, _, where_clause) = generics.split_for_impl();
     output.extend(quote! {
         impl #impl_generics ::rumpsteak::Choices<'__r> for #ident #ty_generics #where_clause {
             type Role = __R;
 
+            fn labels() -> ::rumpsteak::__private::vec::Vec<&'static str> {
+                ::rumpsteak::__private::vec![#(::core::any::type_name::<#keys>()),*]
+            }
+
+            fn label(&self) -> &'static str {
+                match self {
+                    #(Self::#idents(..) => ::core::any::type_name::<#keys>(),)*
+                }
+            }
+
             fn downcast(
                 state: ::rumpsteak::State<'__r, Self::Role>,
-                message: <Self::Role as Role>::Message,
-            ) -> ::core::result::Result<Self, <Self::Role as Role>::Message> {
-                #(let message = match ::rumpsteak::Message::downcast(message) {
-                    Ok(label) => {
-                        return Ok(Self::#idents(
-                            label,
-                            ::rumpsteak::FromState::from_state(state)
-                        ));
+                message: <Self::Role as ::rumpsteak::Role>::Message,
+            ) -> ::core::result::Result<Self, <Self::Role as ::rumpsteak::Role>::Message> {
+                #dispatch
+            }
+        }
+    });
+
+    Ok(quote!(#input #output))
+}
+
+/// Matches the discriminant of the label in `message` against the
+/// discriminant of each choice's key, so that it is only downcast to the label
+/// of the choice it matches. The discriminants of the keys are constants once
+/// the message type is known, so for derived messages this compiles to a jump
+/// on the discriminant.
+fn dispatch(
+    idents: &[&Ident],
+    labels: &[&Type],
+    keys: &[TokenStream],
+    guards: &[TokenStream],
+) -> TokenStream {
+    let message = quote!(<Self::Role as ::rumpsteak::Role>::Message);
+    quote! {
+        match ::rumpsteak::Labelled::discriminant(&message) {
+            #(discriminant if #guards discriminant == <#message as ::rumpsteak::Keyed<#keys>>::discriminant() => {
+                match <#message as ::rumpsteak::Message<#labels, #keys>>::downcast(message) {
+                    ::core::result::Result::Ok(label) => ::core::result::Result::Ok(
+                        Self::#idents(label, ::rumpsteak::FromState::from_state(state)),
+                    ),
+                    ::core::result::Result::Err(message) => ::core::result::Result::Err(message),
+                }
+            })*
+            _ => ::core::result::Result::Err(message),
+        }
+    }
+}
+
+/// Implements `RaceChoices` for an enum whose variants are each received from
+/// the role given by their `#[role(...)]` attribute.
+fn race(
+    ident: &Ident,
+    generics: &Generics,
+    idents: &[&Ident],
+    labels: &[&Type],
+    keys: &[TokenStream],
+    #[cfg_attr(not(feature = "serialize"), allow(unused_variables))] tys: &[&Type],
+    senders: &[Type],
+) -> TokenStream {
+    let mut roles = Vec::<&Type>::new();
+    let mut indices = Vec::with_capacity(senders.len());
+    for sender in senders {
+        let key = sender.to_token_stream().to_string();
+        let index = roles
+            .iter()
+            .position(|role| role.to_token_stream().to_string() == key);
+        indices.push(index.unwrap_or_else(|| {
+            roles.push(sender);
+            roles.len() - 1
+        }));
+    }
+
+    let (impl_generics, ty_generics, _) = generics.split_for_impl();
+
+    let mut output = TokenStream::new();
+
+    #[cfg(feature = "serialize")]
+    {
+        let (_, _, where_clause) = generics.split_for_impl();
+        let mut where_clause = where_clause.cloned().unwrap_or_else(|| parse_quote!(where));
+        where_clause.predicates.push(parse_quote!(Self: 'static));
+
+        output.extend(quote! {
+            impl #impl_generics ::rumpsteak::serialize::SerializeRaceChoices for #ident #ty_generics #where_clause {
+                fn serialize_choices(mut s: ::rumpsteak::serialize::RaceChoicesSerializer<'_>) {
+                    #(s.serialize_choice::<#senders, #keys, #tys>();)*
+                }
+            }
+        });
+    }
+
+    output.extend(erase(ident, generics));
+
+    let mut race_generics = generics.clone();
+    let predicates = &mut race_generics.make_where_clause().predicates;
+    predicates.push(parse_quote! {
+        __R::Message: #(::rumpsteak::Message<#labels, #keys> + ::rumpsteak::Keyed<#keys> +)*
+    });
+
+    for role in &roles {
+        predicates.push(parse_quote!(__R: ::rumpsteak::Route<#role>));
+        predicates.push(parse_quote! {
+            <__R as ::rumpsteak::Route<#role>>::Route: ::rumpsteak::channel::Inbound<__R::Message>
+        });
+    }
+
+    let guards = indices.iter().map(|index| quote!(sender == #index &&));
+    let dispatch = dispatch(idents, labels, keys, &guards.collect::<Vec<_>>());
+    let role_indices = 0..roles.len();
+    let senders = roles.len();
+    let (_, _, where_clause) = race_generics.split_for_impl();
+    output.extend(quote! {
+        impl #impl_generics ::rumpsteak::RaceChoices<'__r> for #ident #ty_generics #where_clause {
+            type Role = __R;
+
+            fn labels() -> ::rumpsteak::__private::vec::Vec<&'static str> {
+                ::rumpsteak::__private::vec![#(::core::any::type_name::<#keys>()),*]
+            }
+
+            fn label(&self) -> &'static str {
+                match self {
+                    #(Self::#idents(..) => ::core::any::type_name::<#keys>(),)*
+                }
+            }
+
+            const SENDERS: usize = #senders;
+
+            fn sender(sender: usize) -> &'static str {
+                [#(::core::any::type_name::<#roles>()),*][sender]
+            }
+
+            fn poll_next(
+                role: &mut Self::Role,
+                start: usize,
+                ended: &mut [::core::option::Option<
+                    ::rumpsteak::ReceiveError<<Self::Role as ::rumpsteak::Role>::Message>,
+                >],
+                cx: &mut ::core::task::Context<'_>,
+            ) -> ::core::task::Poll<::core::result::Result<
+                (usize, <Self::Role as ::rumpsteak::Role>::Message),
+                ::rumpsteak::ReceiveError<<Self::Role as ::rumpsteak::Role>::Message>,
+            >> {
+                for offset in 0..#senders {
+                    let sender = (start + offset) % #senders;
+                    if ended[sender].is_some() {
+                        continue;
                     }
-                    Err(message) => message
-                };)*
 
-                Err(message)
+                    let poll = match sender {
+                        #(#role_indices => ::rumpsteak::channel::Inbound::poll_receive(
+                            <__R as ::rumpsteak::Route<#roles>>::route(role),
+                            cx,
+                        ),)*
+                        _ => ::core::unreachable!(),
+                    };
+
+                    match poll {
+                        ::core::task::Poll::Ready(::core::result::Result::Ok(message)) => {
+                            return ::core::task::Poll::Ready(::core::result::Result::Ok((sender, message)));
+                        }
+                        ::core::task::Poll::Ready(::core::result::Result::Err(error)) => {
+                            ended[sender] = ::core::option::Option::Some(error);
+                        }
+                        ::core::task::Poll::Pending => {}
+                    }
+                }
+
+                if !ended.iter().all(::core::option::Option::is_some) {
+                    return ::core::task::Poll::Pending;
+                }
+
+                let error = ended.iter_mut().find_map(::core::option::Option::take);
+                ::core::task::Poll::Ready(::core::result::Result::Err(error.unwrap()))
+            }
+
+            fn downcast(
+                state: ::rumpsteak::State<'__r, Self::Role>,
+                sender: usize,
+                message: <Self::Role as ::rumpsteak::Role>::Message,
+            ) -> ::core::result::Result<Self, <Self::Role as ::rumpsteak::Role>::Message> {
+                #dispatch
             }
         }
     });
 
-    Ok(quote!(#input #output))
+    output
 }
 
 pub fn session(attr: TokenStream, input: TokenStream) -> Result<TokenStream> {
@@ -0,0 +1,149 @@
+use crate::parse;
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+use syn::{parse2, parse_quote, Data, DeriveInput, Error, Fields, Result, WherePredicate};
+
+pub fn wire(input: TokenStream) -> Result<TokenStream> {
+    let input = parse2::<DeriveInput>(input)?;
+
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, _) = input.generics.split_for_impl();
+
+    let variants = match &input.data {
+        Data::Enum(input) => Ok(&input.variants),
+        _ => Err(Error::new_spanned(&input, "expected an enum")),
+    }?;
+
+    let mut idents = Vec::with_capacity(variants.len());
+    let mut bindings = Vec::with_capacity(variants.len());
+    let mut payloads = Vec::with_capacity(variants.len());
+    let mut tys = Vec::with_capacity(variants.len());
+    let mut payload_tys = Vec::with_capacity(variants.len());
+    let discriminants = parse::discriminants(variants)?;
+    for variant in variants {
+        // A variant with one unnamed field is encoded as that field, and any
+        // other variant as a tuple of its fields in order.
+        let fields = variant
+            .fields
+            .iter()
+            .enumerate()
+            .map(|(index, field)| match &field.ident {
+                Some(ident) => ident.clone(),
+                None => format_ident!("field_{}", index),
+            });
+        let fields = fields.collect::<Vec<_>>();
+        let field_tys = variant.fields.iter().map(|field| &field.ty);
+        let field_tys = field_tys.collect::<Vec<_>>();
+        let single = quote!((#(#fields),*));
+        let tuple = quote!((#(#fields,)*));
+        let tuple_ty = quote!((#(#field_tys,)*));
+        let (binding, payload, ty) = match &variant.fields {
+            Fields::Named(_) => (quote!({ #(#fields),* }), tuple, tuple_ty),
+            Fields::Unnamed(_) if fields.len() == 1 => {
+                (single, quote!(#(#fields)*), quote!(#(#field_tys)*))
+            }
+            Fields::Unnamed(_) => (single, tuple, tuple_ty),
+            Fields::Unit => (quote!(), quote!(()), quote!(())),
+        };
+
+        idents.push(&variant.ident);
+        bindings.push(binding);
+        payloads.push(payload);
+        tys.extend(field_tys);
+        payload_tys.push(ty);
+    }
+
+    let serde = quote!(::rumpsteak::__private::serde);
+
+    let mut serialize_generics = input.generics.clone();
+    let predicates = &mut serialize_generics.make_where_clause().predicates;
+    predicates.extend(
+        tys.iter()
+            .map(|ty| -> WherePredicate { parse_quote!(#ty: #serde::Serialize) }),
+    );
+    let (_, _, serialize_where_clause) = serialize_generics.split_for_impl();
+
+    let mut deserialize_generics = input.generics.clone();
+    deserialize_generics.params.insert(0, parse_quote!('__de));
+    let predicates = &mut deserialize_generics.make_where_clause().predicates;
+    predicates.extend(
+        tys.iter()
+            .map(|ty| -> WherePredicate { parse_quote!(#ty: #serde::Deserialize<'__de>) }),
+    );
+    let (deserialize_impl_generics, deserialize_ty_generics, deserialize_where_clause) =
+        deserialize_generics.split_for_impl();
+
+    let expecting = format!("a label of {}", ident);
+    Ok(quote! {
+        ::rumpsteak::__wire! {
+        impl #impl_generics #serde::Serialize for #ident #ty_generics #serialize_where_clause {
+            fn serialize<__S: #serde::Serializer>(
+                &self,
+                serializer: __S,
+            ) -> ::core::result::Result<__S::Ok, __S::Error> {
+                let mut tuple = #serde::Serializer::serialize_tuple(serializer, 2)?;
+                let discriminant: u32 = ::rumpsteak::Labelled::discriminant(self);
+                #serde::ser::SerializeTuple::serialize_element(&mut tuple, &discriminant)?;
+                match self {
+                    #(Self::#idents #bindings => {
+                        #serde::ser::SerializeTuple::serialize_element(&mut tuple, &#payloads)?;
+                    })*
+                }
+
+                #serde::ser::SerializeTuple::end(tuple)
+            }
+        }
+
+        impl #deserialize_impl_generics #serde::Deserialize<'__de> for #ident #ty_generics
+        #deserialize_where_clause
+        {
+            fn deserialize<__D: #serde::Deserializer<'__de>>(
+                deserializer: __D,
+            ) -> ::core::result::Result<Self, __D::Error> {
+                struct Visitor #deserialize_impl_generics (
+                    ::core::marker::PhantomData<fn(&'__de ()) -> #ident #ty_generics>,
+                ) #deserialize_where_clause;
+
+                impl #deserialize_impl_generics #serde::de::Visitor<'__de> for Visitor #deserialize_ty_generics
+                #deserialize_where_clause
+                {
+                    type Value = #ident #ty_generics;
+
+                    fn expecting(
+                        &self,
+                        formatter: &mut ::core::fmt::Formatter<'_>,
+                    ) -> ::core::fmt::Result {
+                        formatter.write_str(#expecting)
+                    }
+
+                    fn visit_seq<__A: #serde::de::SeqAccess<'__de>>(
+                        self,
+                        mut seq: __A,
+                    ) -> ::core::result::Result<Self::Value, __A::Error> {
+                        let discriminant = #serde::de::SeqAccess::next_element::<u32>(&mut seq)?.ok_or_else(|| {
+                            #serde::de::Error::invalid_length(0, &self)
+                        })?;
+
+                        let label = match discriminant {
+                            #(#discriminants => {
+                                let label = #serde::de::SeqAccess::next_element::<#payload_tys>(&mut seq)?;
+                                label.map(|#payloads| #ident::#idents #bindings)
+                            })*
+                            _ => {
+                                return ::core::result::Result::Err(#serde::de::Error::custom(
+                                    ::core::format_args!("unknown label {}", discriminant),
+                                ));
+                            }
+                        };
+
+                        label.ok_or_else(|| #serde::de::Error::invalid_length(1, &self))
+                    }
+                }
+
+                let visitor = Visitor(::core::marker::PhantomData);
+                #serde::Deserializer::deserialize_tuple(deserializer, 2, visitor)
+            }
+        }
+        }
+    })
+}
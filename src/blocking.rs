@@ -0,0 +1,274 @@
+#![cfg(feature = "std")]
+
+use crate::{
+    channel::{Cancel, Ended, Pair, Signal},
+    Branch, Choice, Choices, End, FromState, Gather, Keyed, Message, Race, RaceChoices, Receive,
+    ReceiveError, Role, Route, Routes, Scatter, Select, Send, SendError, State,
+};
+use futures::{executor, task::AtomicWaker, Sink, Stream};
+use std::{
+    convert::Infallible,
+    pin::Pin,
+    sync::{
+        mpsc::{self, TryRecvError},
+        Arc,
+    },
+    task::{Context, Poll},
+    vec::Vec,
+};
+use thiserror::Error;
+
+/// The error from sending on a blocking channel whose receiver has gone.
+#[derive(Debug, Error)]
+#[error("receiver is disconnected")]
+pub struct Disconnected;
+
+/// The sending half of a channel backed by `std::sync::mpsc`, for roles which
+/// only use the blocking API.
+pub struct Sender<T> {
+    sender: Option<mpsc::Sender<T>>,
+    signal: Signal,
+    waker: Arc<AtomicWaker>,
+}
+
+/// The receiving half of a channel backed by `std::sync::mpsc`.
+///
+/// Polling never blocks: it registers the task to be woken by the sender, so
+/// these routes also work in a `Race` or with a timeout.
+pub struct Receiver<T> {
+    receiver: Option<mpsc::Receiver<T>>,
+    signal: Signal,
+    waker: Arc<AtomicWaker>,
+}
+
+impl<T> Pair<Receiver<T>> for Sender<T> {
+    fn pair() -> (Self, Receiver<T>) {
+        let (sender, receiver) = mpsc::channel();
+        let (signal, waker) = (Signal::default(), Arc::new(AtomicWaker::new()));
+        let sender = Sender {
+            sender: Some(sender),
+            signal: signal.clone(),
+            waker: waker.clone(),
+        };
+        let receiver = Receiver {
+            receiver: Some(receiver),
+            signal,
+            waker,
+        };
+
+        (sender, receiver)
+    }
+}
+
+impl<T> Pair<Sender<T>> for Receiver<T> {
+    fn pair() -> (Self, Sender<T>) {
+        let (sender, receiver) = Pair::pair();
+        (receiver, sender)
+    }
+}
+
+impl<T> Sink<T> for Sender<T> {
+    type Error = Disconnected;
+
+    fn poll_ready(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: T) -> Result<(), Self::Error> {
+        let sender = self.sender.as_ref().ok_or(Disconnected)?;
+        sender.send(item).map_err(|_| Disconnected)?;
+        self.waker.wake();
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<T> Stream for Receiver<T> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let receiver = match &self.receiver {
+            Some(receiver) => receiver,
+            None => return Poll::Ready(None),
+        };
+
+        // The waker is registered before trying to receive, so that a message
+        // sent in between still wakes the task.
+        self.waker.register(cx.waker());
+        match receiver.try_recv() {
+            Ok(item) => Poll::Ready(Some(item)),
+            Err(TryRecvError::Empty) => Poll::Pending,
+            Err(TryRecvError::Disconnected) => Poll::Ready(None),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.sender = None;
+        self.waker.wake();
+    }
+}
+
+impl<T> Cancel for Sender<T> {
+    fn cancel(&mut self) {
+        self.signal.raise();
+        self.sender = None;
+        self.waker.wake();
+    }
+}
+
+/// The stream ends once the sender has been cancelled or dropped, which the
+/// receiver tells apart through a signal shared with the sender.
+impl<T> Cancel for Receiver<T> {
+    fn cancel(&mut self) {
+        self.receiver = None;
+    }
+
+    fn ended(&mut self) -> Ended {
+        self.signal.ended()
+    }
+}
+
+impl<'q, Q: Route<R>, R, L, S: FromState<'q, Role = Q>> Send<'q, Q, R, L, S>
+where
+    Q::Message: Keyed<L> + Message<<Q::Message as Keyed<L>>::Label, L>,
+    Q::Route: Sink<Q::Message> + Unpin,
+{
+    /// Sends like `send`, blocking the thread until the route accepts it.
+    #[inline]
+    pub fn send_blocking(
+        self,
+        label: <Q::Message as Keyed<L>>::Label,
+    ) -> Result<S, SendError<Q, R>> {
+        executor::block_on(self.send(label))
+    }
+}
+
+impl<'q, Q: Route<R>, R, L, S: FromState<'q, Role = Q>> Receive<'q, Q, R, L, S>
+where
+    Q::Message: Keyed<L> + Message<<Q::Message as Keyed<L>>::Label, L>,
+    Q::Route: Stream<Item = Q::Message> + Cancel + Unpin,
+{
+    /// Receives like `receive`, blocking the thread until a message arrives.
+    #[inline]
+    #[allow(clippy::type_complexity)]
+    pub fn receive_blocking(
+        self,
+    ) -> Result<(<Q::Message as Keyed<L>>::Label, S), ReceiveError<Q::Message>> {
+        executor::block_on(self.receive())
+    }
+}
+
+impl<'q, Q: Route<R>, R, C> Select<'q, Q, R, C>
+where
+    Q::Route: Sink<Q::Message> + Unpin,
+{
+    /// Selects like `select`, blocking the thread until the route accepts it.
+    #[inline]
+    pub fn select_blocking<L>(
+        self,
+        label: L,
+    ) -> Result<<C as Choice<'q, L>>::Session, SendError<Q, R>>
+    where
+        Q::Message: Message<L>,
+        C: Choice<'q, L>,
+        C::Session: FromState<'q, Role = Q>,
+    {
+        executor::block_on(self.select(label))
+    }
+
+    /// Selects like `select_keyed`, blocking the thread until the route
+    /// accepts it.
+    #[inline]
+    pub fn select_keyed_blocking<K>(
+        self,
+        label: <Q::Message as Keyed<K>>::Label,
+    ) -> Result<<C as Choice<'q, K>>::Session, SendError<Q, R>>
+    where
+        Q::Message: Keyed<K> + Message<<Q::Message as Keyed<K>>::Label, K>,
+        C: Choice<'q, K>,
+        C::Session: FromState<'q, Role = Q>,
+    {
+        executor::block_on(self.select_keyed::<K>(label))
+    }
+}
+
+impl<'q, Q: Route<R>, R, C: Choices<'q, Role = Q>> Branch<'q, Q, R, C>
+where
+    Q::Route: Stream<Item = Q::Message> + Cancel + Unpin,
+{
+    /// Branches like `branch`, blocking the thread until a message arrives.
+    #[inline]
+    pub fn branch_blocking(self) -> Result<C, ReceiveError<Q::Message>> {
+        executor::block_on(self.branch())
+    }
+}
+
+impl<'q, Q: Role, C: RaceChoices<'q, Role = Q>> Race<'q, Q, C> {
+    /// Branches like `branch`, blocking the thread until a message arrives.
+    #[inline]
+    pub fn branch_blocking(self) -> Result<C, ReceiveError<Q::Message>> {
+        executor::block_on(self.branch())
+    }
+}
+
+impl<'q, Q: Routes<R>, R, L, S: FromState<'q, Role = Q>> Scatter<'q, Q, R, L, S>
+where
+    Q::Message: Message<L>,
+    Q::Route: Sink<Q::Message> + Unpin,
+{
+    /// Sends like `send`, blocking the thread until every route accepts it.
+    #[inline]
+    pub fn send_blocking(
+        self,
+        label: impl FnMut(usize) -> L,
+    ) -> Result<S, <Q::Route as Sink<Q::Message>>::Error> {
+        executor::block_on(self.send(label))
+    }
+}
+
+impl<'q, Q: Routes<R>, R, L, S: FromState<'q, Role = Q>> Gather<'q, Q, R, L, S>
+where
+    Q::Message: Message<L>,
+    Q::Route: Stream<Item = Q::Message> + Cancel + Unpin,
+{
+    /// Receives like `receive`, blocking the thread until every message has
+    /// arrived.
+    #[inline]
+    pub fn receive_blocking(self) -> Result<(Vec<L>, S), ReceiveError<Q::Message>> {
+        executor::block_on(self.receive())
+    }
+}
+
+/// Runs a session like `rumpsteak::session`, but with a synchronous function
+/// for use outside of an async context.
+#[inline]
+pub fn session<'r, R: Role, S: FromState<'r, Role = R>, T>(
+    role: &'r mut R,
+    f: impl FnOnce(S) -> (T, End<'r, R>),
+) -> T {
+    let output = try_session(role, |s| Ok(f(s)));
+    output.unwrap_or_else(|infallible: Infallible| match infallible {})
+}
+
+/// Runs a session like `rumpsteak::try_session`, but with a synchronous
+/// function for use outside of an async context.
+#[inline]
+pub fn try_session<'r, R: Role, S: FromState<'r, Role = R>, T, E>(
+    role: &'r mut R,
+    f: impl FnOnce(S) -> Result<(T, End<'r, R>), E>,
+) -> Result<T, E> {
+    #[cfg(feature = "tracing")]
+    let _span = crate::span::<R>().entered();
+
+    let session = FromState::from_state(State::new(role));
+    f(session).map(|(output, _)| output)
+}
@@ -1,13 +1,269 @@
-use futures::{channel::mpsc, Sink, Stream};
-use std::{
+use crate::{ended, ReceiveError};
+#[cfg(target_has_atomic = "ptr")]
+use alloc::sync::Arc;
+use alloc::{
+    boxed::Box,
+    vec::{self, Vec},
+};
+#[cfg(target_has_atomic = "ptr")]
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::{
+    convert::Infallible,
+    error,
     pin::Pin,
     task::{Context, Poll},
 };
+use futures::{Sink, Stream};
+use thiserror::Error;
+
+// Halves are told apart by a shared pointer, which needs atomics to be shared.
+#[cfg(target_has_atomic = "ptr")]
+mod split;
+
+#[cfg(target_has_atomic = "ptr")]
+pub use split::{ReceiveHalf, ReuniteError, SendHalf};
+
+#[cfg(feature = "std")]
+use futures::channel::mpsc;
 
 pub trait Pair<P: Pair<Self>>: Sized {
     fn pair() -> (Self, P);
 }
 
+/// Pairs a role with every member of a family of `size` roles.
+pub fn pair_family<L: Pair<R>, R: Pair<L>>(size: usize) -> (Vec<L>, Vec<R>) {
+    (0..size).map(|_| Pair::pair()).unzip()
+}
+
+/// Links a role with every member of a family of `size` roles.
+pub fn link_family<L, R>(
+    size: usize,
+    link: &mut impl Link<L, R>,
+) -> Result<(Vec<L>, Vec<R>), LinkError> {
+    let routes = (0..size)
+        .map(|_| link.link())
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(routes.into_iter().unzip())
+}
+
+/// Pairs every member of one family with every member of another, giving the
+/// routes of each member ordered by the index of its peer.
+pub fn pair_families<L: Pair<R>, R: Pair<L>>(
+    left: usize,
+    right: usize,
+) -> (Vec<Vec<L>>, Vec<Vec<R>>) {
+    let families = families(left, right, || Ok::<_, Infallible>(Pair::pair()));
+    families.unwrap_or_else(|infallible| match infallible {})
+}
+
+/// Links every member of one family with every member of another, like
+/// `pair_families`.
+#[allow(clippy::type_complexity)]
+pub fn link_families<L, R>(
+    left: usize,
+    right: usize,
+    link: &mut impl Link<L, R>,
+) -> Result<(Vec<Vec<L>>, Vec<Vec<R>>), LinkError> {
+    families(left, right, || link.link())
+}
+
+#[allow(clippy::type_complexity)]
+fn families<L, R, E>(
+    left: usize,
+    right: usize,
+    mut link: impl FnMut() -> Result<(L, R), E>,
+) -> Result<(Vec<Vec<L>>, Vec<Vec<R>>), E> {
+    let mut lefts = (0..left)
+        .map(|_| Vec::with_capacity(right))
+        .collect::<Vec<_>>();
+    let mut rights = (0..right)
+        .map(|_| Vec::with_capacity(left))
+        .collect::<Vec<_>>();
+    for left in &mut lefts {
+        for right in &mut rights {
+            let (left_route, right_route) = link()?;
+            left.push(left_route);
+            right.push(right_route);
+        }
+    }
+
+    Ok((lefts, rights))
+}
+
+/// Pairs the members of a family with each other, giving each member its
+/// routes to the others ordered by their index. Fails if `size` is less than
+/// two, since the `Next` or `Prev` member of a lone member would be itself.
+pub fn pair_within<T: Pair<T>>(size: usize) -> Result<Vec<Vec<T>>, LinkError> {
+    link_within(size, &mut Unlinked)
+}
+
+/// Links the members of a family with each other, like `pair_within`.
+pub fn link_within<T>(size: usize, link: &mut impl Link<T, T>) -> Result<Vec<Vec<T>>, LinkError> {
+    if size < 2 {
+        return Err(LinkError::Lonely(size));
+    }
+
+    let capacity = size - 1;
+    let mut routes = (0..size)
+        .map(|_| Vec::with_capacity(capacity))
+        .collect::<Vec<_>>();
+    for i in 0..size {
+        for j in i + 1..size {
+            let (left, right) = link.link()?;
+            routes[i].push(left);
+            routes[j].push(right);
+        }
+    }
+
+    Ok(routes)
+}
+
+/// The error from linking the routes between roles.
+#[derive(Debug, Error)]
+pub enum LinkError {
+    /// A family has fewer than two members, so a lone member would route to
+    /// itself as its own neighbour.
+    #[error("a family needs at least two members, but has {0}")]
+    Lonely(usize),
+    /// A link ran out of routes before every pair of roles was linked.
+    #[error("no routes left for this pair of roles")]
+    Exhausted,
+}
+
+/// The routes between two kinds of roles as given to a builder generated by
+/// `#[derive(Roles)]`, which gives the routes of one pair of roles at a time,
+/// since a family has a pair of routes for each of its members.
+pub trait Link<L, R> {
+    /// The routes between the next pair of roles.
+    fn link(&mut self) -> Result<(L, R), LinkError>;
+}
+
+/// A link which was not given to a builder, so its routes are paired.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Unlinked;
+
+impl<L: Pair<R>, R: Pair<L>> Link<L, R> for Unlinked {
+    fn link(&mut self) -> Result<(L, R), LinkError> {
+        Ok(Pair::pair())
+    }
+}
+
+/// A link whose routes were created by a factory given to a builder, once for
+/// each pair of roles.
+#[derive(Clone, Debug)]
+pub struct Linked<L, R>(vec::IntoIter<(L, R)>);
+
+impl<L, R> Linked<L, R> {
+    /// Creates the routes of `count` pairs of roles with `factory`.
+    pub fn new(count: usize, factory: impl FnMut() -> (L, R)) -> Self {
+        let mut factory = factory;
+        let routes = (0..count).map(|_| factory()).collect::<Vec<_>>();
+        Self(routes.into_iter())
+    }
+}
+
+impl<L, R> Link<L, R> for Linked<L, R> {
+    fn link(&mut self) -> Result<(L, R), LinkError> {
+        self.0.next().ok_or(LinkError::Exhausted)
+    }
+}
+
+/// A builder generated by `#[derive(Roles)]` where the link between `Q` and `R`
+/// can still be given, as the routes between a single `Q` and a single `R`.
+pub trait Connect<Q, R> {
+    /// The route from `Q` to `R`.
+    type Left;
+    /// The route from `R` to `Q`.
+    type Right;
+    type Output;
+
+    fn connect(self, factory: impl FnMut() -> (Self::Left, Self::Right)) -> Self::Output;
+}
+
+/// A route which can tell its peer that the protocol was abandoned, so that
+/// the peer fails to receive instead of waiting forever.
+///
+/// Both methods have defaults for routes which cannot signal cancellation, so
+/// such a route only needs an empty `impl Cancel for Route {}` to be received
+/// from and to be used by `#[derive(Role)]`. Its peers then see a cancelled
+/// session as the end of the stream.
+pub trait Cancel {
+    /// Signals to the peer that no more messages will be sent or received.
+    #[inline]
+    fn cancel(&mut self) {}
+
+    /// Why the stream of the route ended, which is checked once it has to tell
+    /// a peer which cancelled apart from one which went away.
+    #[inline]
+    fn ended(&mut self) -> Ended {
+        Ended::Closed
+    }
+}
+
+/// Why the stream of a route ended.
+#[derive(Debug)]
+pub enum Ended {
+    /// The peer went away without cancelling, or the route cannot tell.
+    Closed,
+    /// The peer cancelled the session.
+    Cancelled,
+    /// The route failed, such as when a message could not be decoded, which
+    /// is reported as `ReceiveError::Route`.
+    Failed(Box<dyn error::Error + Send + Sync>),
+}
+
+/// A flag shared by both ends of a pair of routes, which is raised by the end
+/// which cancels so that the other can tell cancellation apart from its peer
+/// going away.
+#[cfg(target_has_atomic = "ptr")]
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Signal(Arc<AtomicBool>);
+
+#[cfg(target_has_atomic = "ptr")]
+impl Signal {
+    pub(crate) fn raise(&self) {
+        self.0.store(true, Ordering::Release);
+    }
+
+    /// Why the stream ended, given that it ended without an error.
+    pub(crate) fn ended(&self) -> Ended {
+        match self.0.load(Ordering::Acquire) {
+            true => Ended::Cancelled,
+            false => Ended::Closed,
+        }
+    }
+}
+
+/// Without atomics the flag cannot be shared between the ends, so a peer which
+/// cancels is reported as having closed its route.
+#[cfg(not(target_has_atomic = "ptr"))]
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Signal;
+
+#[cfg(not(target_has_atomic = "ptr"))]
+impl Signal {
+    pub(crate) fn raise(&self) {}
+
+    pub(crate) fn ended(&self) -> Ended {
+        Ended::Closed
+    }
+}
+
+/// A route which messages can be received from, so that several of them can be
+/// polled at once by a `Race`.
+pub trait Inbound<M>: Stream<Item = M> + Cancel + Unpin {
+    /// Polls for the next message, failing once the stream has ended.
+    fn poll_receive(&mut self, cx: &mut Context<'_>) -> Poll<Result<M, ReceiveError<M>>> {
+        match Pin::new(&mut *self).poll_next(cx) {
+            Poll::Ready(Some(message)) => Poll::Ready(Ok(message)),
+            Poll::Ready(None) => Poll::Ready(Err(ended(self))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<M, T: Stream<Item = M> + Cancel + Unpin> Inbound<M> for T {}
+
 #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Nil;
 
@@ -18,12 +274,16 @@ impl Pair<Nil> for Nil {
     }
 }
 
+impl Cancel for Nil {}
+
+#[cfg(feature = "std")]
 impl<T> Pair<mpsc::UnboundedReceiver<T>> for mpsc::UnboundedSender<T> {
     fn pair() -> (Self, mpsc::UnboundedReceiver<T>) {
         mpsc::unbounded()
     }
 }
 
+#[cfg(feature = "std")]
 impl<T> Pair<mpsc::UnboundedSender<T>> for mpsc::UnboundedReceiver<T> {
     fn pair() -> (Self, mpsc::UnboundedSender<T>) {
         let (sender, receiver) = Pair::pair();
@@ -31,15 +291,131 @@ impl<T> Pair<mpsc::UnboundedSender<T>> for mpsc::UnboundedReceiver<T> {
     }
 }
 
-#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg(feature = "std")]
+impl<T> Cancel for mpsc::UnboundedSender<T> {
+    fn cancel(&mut self) {
+        self.close_channel();
+    }
+}
+
+/// A channel on its own cannot tell whether its sender was cancelled or
+/// dropped, so it is paired within a `Bidirectional` route to tell them apart.
+#[cfg(feature = "std")]
+impl<T> Cancel for mpsc::UnboundedReceiver<T> {
+    fn cancel(&mut self) {
+        self.close();
+    }
+}
+
+/// The error from sending on a bounded channel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Error)]
+pub enum BoundedSendError {
+    #[error("channel is full")]
+    Full,
+    #[error("receiver is disconnected")]
+    Disconnected,
+}
+
+#[cfg(feature = "std")]
+impl From<mpsc::SendError> for BoundedSendError {
+    fn from(error: mpsc::SendError) -> Self {
+        if error.is_full() {
+            Self::Full
+        } else {
+            Self::Disconnected
+        }
+    }
+}
+
+/// The sending half of a bounded channel which holds up to `N` messages, plus
+/// one for each sender, before sending waits for the receiver to catch up.
+///
+/// The capacity is part of the type so that the routes of each role can be
+/// given their own capacities while still being paired by `#[derive(Roles)]`.
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct BoundedSender<T, const N: usize>(mpsc::Sender<T>);
+
+#[cfg(feature = "std")]
+impl<T, const N: usize> Pair<mpsc::Receiver<T>> for BoundedSender<T, N> {
+    fn pair() -> (Self, mpsc::Receiver<T>) {
+        let (sender, receiver) = mpsc::channel(N);
+        (BoundedSender(sender), receiver)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T, const N: usize> Pair<BoundedSender<T, N>> for mpsc::Receiver<T> {
+    fn pair() -> (Self, BoundedSender<T, N>) {
+        let (sender, receiver) = Pair::pair();
+        (receiver, sender)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T, const N: usize> Sink<T> for BoundedSender<T, N> {
+    type Error = BoundedSendError;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let sender = &mut self.get_mut().0;
+        Pin::new(sender).poll_ready(cx).map_err(From::from)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: T) -> Result<(), Self::Error> {
+        let sender = &mut self.get_mut().0;
+        Pin::new(sender).start_send(item).map_err(From::from)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let sender = &mut self.get_mut().0;
+        Pin::new(sender).poll_flush(cx).map_err(From::from)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let sender = &mut self.get_mut().0;
+        Pin::new(sender).poll_close(cx).map_err(From::from)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T, const N: usize> Cancel for BoundedSender<T, N> {
+    fn cancel(&mut self) {
+        self.0.close_channel();
+    }
+}
+
+/// Like an unbounded channel, this cannot tell cancellation apart from its
+/// sender being dropped unless it is paired within a `Bidirectional` route.
+#[cfg(feature = "std")]
+impl<T> Cancel for mpsc::Receiver<T> {
+    fn cancel(&mut self) {
+        self.close();
+    }
+}
+
+/// A route made of a sender and a receiver.
+///
+/// Routes which were paired with `Pair` share a signal, so that the receiver
+/// of one reports `Ended::Cancelled` once the other is cancelled. Routes made
+/// with `new` cannot tell, unless their receiver can itself.
+#[derive(Clone, Debug, Default)]
 pub struct Bidirectional<S, R> {
     sender: S,
     receiver: R,
+    signal: Signal,
 }
 
 impl<S, R> Bidirectional<S, R> {
     pub fn new(sender: S, receiver: R) -> Self {
-        Self { sender, receiver }
+        Self::with_signal(sender, receiver, Signal::default())
+    }
+
+    fn with_signal(sender: S, receiver: R, signal: Signal) -> Self {
+        Self {
+            sender,
+            receiver,
+            signal,
+        }
     }
 }
 
@@ -47,13 +423,29 @@ impl<S: Pair<R>, R: Pair<S>> Pair<Self> for Bidirectional<S, R> {
     fn pair() -> (Self, Self) {
         let (left_sender, right_receiver) = Pair::pair();
         let (right_sender, left_receiver) = Pair::pair();
+        let signal = Signal::default();
         (
-            Bidirectional::new(left_sender, left_receiver),
-            Bidirectional::new(right_sender, right_receiver),
+            Bidirectional::with_signal(left_sender, left_receiver, signal.clone()),
+            Bidirectional::with_signal(right_sender, right_receiver, signal),
         )
     }
 }
 
+impl<S: Cancel, R: Cancel> Cancel for Bidirectional<S, R> {
+    fn cancel(&mut self) {
+        self.signal.raise();
+        self.sender.cancel();
+        self.receiver.cancel();
+    }
+
+    fn ended(&mut self) -> Ended {
+        match self.receiver.ended() {
+            Ended::Closed => self.signal.ended(),
+            ended => ended,
+        }
+    }
+}
+
 impl<S: Unpin, R: Unpin> Bidirectional<S, R> {
     fn sender(self: Pin<&mut Self>) -> Pin<&mut S> {
         Pin::new(&mut self.get_mut().sender)
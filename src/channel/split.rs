@@ -0,0 +1,158 @@
+use super::{Bidirectional, Cancel, Ended, Pair, Signal};
+use alloc::sync::Arc;
+use core::{
+    fmt::{self, Debug, Formatter},
+    pin::Pin,
+    task::{Context, Poll},
+};
+use futures::{Sink, Stream};
+use thiserror::Error;
+
+impl<S, R> Bidirectional<S, R> {
+    /// Splits the route into halves which can be owned separately, such as by
+    /// handing the receiving half to a background task while a session keeps
+    /// the sending half.
+    pub fn split(self) -> (SendHalf<S>, ReceiveHalf<R>) {
+        let route = Arc::new(());
+        let sender = SendHalf {
+            sender: self.sender,
+            route: route.clone(),
+            signal: self.signal.clone(),
+        };
+        let receiver = ReceiveHalf {
+            receiver: self.receiver,
+            route,
+            signal: self.signal,
+        };
+        (sender, receiver)
+    }
+
+    /// Puts back together the halves of a route which was split, failing if
+    /// they came from different routes.
+    pub fn reunite(
+        sender: SendHalf<S>,
+        receiver: ReceiveHalf<R>,
+    ) -> Result<Self, ReuniteError<S, R>> {
+        if !Arc::ptr_eq(&sender.route, &receiver.route) {
+            return Err(ReuniteError(sender, receiver));
+        }
+
+        let signal = receiver.signal;
+        Ok(Self::with_signal(sender.sender, receiver.receiver, signal))
+    }
+}
+
+/// The sending half of a `Bidirectional` route.
+pub struct SendHalf<S> {
+    sender: S,
+    route: Arc<()>,
+    signal: Signal,
+}
+
+impl<S> SendHalf<S> {
+    pub fn get_ref(&self) -> &S {
+        &self.sender
+    }
+}
+
+/// The receiving half of a `Bidirectional` route.
+pub struct ReceiveHalf<R> {
+    receiver: R,
+    route: Arc<()>,
+    signal: Signal,
+}
+
+impl<R> ReceiveHalf<R> {
+    pub fn get_ref(&self) -> &R {
+        &self.receiver
+    }
+}
+
+/// Halves can also be paired directly, so that a route which only goes one way
+/// can be given to a role by `#[derive(Roles)]`. These halves are not from the
+/// same route, so they cannot be reunited with each other.
+impl<S: Pair<R>, R: Pair<S>> Pair<ReceiveHalf<R>> for SendHalf<S> {
+    fn pair() -> (Self, ReceiveHalf<R>) {
+        let (sender, receiver) = Pair::pair();
+        let signal = Signal::default();
+        let sender = SendHalf {
+            sender,
+            route: Arc::new(()),
+            signal: signal.clone(),
+        };
+        let receiver = ReceiveHalf {
+            receiver,
+            route: Arc::new(()),
+            signal,
+        };
+        (sender, receiver)
+    }
+}
+
+impl<S: Pair<R>, R: Pair<S>> Pair<SendHalf<S>> for ReceiveHalf<R> {
+    fn pair() -> (Self, SendHalf<S>) {
+        let (sender, receiver) = Pair::pair();
+        (receiver, sender)
+    }
+}
+
+impl<T, S: Sink<T> + Unpin> Sink<T> for SendHalf<S> {
+    type Error = S::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.get_mut().sender).poll_ready(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: T) -> Result<(), Self::Error> {
+        Pin::new(&mut self.get_mut().sender).start_send(item)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.get_mut().sender).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.get_mut().sender).poll_close(cx)
+    }
+}
+
+impl<R: Stream + Unpin> Stream for ReceiveHalf<R> {
+    type Item = R::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.get_mut().receiver).poll_next(cx)
+    }
+}
+
+impl<S: Cancel> Cancel for SendHalf<S> {
+    fn cancel(&mut self) {
+        self.signal.raise();
+        self.sender.cancel();
+    }
+}
+
+impl<R: Cancel> Cancel for ReceiveHalf<R> {
+    fn cancel(&mut self) {
+        self.signal.raise();
+        self.receiver.cancel();
+    }
+
+    fn ended(&mut self) -> Ended {
+        match self.receiver.ended() {
+            Ended::Closed => self.signal.ended(),
+            ended => ended,
+        }
+    }
+}
+
+/// The error from reuniting halves which came from different routes, giving
+/// the halves back.
+#[derive(Error)]
+#[error("tried to reunite halves which are not from the same route")]
+pub struct ReuniteError<S, R>(pub SendHalf<S>, pub ReceiveHalf<R>);
+
+impl<S, R> Debug for ReuniteError<S, R> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "ReuniteError(..)")
+    }
+}
@@ -0,0 +1,218 @@
+#[cfg(target_has_atomic = "ptr")]
+use crate::{private, Session};
+use crate::{
+    try_session, Branch, End, FromState, Gather, Race, Receive, Role, Scatter, Select, Send,
+    Timeout,
+};
+#[cfg(target_has_atomic = "ptr")]
+use alloc::sync::Arc;
+#[cfg(target_has_atomic = "ptr")]
+use core::fmt::{self, Debug, Formatter};
+use core::{convert::Infallible, future::Future, marker::PhantomData};
+use futures::FutureExt;
+#[cfg(target_has_atomic = "ptr")]
+use thiserror::Error;
+
+/// Relates a session type to the same session with every lifetime replaced by
+/// `'static`. This names a protocol independently of the role it borrows, so
+/// that it can be carried in the type of a message.
+pub trait Erase {
+    type Static: 'static;
+}
+
+impl<'r, R: Role + 'static> Erase for End<'r, R> {
+    type Static = End<'static, R>;
+}
+
+impl<'q, Q: Role + 'static, R: 'static, L: 'static, S> Erase for Send<'q, Q, R, L, S>
+where
+    S: FromState<'q, Role = Q> + Erase,
+    S::Static: FromState<'static, Role = Q>,
+{
+    type Static = Send<'static, Q, R, L, S::Static>;
+}
+
+impl<'q, Q: Role + 'static, R: 'static, L: 'static, S> Erase for Receive<'q, Q, R, L, S>
+where
+    S: FromState<'q, Role = Q> + Erase,
+    S::Static: FromState<'static, Role = Q>,
+{
+    type Static = Receive<'static, Q, R, L, S::Static>;
+}
+
+impl<'q, Q: Role + 'static, R: 'static, L: 'static, S> Erase for Scatter<'q, Q, R, L, S>
+where
+    S: FromState<'q, Role = Q> + Erase,
+    S::Static: FromState<'static, Role = Q>,
+{
+    type Static = Scatter<'static, Q, R, L, S::Static>;
+}
+
+impl<'q, Q: Role + 'static, R: 'static, L: 'static, S> Erase for Gather<'q, Q, R, L, S>
+where
+    S: FromState<'q, Role = Q> + Erase,
+    S::Static: FromState<'static, Role = Q>,
+{
+    type Static = Gather<'static, Q, R, L, S::Static>;
+}
+
+impl<'q, Q: Role + 'static, R: 'static, C: Erase> Erase for Select<'q, Q, R, C> {
+    type Static = Select<'static, Q, R, C::Static>;
+}
+
+impl<'q, Q: Role + 'static, R: 'static, C: Erase> Erase for Branch<'q, Q, R, C> {
+    type Static = Branch<'static, Q, R, C::Static>;
+}
+
+impl<'q, Q: Role + 'static, C: Erase> Erase for Race<'q, Q, C> {
+    type Static = Race<'static, Q, C::Static>;
+}
+
+impl<'q, Q: Role + 'static, R: 'static, C: Erase> Erase for Timeout<'q, Q, R, C> {
+    type Static = Timeout<'static, Q, R, C::Static>;
+}
+
+/// A role sent as a message so that the receiver continues its protocol `S` in
+/// place of the sender. `S` is the session type of the delegated role with its
+/// lifetime erased, e.g. `Server<'static, P>`, so that sending the role to a
+/// peer which would run a different protocol is a type error.
+///
+/// A session can only stop part way through within a delegation, using
+/// `try_hand_off`, so any other role is at the start of its session. The rest
+/// of a session which was handed off is delegated with `resume`.
+pub struct Delegate<R, S> {
+    role: R,
+    /// Tells which handoff the role is waiting to be resumed from, so that it
+    /// cannot be resumed from the handoff of another role.
+    #[cfg(target_has_atomic = "ptr")]
+    handoff: Option<Arc<()>>,
+    phantom: PhantomData<fn() -> S>,
+}
+
+impl<R: Role, S: FromState<'static, Role = R>> Delegate<R, S> {
+    /// Delegates a role so that the receiver runs `S` from its start.
+    pub fn new(role: R) -> Self {
+        Self {
+            role,
+            #[cfg(target_has_atomic = "ptr")]
+            handoff: None,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Runs the delegated protocol like `session`, where the session type
+    /// given to `f` must be the one named by the delegation.
+    #[inline]
+    pub async fn session<'r, T, F, D>(&'r mut self, f: impl FnOnce(D) -> F) -> T
+    where
+        D: FromState<'r, Role = R> + Erase<Static = S>,
+        F: Future<Output = (T, End<'r, R>)>,
+    {
+        let output = self.try_session(|s: D| f(s).map(Ok)).await;
+        output.unwrap_or_else(|infallible: Infallible| match infallible {})
+    }
+
+    /// Runs the delegated protocol like `try_session`, where the session type
+    /// given to `f` must be the one named by the delegation.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the session was handed off and not resumed.
+    #[inline]
+    pub async fn try_session<'r, T, E, F, D>(&'r mut self, f: impl FnOnce(D) -> F) -> Result<T, E>
+    where
+        D: FromState<'r, Role = R> + Erase<Static = S>,
+        F: Future<Output = Result<(T, End<'r, R>), E>>,
+    {
+        self.check();
+        try_session(&mut self.role, f).await
+    }
+
+    #[cfg(target_has_atomic = "ptr")]
+    fn check(&self) {
+        assert!(self.handoff.is_none(), "session was handed off");
+    }
+
+    #[cfg(not(target_has_atomic = "ptr"))]
+    fn check(&self) {}
+}
+
+#[cfg(target_has_atomic = "ptr")]
+impl<R: Role, S: FromState<'static, Role = R>> Delegate<R, S> {
+    /// Runs the delegated protocol like `try_session`, except that `f` stops
+    /// part way through by giving back the rest of the session, which is
+    /// delegated by resuming this delegation with the returned `Handoff`. The
+    /// role is not cancelled, since its peers expect the protocol to continue.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the session was already handed off and not resumed.
+    #[inline]
+    #[allow(clippy::type_complexity)]
+    pub async fn try_hand_off<'r, T, E, F, D, N>(
+        &'r mut self,
+        f: impl FnOnce(D) -> F,
+    ) -> Result<(T, Handoff<R, N::Static>), E>
+    where
+        D: FromState<'r, Role = R> + Erase<Static = S>,
+        N: Session<'r, Role = R> + Erase,
+        N::Static: FromState<'static, Role = R>,
+        F: Future<Output = Result<(T, N), E>>,
+    {
+        self.check();
+        let Self { role, handoff, .. } = self;
+        let output = try_session(role, |s: D| {
+            f(s).map(|output| output.map(|(output, rest)| (output, end(rest))))
+        })
+        .await?;
+
+        let brand = Arc::new(());
+        *handoff = Some(brand.clone());
+        let handoff = Handoff {
+            brand,
+            phantom: PhantomData,
+        };
+        Ok((output, handoff))
+    }
+
+    /// Delegates the rest of a session which was handed off, failing if
+    /// `handoff` came from another delegation.
+    pub fn resume<N: FromState<'static, Role = R>>(
+        self,
+        handoff: Handoff<R, N>,
+    ) -> Result<Delegate<R, N>, ResumeError<R, S, N>> {
+        match &self.handoff {
+            Some(brand) if Arc::ptr_eq(brand, &handoff.brand) => Ok(Delegate::new(self.role)),
+            _ => Err(ResumeError(self, handoff)),
+        }
+    }
+}
+
+/// Ends a session which was handed off without cancelling its role.
+#[cfg(target_has_atomic = "ptr")]
+fn end<'r, S: Session<'r>>(session: S) -> End<'r, S::Role> {
+    FromState::from_state(private::Session::into_state(session))
+}
+
+/// Proof that the session of a delegation of a role of type `R` stopped part
+/// way through, where it would have continued as `S`, which lets the rest be
+/// delegated with `Delegate::resume`.
+#[cfg(target_has_atomic = "ptr")]
+pub struct Handoff<R, S> {
+    brand: Arc<()>,
+    phantom: PhantomData<fn() -> (R, S)>,
+}
+
+/// The error from resuming a delegation with the handoff of another, which
+/// gives both back.
+#[cfg(target_has_atomic = "ptr")]
+#[derive(Error)]
+#[error("handoff came from another delegation")]
+pub struct ResumeError<R, S, N>(pub Delegate<R, S>, pub Handoff<R, N>);
+
+#[cfg(target_has_atomic = "ptr")]
+impl<R, S, N> Debug for ResumeError<R, S, N> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "ResumeError(..)")
+    }
+}
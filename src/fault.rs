@@ -0,0 +1,378 @@
+#![cfg(feature = "std")]
+
+use crate::channel::{Cancel, Ended};
+use futures::{Sink, Stream};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    vec::Vec,
+};
+use thiserror::Error;
+
+/// A fault to inject into a message sent on a route.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fault<M> {
+    /// Drops the message so the peer never receives it.
+    Drop,
+    /// Sends the message followed by the given copy of it.
+    Duplicate(M),
+    /// Sends the given message instead, such as one with a different label.
+    Corrupt(M),
+    /// Holds the message back until this many later messages have been sent,
+    /// or until the route is closed or received from.
+    Delay(usize),
+    /// Drops the message and cancels the route, so this and every later send
+    /// fails and the peer's stream ends.
+    Disconnect,
+}
+
+impl<M> Fault<M> {
+    pub fn kind(&self) -> FaultKind {
+        match self {
+            Self::Drop => FaultKind::Drop,
+            Self::Duplicate(_) => FaultKind::Duplicate,
+            Self::Corrupt(_) => FaultKind::Corrupt,
+            Self::Delay(_) => FaultKind::Delay,
+            Self::Disconnect => FaultKind::Disconnect,
+        }
+    }
+}
+
+/// The kind of a fault, without the messages it carries.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum FaultKind {
+    Drop,
+    Duplicate,
+    Corrupt,
+    Delay,
+    Disconnect,
+}
+
+/// A fault which was injected into the message at `index`, counting from zero
+/// among the messages sent on the route.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Fired {
+    pub index: usize,
+    pub kind: FaultKind,
+}
+
+/// Decides which fault, if any, to inject into each message sent on a route.
+pub trait Policy<M> {
+    fn inject(&mut self, index: usize, message: &M) -> Option<Fault<M>>;
+}
+
+impl<M, F: FnMut(usize, &M) -> Option<Fault<M>>> Policy<M> for F {
+    fn inject(&mut self, index: usize, message: &M) -> Option<Fault<M>> {
+        self(index, message)
+    }
+}
+
+/// A policy which injects faults into the messages at given indices.
+#[derive(Clone, Debug)]
+pub struct Script<M>(BTreeMap<usize, Fault<M>>);
+
+impl<M> Script<M> {
+    pub fn new() -> Self {
+        Self(BTreeMap::new())
+    }
+
+    /// Injects `fault` into the message at `index`.
+    pub fn at(mut self, index: usize, fault: Fault<M>) -> Self {
+        self.0.insert(index, fault);
+        self
+    }
+}
+
+impl<M> Default for Script<M> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<M> Policy<M> for Script<M> {
+    fn inject(&mut self, index: usize, _: &M) -> Option<Fault<M>> {
+        self.0.remove(&index)
+    }
+}
+
+/// A shared record of the faults which fired on a route, which can still be
+/// inspected after the route has been moved into a role.
+#[derive(Clone, Debug, Default)]
+pub struct Log(Arc<Mutex<Vec<Fired>>>);
+
+impl Log {
+    /// The faults which have fired so far, in the order they fired.
+    pub fn fired(&self) -> Vec<Fired> {
+        self.0.lock().unwrap().clone()
+    }
+
+    /// How many faults of `kind` have fired so far.
+    pub fn count(&self, kind: FaultKind) -> usize {
+        let fired = self.0.lock().unwrap();
+        fired.iter().filter(|fired| fired.kind == kind).count()
+    }
+
+    fn push(&self, fired: Fired) {
+        self.0.lock().unwrap().push(fired);
+    }
+}
+
+/// The error from sending on a route with injected faults.
+#[derive(Debug, Error)]
+pub enum Error<E> {
+    #[error("route was disconnected by an injected fault")]
+    Disconnected,
+    #[error(transparent)]
+    Route(E),
+}
+
+/// Wraps a route to inject faults into the messages sent on it, so that the
+/// error paths of a protocol can be exercised.
+///
+/// Only outgoing messages are affected, while received messages are passed
+/// through unchanged, so faults on both directions of a link are injected by
+/// wrapping the routes at each end.
+pub struct Faulty<T, M, P> {
+    route: T,
+    policy: P,
+    log: Log,
+    index: usize,
+    sent: usize,
+    outgoing: VecDeque<M>,
+    delayed: VecDeque<(usize, M)>,
+    disconnected: bool,
+}
+
+impl<T, M, P: Policy<M>> Faulty<T, M, P> {
+    pub fn new(route: T, policy: P) -> Self {
+        Self {
+            route,
+            policy,
+            log: Log::default(),
+            index: 0,
+            sent: 0,
+            outgoing: VecDeque::new(),
+            delayed: VecDeque::new(),
+            disconnected: false,
+        }
+    }
+
+    /// A handle to the faults fired on this route.
+    pub fn log(&self) -> Log {
+        self.log.clone()
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.route
+    }
+
+    pub fn into_inner(self) -> T {
+        self.route
+    }
+
+    /// Holds back a message until `later` more messages have been queued.
+    fn delay(&mut self, later: usize, message: M) {
+        self.delayed.push_back((self.sent + later, message));
+    }
+
+    /// Queues a message to be sent, releasing any delayed messages which were
+    /// waiting for it. Delayed messages are released in the order they were
+    /// sent, even if a later one is released first.
+    fn enqueue(&mut self, message: M) {
+        self.outgoing.push_back(message);
+        self.sent += 1;
+
+        let mut i = 0;
+        while i < self.delayed.len() {
+            if self.delayed[i].0 > self.sent {
+                i += 1;
+                continue;
+            }
+
+            let (_, message) = self.delayed.remove(i).unwrap();
+            self.outgoing.push_back(message);
+        }
+    }
+}
+
+impl<T: Sink<M> + Unpin, M, P: Unpin> Faulty<T, M, P> {
+    /// Queues every delayed message, regardless of how many later messages it
+    /// was waiting for.
+    fn release(&mut self) {
+        let delayed = self.delayed.drain(..).map(|(_, message)| message);
+        self.outgoing.extend(delayed);
+    }
+
+    /// Sends the queued messages on to the route.
+    fn poll_outgoing(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Error<T::Error>>> {
+        while !self.outgoing.is_empty() {
+            let mut route = Pin::new(&mut self.route);
+            futures::ready!(route.as_mut().poll_ready(cx)).map_err(Error::Route)?;
+            let message = self.outgoing.pop_front().unwrap();
+            route.start_send(message).map_err(Error::Route)?;
+        }
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<T: Sink<M> + Cancel + Unpin, M: Unpin, P: Policy<M> + Unpin> Sink<M> for Faulty<T, M, P> {
+    type Error = Error<T::Error>;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        if this.disconnected {
+            return Poll::Ready(Err(Error::Disconnected));
+        }
+
+        this.poll_outgoing(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, message: M) -> Result<(), Self::Error> {
+        let this = self.get_mut();
+        if this.disconnected {
+            return Err(Error::Disconnected);
+        }
+
+        let index = this.index;
+        this.index += 1;
+
+        let fault = match this.policy.inject(index, &message) {
+            Some(fault) => fault,
+            None => {
+                this.enqueue(message);
+                return Ok(());
+            }
+        };
+
+        let kind = fault.kind();
+        this.log.push(Fired { index, kind });
+        match fault {
+            Fault::Drop => {}
+            Fault::Duplicate(copy) => {
+                this.enqueue(message);
+                this.enqueue(copy);
+            }
+            Fault::Corrupt(message) => this.enqueue(message),
+            Fault::Delay(0) => this.enqueue(message),
+            Fault::Delay(later) => this.delay(later, message),
+            Fault::Disconnect => {
+                this.disconnected = true;
+                this.outgoing.clear();
+                this.delayed.clear();
+                this.route.cancel();
+                return Err(Error::Disconnected);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        futures::ready!(this.poll_outgoing(cx))?;
+        let route = Pin::new(&mut this.route);
+        route.poll_flush(cx).map_err(Error::Route)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        this.release();
+        futures::ready!(this.poll_outgoing(cx))?;
+        let route = Pin::new(&mut this.route);
+        route.poll_close(cx).map_err(Error::Route)
+    }
+}
+
+/// Receiving first sends every delayed message, since a role which waits for
+/// its peer would otherwise hold them back forever once it stops sending. Any
+/// error from sending them is left for the next send to report, since a stream
+/// cannot yield errors.
+impl<T: Sink<M> + Stream + Unpin, M: Unpin, P: Unpin> Stream for Faulty<T, M, P> {
+    type Item = <T as Stream>::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if !this.disconnected {
+            this.release();
+            if let Poll::Ready(Ok(())) = this.poll_outgoing(cx) {
+                let _ = Pin::new(&mut this.route).poll_flush(cx);
+            }
+        }
+
+        Pin::new(&mut this.route).poll_next(cx)
+    }
+}
+
+impl<T: Cancel, M, P> Cancel for Faulty<T, M, P> {
+    fn cancel(&mut self) {
+        self.route.cancel();
+    }
+
+    fn ended(&mut self) -> Ended {
+        self.route.ended()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel::{Bidirectional, Pair};
+    use futures::{channel::mpsc, executor, future, SinkExt, StreamExt};
+
+    fn send_all(policy: Script<usize>, count: usize) -> Vec<usize> {
+        let (sender, receiver) = mpsc::unbounded();
+        let mut route = Faulty::new(sender, policy);
+        executor::block_on(async {
+            for message in 0..count {
+                route.send(message).await.unwrap();
+            }
+
+            route.close().await.unwrap();
+            receiver.collect().await
+        })
+    }
+
+    #[test]
+    fn delay() {
+        let policy = Script::new().at(1, Fault::Delay(2));
+        assert_eq!(send_all(policy, 5), [0, 2, 3, 1, 4]);
+    }
+
+    #[test]
+    fn delays_out_of_order() {
+        let policy = Script::new().at(0, Fault::Delay(3)).at(1, Fault::Delay(1));
+        assert_eq!(send_all(policy, 5), [2, 1, 3, 4, 0]);
+    }
+
+    #[test]
+    fn delays_released_together() {
+        let policy = Script::new().at(0, Fault::Delay(1)).at(1, Fault::Delay(1));
+        assert_eq!(send_all(policy, 3), [2, 0, 1]);
+    }
+
+    #[test]
+    fn delay_past_last_send() {
+        let policy = Script::new().at(1, Fault::Delay(5));
+        assert_eq!(send_all(policy, 3), [0, 2, 1]);
+    }
+
+    #[test]
+    fn delay_released_on_receive() {
+        type Route = Bidirectional<mpsc::UnboundedSender<usize>, mpsc::UnboundedReceiver<usize>>;
+        let (left, mut right) = Route::pair();
+        let mut route = Faulty::new(left, Script::new().at(0, Fault::Delay(5)));
+        executor::block_on(async {
+            route.send(1).await.unwrap();
+            let reply = async {
+                let message = right.next().await.unwrap();
+                right.send(message + 1).await.unwrap();
+            };
+
+            let (received, ()) = future::join(route.next(), reply).await;
+            assert_eq!(received, Some(2));
+        });
+    }
+}
@@ -1,31 +1,220 @@
+#![no_std]
+
+extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
+
+pub mod blocking;
 pub mod channel;
+pub mod delegate;
+pub mod fault;
+pub mod metrics;
+pub mod monitor;
+pub mod net;
+pub mod record;
 pub mod serialize;
+pub mod sim;
+
+pub use rumpsteak_macros::{protocol, session, Message, Role, Roles, Wire};
+
+/// Items used by the code which the macros generate, since that code may be
+/// within a crate without `std`.
+#[doc(hidden)]
+pub mod __private {
+    pub use alloc::vec;
+    pub use futures;
+
+    #[cfg(feature = "serde")]
+    pub use serde;
+}
 
-pub use rumpsteak_macros::{session, Message, Role, Roles};
+/// Emits the items generated by `#[derive(Wire)]`, which need `serde`.
+#[cfg(feature = "serde")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __wire {
+    ($($item:item)*) => {
+        $($item)*
+    };
+}
 
-use futures::{FutureExt, Sink, SinkExt, Stream, StreamExt};
-use std::{
-    any::Any,
+/// Fails where `#[derive(Wire)]` is used, since it needs `serde`.
+#[cfg(not(feature = "serde"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __wire {
+    ($($item:item)*) => {
+        ::core::compile_error!("#[derive(Wire)] requires the `serde` feature of rumpsteak");
+    };
+}
+
+use alloc::{boxed::Box, vec, vec::Vec};
+use channel::{Cancel, Ended};
+use core::{
+    any::{type_name, Any, TypeId},
     convert::Infallible,
+    error,
+    fmt::{self, Debug, Display, Formatter},
     future::Future,
     marker::{self, PhantomData},
+    task::{Context, Poll},
+};
+use futures::{
+    future::{self, Either},
+    pin_mut, FutureExt, Sink, SinkExt, Stream, StreamExt,
 };
 use thiserror::Error;
 
+#[cfg(all(feature = "tracing", target_has_atomic = "64"))]
+use core::sync::atomic::{AtomicU64, Ordering};
+#[cfg(feature = "tracing")]
+use tracing::Instrument;
+
+/// Records an action on the route to `$peer` as an event within the span of
+/// the current session.
+macro_rules! trace {
+    ($action:literal, $peer:ty, $label:expr) => {
+        trace!($action, peer = type_name::<$peer>(), $label)
+    };
+    ($action:literal, peer = $peer:expr, $label:expr) => {
+        #[cfg(feature = "tracing")]
+        ::tracing::debug!(peer = $peer, label = $label, $action);
+    };
+}
+
 pub type SendError<Q, R> = <<Q as Route<R>>::Route as Sink<<Q as Role>::Message>>::Error;
 
-#[derive(Debug, Error)]
-pub enum ReceiveError {
+#[derive(Error)]
+pub enum ReceiveError<M> {
     #[error("receiver stream is empty")]
     EmptyStream,
-    #[error("received message with an unexpected type")]
-    UnexpectedType,
+    #[error(transparent)]
+    UnexpectedMessage(UnexpectedMessage<M>),
+    #[error("timed out before receiving a message")]
+    Timeout,
+    #[error("peer cancelled the session")]
+    Cancelled,
+    #[error(transparent)]
+    Route(Box<dyn error::Error + marker::Send + Sync>),
+}
+
+impl<M> Debug for ReceiveError<M> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::EmptyStream => write!(f, "EmptyStream"),
+            Self::UnexpectedMessage(error) => {
+                f.debug_tuple("UnexpectedMessage").field(error).finish()
+            }
+            Self::Timeout => write!(f, "Timeout"),
+            Self::Cancelled => write!(f, "Cancelled"),
+            Self::Route(error) => f.debug_tuple("Route").field(error).finish(),
+        }
+    }
+}
+
+/// A message which could not be downcast to any label expected by the current
+/// state of the session. The message itself is kept so that it can be
+/// inspected or forwarded, along with the context needed to explain the
+/// protocol violation.
+#[derive(Error)]
+#[error(
+    "{role} received an unexpected message from {peer} while expecting {} in state {state}",
+    Expected(expected)
+)]
+pub struct UnexpectedMessage<M> {
+    message: M,
+    role: &'static str,
+    peer: &'static str,
+    expected: Vec<&'static str>,
+    state: &'static str,
+}
+
+impl<M> UnexpectedMessage<M> {
+    fn new<Q: Role<Message = M>, S>(
+        message: M,
+        peer: &'static str,
+        expected: Vec<&'static str>,
+    ) -> Self {
+        Self {
+            message,
+            role: type_name::<Q>(),
+            peer,
+            expected,
+            state: type_name::<S>(),
+        }
+    }
+
+    /// The message which was received.
+    pub fn message(&self) -> &M {
+        &self.message
+    }
+
+    /// Gives back the message which was received.
+    pub fn into_message(self) -> M {
+        self.message
+    }
+
+    /// The name of the role which received the message.
+    pub fn role(&self) -> &'static str {
+        self.role
+    }
+
+    /// The name of the role which sent the message.
+    pub fn peer(&self) -> &'static str {
+        self.peer
+    }
+
+    /// The names of the label types which could have been received.
+    pub fn expected(&self) -> &[&'static str] {
+        &self.expected
+    }
+
+    /// The name of the session type the receiving role was in.
+    pub fn state(&self) -> &'static str {
+        self.state
+    }
+}
+
+impl<M> Debug for UnexpectedMessage<M> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UnexpectedMessage")
+            .field("message", &type_name::<M>())
+            .field("role", &self.role)
+            .field("peer", &self.peer)
+            .field("expected", &self.expected)
+            .field("state", &self.state)
+            .finish()
+    }
+}
+
+struct Expected<'a>(&'a [&'static str]);
+
+impl Display for Expected<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            [] => write!(f, "nothing"),
+            [label] => write!(f, "{}", label),
+            [first, labels @ ..] => {
+                write!(f, "one of {}", first)?;
+                for label in labels {
+                    write!(f, ", {}", label)?;
+                }
+
+                Ok(())
+            }
+        }
+    }
 }
 
 /// This trait represents a message to be exchanged between two participants.
 /// The generic type L is the type of the label (i.e. the content of the
 /// message).
-pub trait Message<L>: Sized {
+///
+/// The generic type K is the key which identifies the label, which is the type
+/// of the label itself unless given otherwise. Labels with distinct keys can
+/// carry the same type, so that choices such as `Ok(u32)` and `Err(u32)` are
+/// told apart by their key rather than by their content.
+pub trait Message<L, K = L>: Sized {
     /// Creates a message from a label.
     fn upcast(label: L) -> Self;
 
@@ -35,8 +224,70 @@ pub trait Message<L>: Sized {
     fn downcast(self) -> Result<L, Self>;
 }
 
+/// This trait represents a message which can tell which of its labels it
+/// contains without downcasting it, so that a branch can go straight to the
+/// matching choice, and so that labels can be told apart in logs, metrics and
+/// encodings. It is implemented by `#[derive(Message)]`, where the
+/// discriminant of each variant is its position unless given with
+/// `#[discriminant(...)]`, and for boxed messages, where it is the `TypeId` of
+/// the label.
+pub trait Labelled {
+    /// The type of the discriminants which tell the labels apart.
+    type Discriminant: PartialEq;
+
+    /// The discriminant of the label contained in the message.
+    fn discriminant(&self) -> Self::Discriminant;
+
+    /// The name of the label contained in the message, which for an enum is
+    /// the name of its variant. A boxed message is named after the type of its
+    /// label once a label of that type has been upcast, which needs `std`, and
+    /// after the box otherwise.
+    fn name(&self) -> &'static str;
+}
+
+/// The names of the labels which have been upcast into boxed messages, looked
+/// up by the `TypeId` which their discriminant already is.
+#[cfg(feature = "std")]
+static NAMES: std::sync::RwLock<alloc::collections::BTreeMap<TypeId, &'static str>> =
+    std::sync::RwLock::new(alloc::collections::BTreeMap::new());
+
+fn register<L: 'static>() {
+    #[cfg(feature = "std")]
+    {
+        let id = TypeId::of::<L>();
+        if !NAMES.read().unwrap().contains_key(&id) {
+            NAMES.write().unwrap().insert(id, type_name::<L>());
+        }
+    }
+}
+
+fn label_name(id: TypeId) -> Option<&'static str> {
+    #[cfg(feature = "std")]
+    {
+        NAMES.read().unwrap().get(&id).copied()
+    }
+
+    #[cfg(not(feature = "std"))]
+    {
+        let _ = id;
+        None
+    }
+}
+
+/// This trait represents a message which is `Labelled` and can contain the
+/// label with key `K`, giving the type and the discriminant of that label.
+pub trait Keyed<K>: Labelled {
+    /// The type of the label with key `K`, which is `K` itself unless the key
+    /// was given otherwise.
+    type Label;
+
+    /// The discriminant of the label with key `K`.
+    fn discriminant() -> Self::Discriminant;
+}
+
 impl<L: 'static> Message<L> for Box<dyn Any> {
     fn upcast(label: L) -> Self {
+        register::<L>();
         Box::new(label)
     }
 
@@ -47,6 +298,7 @@ impl<L: 'static> Message<L> for Box<dyn Any> {
 
 impl<L: marker::Send + 'static> Message<L> for Box<dyn Any + marker::Send> {
     fn upcast(label: L) -> Self {
+        register::<L>();
         Box::new(label)
     }
 
@@ -57,6 +309,7 @@ impl<L: marker::Send + 'static> Message<L> for Box<dyn Any + marker::Send> {
 
 impl<L: marker::Send + Sync + 'static> Message<L> for Box<dyn Any + marker::Send + Sync> {
     fn upcast(label: L) -> Self {
+        register::<L>();
         Box::new(label)
     }
 
@@ -65,8 +318,74 @@ impl<L: marker::Send + Sync + 'static> Message<L> for Box<dyn Any + marker::Send
     }
 }
 
+impl Labelled for Box<dyn Any> {
+    type Discriminant = TypeId;
+
+    fn discriminant(&self) -> TypeId {
+        (**self).type_id()
+    }
+
+    fn name(&self) -> &'static str {
+        label_name(Labelled::discriminant(self)).unwrap_or_else(type_name::<Self>)
+    }
+}
+
+impl<L: 'static> Keyed<L> for Box<dyn Any> {
+    type Label = L;
+
+    fn discriminant() -> TypeId {
+        TypeId::of::<L>()
+    }
+}
+
+impl Labelled for Box<dyn Any + marker::Send> {
+    type Discriminant = TypeId;
+
+    fn discriminant(&self) -> TypeId {
+        (**self).type_id()
+    }
+
+    fn name(&self) -> &'static str {
+        label_name(Labelled::discriminant(self)).unwrap_or_else(type_name::<Self>)
+    }
+}
+
+impl<L: marker::Send + 'static> Keyed<L> for Box<dyn Any + marker::Send> {
+    type Label = L;
+
+    fn discriminant() -> TypeId {
+        TypeId::of::<L>()
+    }
+}
+
+impl Labelled for Box<dyn Any + marker::Send + Sync> {
+    type Discriminant = TypeId;
+
+    fn discriminant(&self) -> TypeId {
+        (**self).type_id()
+    }
+
+    fn name(&self) -> &'static str {
+        label_name(Labelled::discriminant(self)).unwrap_or_else(type_name::<Self>)
+    }
+}
+
+impl<L: marker::Send + Sync + 'static> Keyed<L> for Box<dyn Any + marker::Send + Sync> {
+    type Label = L;
+
+    fn discriminant() -> TypeId {
+        TypeId::of::<L>()
+    }
+}
+
 pub trait Role {
     type Message;
+
+    /// Signals cancellation on every route of the role. This is called when a
+    /// session is dropped before reaching `End`, and is implemented by
+    /// `#[derive(Role)]`.
+    #[inline]
+    fn cancel(&mut self) {}
 }
 
 pub trait Route<R>: Role + Sized {
@@ -75,19 +394,98 @@ pub trait Route<R>: Role + Sized {
     fn route(&mut self) -> &mut Self::Route;
 }
 
+/// A role with a route to every member of the family of roles `R`, whose size
+/// is only known at runtime. This is implemented by `#[derive(Role)]` for
+/// fields marked with `#[family(R)]`.
+pub trait Routes<R>: Role + Sized {
+    type Route;
+
+    /// The number of members in the family.
+    fn size(&self) -> usize;
+
+    /// The route to the member of the family with the given index.
+    fn route_to(&mut self, index: usize) -> &mut Self::Route;
+}
+
+/// A member of a family of roles, which knows its own index. This is
+/// implemented by `#[derive(Role)]` for the field marked with `#[index]`.
+pub trait Indexed {
+    fn index(&self) -> usize;
+}
+
+/// Creates a member of a family from its index and its routes, which lets
+/// `#[derive(Roles)]` create members without knowing the name of their
+/// `#[index]` field. This is implemented by `#[derive(Role)]`, with the routes
+/// ordered by field name, or by position for a tuple struct.
+#[doc(hidden)]
+pub trait Member: Indexed {
+    type Routes;
+
+    fn member(index: usize, routes: Self::Routes) -> Self;
+}
+
+/// The member of the family `R` after the current role, wrapping around at
+/// the end, which is useful for rings and pipelines.
+pub struct Next<R>(PhantomData<R>);
+
+impl<Q: Routes<R> + Indexed, R> Route<Next<R>> for Q {
+    type Route = Q::Route;
+
+    fn route(&mut self) -> &mut Self::Route {
+        let index = (self.index() + 1) % self.size();
+        self.route_to(index)
+    }
+}
+
+/// The member of the family `R` before the current role, wrapping around at
+/// the start.
+pub struct Prev<R>(PhantomData<R>);
+
+impl<Q: Routes<R> + Indexed, R> Route<Prev<R>> for Q {
+    type Route = Q::Route;
+
+    fn route(&mut self) -> &mut Self::Route {
+        let size = self.size();
+        let index = (self.index() + size - 1) % size;
+        self.route_to(index)
+    }
+}
+
 /// This structure is mainly a placeholder for a `Role` and for types.
 /// Typically, each each state (in the sense of automata state) of the protocol,
 /// e.g. a `Send`, a `Receive`, etc, contains a `State`, as well as some type
 /// bounds. When an action is taken (e.g. when `send` is called on a `Send`),
 /// the `Send` will take it state and convert it into the continuation.
+///
+/// Sessions are affine: if a `State` is dropped before the protocol reaches
+/// `End`, for example because of an error or a panic, its role is cancelled so
+/// that peers fail with `ReceiveError::Cancelled` rather than waiting forever.
 pub struct State<'r, R: Role> {
     role: &'r mut R,
+    ended: bool,
+    /// The sender which the next `Race` polls first, moved past the winner of
+    /// each race so that no route is always favoured.
+    turn: usize,
 }
 
 impl<'r, R: Role> State<'r, R> {
     #[inline]
     fn new(role: &'r mut R) -> Self {
-        Self { role }
+        Self {
+            role,
+            ended: false,
+            turn: 0,
+        }
+    }
+}
+
+impl<'r, R: Role> Drop for State<'r, R> {
+    fn drop(&mut self) {
+        if !self.ended {
+            #[cfg(feature = "tracing")]
+            tracing::debug!(role = type_name::<R>(), "cancel");
+            self.role.cancel();
+        }
     }
 }
 
@@ -97,7 +495,7 @@ pub trait FromState<'r> {
     fn from_state(state: State<'r, Self::Role>) -> Self;
 }
 
-pub trait Session<'r>: FromState<'r> + private::Session {}
+pub trait Session<'r>: FromState<'r> + private::Session<'r> {}
 
 pub trait IntoSession<'r>: FromState<'r> {
     type Session: Session<'r, Role = Self::Role>;
@@ -114,16 +512,24 @@ impl<'r, R: Role> FromState<'r> for End<'r, R> {
     type Role = R;
 
     #[inline]
-    fn from_state(state: State<'r, Self::Role>) -> Self {
+    fn from_state(mut state: State<'r, Self::Role>) -> Self {
+        state.ended = true;
         Self { _state: state }
     }
 }
 
-impl<'r, R: Role> private::Session for End<'r, R> {}
+impl<'r, R: Role> private::Session<'r> for End<'r, R> {
+    #[inline]
+    fn into_state(self) -> State<'r, Self::Role> {
+        self._state
+    }
+}
 
 impl<'r, R: Role> Session<'r> for End<'r, R> {}
 
-/// This structure represents a protocol which next action is to send.
+/// This structure represents a protocol which next action is to send. The
+/// label is named by its key `L`, whose label type is given by `Keyed`, so
+/// that labels which carry the same type can still be told apart.
 pub struct Send<'q, Q: Role, R, L, S: FromState<'q, Role = Q>> {
     state: State<'q, Q>,
     phantom: PhantomData<(R, L, S)>,
@@ -143,21 +549,29 @@ impl<'q, Q: Role, R, L, S: FromState<'q, Role = Q>> FromState<'q> for Send<'q, Q
 
 impl<'q, Q: Route<R>, R, L, S: FromState<'q, Role = Q>> Send<'q, Q, R, L, S>
 where
-    Q::Message: Message<L>,
+    Q::Message: Keyed<L> + Message<<Q::Message as Keyed<L>>::Label, L>,
     Q::Route: Sink<Q::Message> + Unpin,
 {
     #[inline]
-    pub async fn send(self, label: L) -> Result<S, SendError<Q, R>> {
-        self.state.role.route().send(Message::upcast(label)).await?;
+    pub async fn send(self, label: <Q::Message as Keyed<L>>::Label) -> Result<S, SendError<Q, R>> {
+        let message = Message::<_, L>::upcast(label);
+        self.state.role.route().send(message).await?;
+        trace!("send", R, type_name::<L>());
         Ok(FromState::from_state(self.state))
     }
 }
 
-impl<'q, Q: Role, R, L, S: FromState<'q, Role = Q>> private::Session for Send<'q, Q, R, L, S> {}
+impl<'q, Q: Role, R, L, S: FromState<'q, Role = Q>> private::Session<'q> for Send<'q, Q, R, L, S> {
+    #[inline]
+    fn into_state(self) -> State<'q, Self::Role> {
+        self.state
+    }
+}
 
 impl<'q, Q: Role, R, L, S: FromState<'q, Role = Q>> Session<'q> for Send<'q, Q, R, L, S> {}
 
-/// This structure represents a protocol which next action is to receive .
+/// This structure represents a protocol which next action is to receive. The
+/// label is named by its key `L`, as with `Send`.
 pub struct Receive<'q, Q: Role, R, L, S: FromState<'q, Role = Q>> {
     state: State<'q, Q>,
     phantom: PhantomData<(R, L, S)>,
@@ -177,23 +591,57 @@ impl<'q, Q: Role, R, L, S: FromState<'q, Role = Q>> FromState<'q> for Receive<'q
 
 impl<'q, Q: Route<R>, R, L, S: FromState<'q, Role = Q>> Receive<'q, Q, R, L, S>
 where
-    Q::Message: Message<L>,
-    Q::Route: Stream<Item = Q::Message> + Unpin,
+    Q::Message: Keyed<L> + Message<<Q::Message as Keyed<L>>::Label, L>,
+    Q::Route: Stream<Item = Q::Message> + Cancel + Unpin,
 {
     #[inline]
-    pub async fn receive(self) -> Result<(L, S), ReceiveError> {
+    #[allow(clippy::type_complexity)]
+    pub async fn receive(
+        self,
+    ) -> Result<(<Q::Message as Keyed<L>>::Label, S), ReceiveError<Q::Message>> {
         let message = self.state.role.route().next().await;
-        let message = message.ok_or(ReceiveError::EmptyStream)?;
-        let label = message.downcast().or(Err(ReceiveError::UnexpectedType))?;
+        let message = message.ok_or_else(|| ended(self.state.role.route()))?;
+        let label = Message::<_, L>::downcast(message).map_err(Self::unexpected)?;
+        trace!("receive", R, type_name::<L>());
         Ok((label, FromState::from_state(self.state)))
     }
+
+    /// Receives like `receive`, but fails with `ReceiveError::Timeout` if the
+    /// `timeout` future completes before a message arrives.
+    #[inline]
+    #[allow(clippy::type_complexity)]
+    pub async fn receive_timeout(
+        self,
+        timeout: impl Future<Output = ()>,
+    ) -> Result<(<Q::Message as Keyed<L>>::Label, S), ReceiveError<Q::Message>> {
+        let message = next_or_timeout(self.state.role.route(), timeout).await;
+        let message = message.ok_or(ReceiveError::Timeout)?;
+        let message = message.ok_or_else(|| ended(self.state.role.route()))?;
+        let label = Message::<_, L>::downcast(message).map_err(Self::unexpected)?;
+        trace!("receive", R, type_name::<L>());
+        Ok((label, FromState::from_state(self.state)))
+    }
+
+    fn unexpected(message: Q::Message) -> ReceiveError<Q::Message> {
+        let expected = vec![type_name::<L>()];
+        let error = UnexpectedMessage::new::<Q, Self>(message, type_name::<R>(), expected);
+        ReceiveError::UnexpectedMessage(error)
+    }
 }
 
-impl<'q, Q: Role, R, L, S: FromState<'q, Role = Q>> private::Session for Receive<'q, Q, R, L, S> {}
+impl<'q, Q: Role, R, L, S: FromState<'q, Role = Q>> private::Session<'q>
+    for Receive<'q, Q, R, L, S>
+{
+    #[inline]
+    fn into_state(self) -> State<'q, Self::Role> {
+        self.state
+    }
+}
 
 impl<'q, Q: Role, R, L, S: FromState<'q, Role = Q>> Session<'q> for Receive<'q, Q, R, L, S> {}
 
-pub trait Choice<'r, L> {
+/// The continuation of a choice, identified by the key `K` of its label.
+pub trait Choice<'r, K> {
     type Session: FromState<'r>;
 }
 
@@ -226,17 +674,49 @@ where
         C::Session: FromState<'q, Role = Q>,
     {
         self.state.role.route().send(Message::upcast(label)).await?;
+        trace!("select", R, type_name::<L>());
+        Ok(FromState::from_state(self.state))
+    }
+
+    /// Selects the choice whose label has the key `K`, which is given
+    /// explicitly, as in `select_keyed::<K>(label)`, since its label may carry
+    /// the same type as the labels of other choices.
+    #[inline]
+    pub async fn select_keyed<K>(
+        self,
+        label: <Q::Message as Keyed<K>>::Label,
+    ) -> Result<<C as Choice<'q, K>>::Session, SendError<Q, R>>
+    where
+        Q::Message: Keyed<K> + Message<<Q::Message as Keyed<K>>::Label, K>,
+        C: Choice<'q, K>,
+        C::Session: FromState<'q, Role = Q>,
+    {
+        let message = Message::<_, K>::upcast(label);
+        self.state.role.route().send(message).await?;
+        trace!("select", R, type_name::<K>());
         Ok(FromState::from_state(self.state))
     }
 }
 
-impl<'q, Q: Role, R, C> private::Session for Select<'q, Q, R, C> {}
+impl<'q, Q: Role, R, C> private::Session<'q> for Select<'q, Q, R, C> {
+    #[inline]
+    fn into_state(self) -> State<'q, Self::Role> {
+        self.state
+    }
+}
 
 impl<'q, Q: Role, R, C> Session<'q> for Select<'q, Q, R, C> {}
 
 pub trait Choices<'r>: Sized {
     type Role: Role;
 
+    /// The names of the label keys of each choice, used for reporting
+    /// unexpected messages.
+    fn labels() -> Vec<&'static str>;
+
+    /// The name of the label key of this choice.
+    fn label(&self) -> &'static str;
+
     fn downcast(
         state: State<'r, Self::Role>,
         message: <Self::Role as Role>::Message,
@@ -262,21 +742,349 @@ impl<'q, Q: Role, R, C> FromState<'q> for Branch<'q, Q, R, C> {
 
 impl<'q, Q: Route<R>, R, C: Choices<'q, Role = Q>> Branch<'q, Q, R, C>
 where
-    Q::Route: Stream<Item = Q::Message> + Unpin,
+    Q::Route: Stream<Item = Q::Message> + Cancel + Unpin,
 {
     #[inline]
-    pub async fn branch(self) -> Result<C, ReceiveError> {
+    pub async fn branch(self) -> Result<C, ReceiveError<Q::Message>> {
         let message = self.state.role.route().next().await;
-        let message = message.ok_or(ReceiveError::EmptyStream)?;
-        let choice = C::downcast(self.state, message);
-        choice.or(Err(ReceiveError::UnexpectedType))
+        let message = message.ok_or_else(|| ended(self.state.role.route()))?;
+        let choice = C::downcast(self.state, message).map_err(Self::unexpected)?;
+        trace!("branch", R, Choices::label(&choice));
+        Ok(choice)
+    }
+
+    /// Branches like `branch`, but fails with `ReceiveError::Timeout` if the
+    /// `timeout` future completes before a message arrives.
+    #[inline]
+    pub async fn branch_timeout(
+        self,
+        timeout: impl Future<Output = ()>,
+    ) -> Result<C, ReceiveError<Q::Message>> {
+        let message = next_or_timeout(self.state.role.route(), timeout).await;
+        let message = message.ok_or(ReceiveError::Timeout)?;
+        let message = message.ok_or_else(|| ended(self.state.role.route()))?;
+        let choice = C::downcast(self.state, message).map_err(Self::unexpected)?;
+        trace!("branch", R, Choices::label(&choice));
+        Ok(choice)
+    }
+
+    fn unexpected(message: Q::Message) -> ReceiveError<Q::Message> {
+        let error = UnexpectedMessage::new::<Q, Self>(message, type_name::<R>(), C::labels());
+        ReceiveError::UnexpectedMessage(error)
     }
 }
 
-impl<'q, Q: Role, R, C> private::Session for Branch<'q, Q, R, C> {}
+impl<'q, Q: Role, R, C> private::Session<'q> for Branch<'q, Q, R, C> {
+    #[inline]
+    fn into_state(self) -> State<'q, Self::Role> {
+        self.state
+    }
+}
 
 impl<'q, Q: Role, R, C> Session<'q> for Branch<'q, Q, R, C> {}
 
+/// The choices of a `Race`, where each choice may be received from a different
+/// role. These are generated by `#[session]` for enums whose variants are each
+/// tagged with the role they come from using `#[role(...)]`.
+pub trait RaceChoices<'r>: Sized {
+    type Role: Role;
+
+    /// The names of the label keys of each choice, used for reporting
+    /// unexpected messages.
+    fn labels() -> Vec<&'static str>;
+
+    /// The name of the label key of this choice.
+    fn label(&self) -> &'static str;
+
+    /// The number of roles which the choices are received from.
+    const SENDERS: usize;
+
+    /// The name of the role with the given index among the senders.
+    fn sender(sender: usize) -> &'static str;
+
+    /// Polls the route of each sender which has not ended, starting from the
+    /// sender with index `start`, and returns the first message to arrive along
+    /// with the index of the role it came from. A route which ends leaves its
+    /// error in `ended`, and polling only fails once every route has ended.
+    #[allow(clippy::type_complexity)]
+    fn poll_next(
+        role: &mut Self::Role,
+        start: usize,
+        ended: &mut [Option<ReceiveError<<Self::Role as Role>::Message>>],
+        cx: &mut Context<'_>,
+    ) -> Poll<
+        Result<(usize, <Self::Role as Role>::Message), ReceiveError<<Self::Role as Role>::Message>>,
+    >;
+
+    fn downcast(
+        state: State<'r, Self::Role>,
+        sender: usize,
+        message: <Self::Role as Role>::Message,
+    ) -> Result<Self, <Self::Role as Role>::Message>;
+}
+
+/// This structure represents a protocol which next action is to branch on a
+/// message from whichever of several roles sends first.
+pub struct Race<'q, Q: Role, C> {
+    state: State<'q, Q>,
+    phantom: PhantomData<C>,
+}
+
+impl<'q, Q: Role, C> FromState<'q> for Race<'q, Q, C> {
+    type Role = Q;
+
+    #[inline]
+    fn from_state(state: State<'q, Self::Role>) -> Self {
+        Self {
+            state,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<'q, Q: Role, C: RaceChoices<'q, Role = Q>> Race<'q, Q, C> {
+    #[inline]
+    pub async fn branch(mut self) -> Result<C, ReceiveError<Q::Message>> {
+        let role = &mut *self.state.role;
+        let start = self.state.turn % C::SENDERS;
+        let mut ended = (0..C::SENDERS).map(|_| None).collect::<Vec<_>>();
+        let (sender, message) =
+            future::poll_fn(|cx| C::poll_next(role, start, &mut ended, cx)).await?;
+        self.state.turn = sender + 1;
+        let choice = C::downcast(self.state, sender, message).map_err(|message| {
+            let peer = C::sender(sender);
+            let error = UnexpectedMessage::new::<Q, Self>(message, peer, C::labels());
+            ReceiveError::UnexpectedMessage(error)
+        })?;
+
+        trace!(
+            "branch",
+            peer = C::sender(sender),
+            RaceChoices::label(&choice)
+        );
+        Ok(choice)
+    }
+}
+
+impl<'q, Q: Role, C> private::Session<'q> for Race<'q, Q, C> {
+    #[inline]
+    fn into_state(self) -> State<'q, Self::Role> {
+        self.state
+    }
+}
+
+impl<'q, Q: Role, C> Session<'q> for Race<'q, Q, C> {}
+
+/// This structure represents a protocol which next action is to send a message
+/// to every member of the family `R` in order of their index.
+pub struct Scatter<'q, Q: Role, R, L, S: FromState<'q, Role = Q>> {
+    state: State<'q, Q>,
+    phantom: PhantomData<(R, L, S)>,
+}
+
+impl<'q, Q: Role, R, L, S: FromState<'q, Role = Q>> FromState<'q> for Scatter<'q, Q, R, L, S> {
+    type Role = Q;
+
+    #[inline]
+    fn from_state(state: State<'q, Self::Role>) -> Self {
+        Self {
+            state,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<'q, Q: Routes<R>, R, L, S: FromState<'q, Role = Q>> Scatter<'q, Q, R, L, S>
+where
+    Q::Message: Message<L>,
+    Q::Route: Sink<Q::Message> + Unpin,
+{
+    /// Sends `label(index)` to the member of the family with each index.
+    #[inline]
+    pub async fn send(
+        self,
+        mut label: impl FnMut(usize) -> L,
+    ) -> Result<S, <Q::Route as Sink<Q::Message>>::Error> {
+        for index in 0..self.state.role.size() {
+            let route = self.state.role.route_to(index);
+            route.send(Message::upcast(label(index))).await?;
+            trace!("send", peer = type_name::<R>(), type_name::<L>());
+        }
+
+        Ok(FromState::from_state(self.state))
+    }
+}
+
+impl<'q, Q: Role, R, L, S: FromState<'q, Role = Q>> private::Session<'q>
+    for Scatter<'q, Q, R, L, S>
+{
+    #[inline]
+    fn into_state(self) -> State<'q, Self::Role> {
+        self.state
+    }
+}
+
+impl<'q, Q: Role, R, L, S: FromState<'q, Role = Q>> Session<'q> for Scatter<'q, Q, R, L, S> {}
+
+/// This structure represents a protocol which next action is to receive a
+/// message from every member of the family `R` in order of their index.
+pub struct Gather<'q, Q: Role, R, L, S: FromState<'q, Role = Q>> {
+    state: State<'q, Q>,
+    phantom: PhantomData<(R, L, S)>,
+}
+
+impl<'q, Q: Role, R, L, S: FromState<'q, Role = Q>> FromState<'q> for Gather<'q, Q, R, L, S> {
+    type Role = Q;
+
+    #[inline]
+    fn from_state(state: State<'q, Self::Role>) -> Self {
+        Self {
+            state,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<'q, Q: Routes<R>, R, L, S: FromState<'q, Role = Q>> Gather<'q, Q, R, L, S>
+where
+    Q::Message: Message<L>,
+    Q::Route: Stream<Item = Q::Message> + Cancel + Unpin,
+{
+    /// Receives one label from each member of the family, ordered by index.
+    #[inline]
+    pub async fn receive(self) -> Result<(Vec<L>, S), ReceiveError<Q::Message>> {
+        let size = self.state.role.size();
+        let mut labels = Vec::with_capacity(size);
+        for index in 0..size {
+            let route = self.state.role.route_to(index);
+            let message = route.next().await;
+            let message = message.ok_or_else(|| ended(self.state.role.route_to(index)))?;
+            labels.push(message.downcast().map_err(|message| {
+                let expected = vec![type_name::<L>()];
+                let error = UnexpectedMessage::new::<Q, Self>(message, type_name::<R>(), expected);
+                ReceiveError::UnexpectedMessage(error)
+            })?);
+            trace!("receive", peer = type_name::<R>(), type_name::<L>());
+        }
+
+        Ok((labels, FromState::from_state(self.state)))
+    }
+}
+
+impl<'q, Q: Role, R, L, S: FromState<'q, Role = Q>> private::Session<'q>
+    for Gather<'q, Q, R, L, S>
+{
+    #[inline]
+    fn into_state(self) -> State<'q, Self::Role> {
+        self.state
+    }
+}
+
+impl<'q, Q: Role, R, L, S: FromState<'q, Role = Q>> Session<'q> for Gather<'q, Q, R, L, S> {}
+
+/// The outcome of a `Timeout`, which is either one of the choices received
+/// before the deadline or the continuation taken once the deadline passed.
+pub enum Timed<C, S> {
+    Received(C),
+    Elapsed(S),
+}
+
+/// The continuation of a `Timeout` once its deadline passes, which is given by
+/// its choices so that the continuation of every session type is its last
+/// argument. This is generated by `#[session]` for enums tagged with
+/// `#[timeout(...)]`.
+pub trait Elapse<'r> {
+    type Session: FromState<'r>;
+}
+
+/// This structure represents a protocol which next action is to branch on a
+/// message from `R`, unless a deadline passes first, in which case the
+/// protocol continues as given by the `Elapse` impl of `C` without receiving
+/// anything.
+pub struct Timeout<'q, Q: Role, R, C> {
+    state: State<'q, Q>,
+    phantom: PhantomData<(R, C)>,
+}
+
+impl<'q, Q: Role, R, C> FromState<'q> for Timeout<'q, Q, R, C> {
+    type Role = Q;
+
+    #[inline]
+    fn from_state(state: State<'q, Self::Role>) -> Self {
+        Self {
+            state,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<'q, Q: Route<R>, R, C> Timeout<'q, Q, R, C>
+where
+    Q::Route: Stream<Item = Q::Message> + Cancel + Unpin,
+    C: Choices<'q, Role = Q> + Elapse<'q>,
+    <C as Elapse<'q>>::Session: FromState<'q, Role = Q>,
+{
+    /// Waits for a choice from `R` until the `timeout` future completes.
+    #[inline]
+    #[allow(clippy::type_complexity)]
+    pub async fn branch(
+        self,
+        timeout: impl Future<Output = ()>,
+    ) -> Result<Timed<C, <C as Elapse<'q>>::Session>, ReceiveError<Q::Message>> {
+        let message = next_or_timeout(self.state.role.route(), timeout).await;
+        let message = match message {
+            Some(message) => message.ok_or_else(|| ended(self.state.role.route()))?,
+            None => {
+                trace!("timeout", R, "timeout");
+                return Ok(Timed::Elapsed(FromState::from_state(self.state)));
+            }
+        };
+
+        let choice = C::downcast(self.state, message).map_err(|message| {
+            let error = UnexpectedMessage::new::<Q, Self>(message, type_name::<R>(), C::labels());
+            ReceiveError::UnexpectedMessage(error)
+        })?;
+
+        trace!("branch", R, Choices::label(&choice));
+        Ok(Timed::Received(choice))
+    }
+}
+
+impl<'q, Q: Role, R, C> private::Session<'q> for Timeout<'q, Q, R, C> {
+    #[inline]
+    fn into_state(self) -> State<'q, Self::Role> {
+        self.state
+    }
+}
+
+impl<'q, Q: Role, R, C> Session<'q> for Timeout<'q, Q, R, C> {}
+
+/// The error for a route whose stream has ended, depending on whether the peer
+/// cancelled or the route failed.
+#[inline]
+fn ended<M>(route: &mut (impl Cancel + ?Sized)) -> ReceiveError<M> {
+    match route.ended() {
+        Ended::Closed => ReceiveError::EmptyStream,
+        Ended::Cancelled => ReceiveError::Cancelled,
+        Ended::Failed(error) => ReceiveError::Route(error),
+    }
+}
+
+/// Polls the next message from `route`, returning `None` if `timeout`
+/// completes first. Nothing is lost on a timeout since taking the next item of
+/// a stream is cancellation safe.
+#[inline]
+async fn next_or_timeout<T: Stream + Unpin>(
+    route: &mut T,
+    timeout: impl Future<Output = ()>,
+) -> Option<Option<T::Item>> {
+    pin_mut!(timeout);
+    match future::select(route.next(), timeout).await {
+        Either::Left((message, _)) => Some(message),
+        Either::Right(((), _)) => None,
+    }
+}
+
 #[inline]
 pub async fn session<'r, R: Role, S: FromState<'r, Role = R>, T, F>(
     role: &'r mut R,
@@ -298,9 +1106,41 @@ where
     F: Future<Output = Result<(T, End<'r, R>), E>>,
 {
     let session = FromState::from_state(State::new(role));
-    f(session).await.map(|(output, _)| output)
+    let future = f(session);
+
+    #[cfg(feature = "tracing")]
+    let future = future.instrument(span::<R>());
+
+    future.await.map(|(output, _)| output)
+}
+
+/// Creates the span which the actions of a new session are recorded in.
+#[cfg(feature = "tracing")]
+fn span<R>() -> tracing::Span {
+    let id = session_id();
+    tracing::debug_span!("session", id, role = type_name::<R>())
+}
+
+/// Numbers each session, so that the spans of concurrent sessions of the same
+/// role can be told apart.
+#[cfg(all(feature = "tracing", target_has_atomic = "64"))]
+fn session_id() -> Option<u64> {
+    static SESSIONS: AtomicU64 = AtomicU64::new(0);
+    Some(SESSIONS.fetch_add(1, Ordering::Relaxed))
+}
+
+/// Sessions are left unnumbered on targets without 64-bit atomics.
+#[cfg(all(feature = "tracing", not(target_has_atomic = "64")))]
+fn session_id() -> Option<u64> {
+    None
 }
 
 mod private {
-    pub trait Session {}
+    use crate::{FromState, State};
+
+    pub trait Session<'r>: FromState<'r> {
+        /// Gives back the state of the session, which is only used when the
+        /// session is handed off so that it is not cancelled.
+        fn into_state(self) -> State<'r, Self::Role>;
+    }
 }
@@ -0,0 +1,513 @@
+#![cfg(feature = "std")]
+
+use crate::{
+    channel::{Cancel, Ended},
+    Labelled,
+};
+use futures::{Sink, Stream};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::{self, Write},
+    pin::Pin,
+    string::{String, ToString},
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+#[cfg(feature = "net")]
+use {crate::net::Codec, std::vec::Vec};
+
+/// The upper bounds, in seconds, of the buckets which blocked receives are
+/// counted in.
+const BUCKETS: [f64; 8] = [1e-6, 1e-5, 1e-4, 1e-3, 1e-2, 1e-1, 1.0, 10.0];
+
+type Key = (&'static str, &'static str, &'static str);
+
+#[derive(Default)]
+struct Counters {
+    sent: u64,
+    received: u64,
+    sent_bytes: u64,
+    received_bytes: u64,
+    buckets: [u64; BUCKETS.len()],
+    blocked: Duration,
+}
+
+#[derive(Default)]
+struct Registry {
+    routes: BTreeSet<(&'static str, &'static str)>,
+    counters: BTreeMap<Key, Counters>,
+}
+
+impl Registry {
+    fn counters(&mut self, key: Key) -> &mut Counters {
+        self.counters.entry(key).or_default()
+    }
+}
+
+/// A registry of metrics for the routes of every role, kept separately for
+/// each local role, peer role and label.
+///
+/// For each label, it counts the messages sent and received, and the time
+/// spent blocked waiting to receive them, whether in `receive` or `branch`.
+/// Routes over the network can also have the bytes of their frames counted
+/// by wrapping their codec with `codec`, since only the codec knows how large
+/// each message is once encoded.
+/// When the routes at both ends of a link are instrumented, it also gives the
+/// number of messages which have been sent but not yet received, which is the
+/// depth of the queue for unbounded channels.
+#[derive(Clone, Default)]
+pub struct Metrics(Arc<Mutex<Registry>>);
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Wraps the route from `role` to `peer` so that its messages are counted.
+    pub fn route<T>(&self, role: &'static str, peer: &'static str, route: T) -> Instrumented<T> {
+        self.0.lock().unwrap().routes.insert((role, peer));
+        Instrumented {
+            route,
+            metrics: self.clone(),
+            role,
+            peer,
+            since: None,
+        }
+    }
+
+    /// Wraps the codec of a route from `role` to `peer` so that the bytes of
+    /// its frames are counted.
+    #[cfg(feature = "net")]
+    pub fn codec<C>(
+        &self,
+        role: &'static str,
+        peer: &'static str,
+        codec: C,
+    ) -> InstrumentedCodec<C> {
+        InstrumentedCodec {
+            codec,
+            metrics: self.clone(),
+            role,
+            peer,
+        }
+    }
+
+    /// The number of messages with `label` which `role` has sent to `peer`.
+    pub fn sent(&self, role: &'static str, peer: &'static str, label: &'static str) -> u64 {
+        let registry = self.0.lock().unwrap();
+        let counters = registry.counters.get(&(role, peer, label));
+        counters.map_or(0, |counters| counters.sent)
+    }
+
+    /// The number of messages with `label` which `role` has received from
+    /// `peer`.
+    pub fn received(&self, role: &'static str, peer: &'static str, label: &'static str) -> u64 {
+        let registry = self.0.lock().unwrap();
+        let counters = registry.counters.get(&(role, peer, label));
+        counters.map_or(0, |counters| counters.received)
+    }
+
+    /// The number of bytes in the frames of messages with `label` which `role`
+    /// has sent to `peer`.
+    pub fn sent_bytes(&self, role: &'static str, peer: &'static str, label: &'static str) -> u64 {
+        let registry = self.0.lock().unwrap();
+        let counters = registry.counters.get(&(role, peer, label));
+        counters.map_or(0, |counters| counters.sent_bytes)
+    }
+
+    /// The number of bytes in the frames of messages with `label` which `role`
+    /// has received from `peer`.
+    pub fn received_bytes(
+        &self,
+        role: &'static str,
+        peer: &'static str,
+        label: &'static str,
+    ) -> u64 {
+        let registry = self.0.lock().unwrap();
+        let counters = registry.counters.get(&(role, peer, label));
+        counters.map_or(0, |counters| counters.received_bytes)
+    }
+
+    /// The total time which `role` has spent blocked waiting to receive
+    /// messages with `label` from `peer`.
+    pub fn blocked(&self, role: &'static str, peer: &'static str, label: &'static str) -> Duration {
+        let registry = self.0.lock().unwrap();
+        let counters = registry.counters.get(&(role, peer, label));
+        counters.map_or(Duration::ZERO, |counters| counters.blocked)
+    }
+
+    /// Writes every metric in the Prometheus text exposition format.
+    pub fn export(&self) -> String {
+        let mut output = String::new();
+        self.write(&mut output).unwrap();
+        output
+    }
+
+    fn write(&self, output: &mut String) -> fmt::Result {
+        let registry = self.0.lock().unwrap();
+
+        // Counters are shared between the labels which a role sends and those
+        // it receives, so each metric only includes the labels it applies to.
+        let sent = || {
+            let counters = registry.counters.iter();
+            counters.filter(|(_, counters)| counters.sent > 0)
+        };
+        let received = || {
+            let counters = registry.counters.iter();
+            counters.filter(|(_, counters)| counters.received > 0)
+        };
+
+        let name = "rumpsteak_sent_total";
+        header(output, name, "counter", "Messages sent to a peer.")?;
+        for (&key, counters) in sent() {
+            writeln!(output, "{}{} {}", name, Labels(key, None), counters.sent)?;
+        }
+
+        let name = "rumpsteak_received_total";
+        header(output, name, "counter", "Messages received from a peer.")?;
+        for (&key, counters) in received() {
+            writeln!(
+                output,
+                "{}{} {}",
+                name,
+                Labels(key, None),
+                counters.received
+            )?;
+        }
+
+        let name = "rumpsteak_sent_bytes_total";
+        header(output, name, "counter", "Bytes of frames sent to a peer.")?;
+        for (&key, counters) in &registry.counters {
+            if counters.sent_bytes > 0 {
+                let labels = Labels(key, None);
+                writeln!(output, "{}{} {}", name, labels, counters.sent_bytes)?;
+            }
+        }
+
+        let name = "rumpsteak_received_bytes_total";
+        header(
+            output,
+            name,
+            "counter",
+            "Bytes of frames received from a peer.",
+        )?;
+        for (&key, counters) in &registry.counters {
+            if counters.received_bytes > 0 {
+                let labels = Labels(key, None);
+                writeln!(output, "{}{} {}", name, labels, counters.received_bytes)?;
+            }
+        }
+
+        let name = "rumpsteak_blocked_seconds";
+        let help = "Time blocked waiting to receive from a peer.";
+        header(output, name, "histogram", help)?;
+        for (&key, counters) in received() {
+            let mut count = 0;
+            for (bound, bucket) in BUCKETS.iter().zip(&counters.buckets) {
+                count += bucket;
+                let bound = bound.to_string();
+                let labels = Labels(key, Some(&bound));
+                writeln!(output, "{}_bucket{} {}", name, labels, count)?;
+            }
+
+            let (labels, count) = (Labels(key, None), counters.received);
+            let blocked = counters.blocked.as_secs_f64();
+            writeln!(
+                output,
+                "{}_bucket{} {}",
+                name,
+                Labels(key, Some("+Inf")),
+                count
+            )?;
+            writeln!(output, "{}_sum{} {}", name, labels, blocked)?;
+            writeln!(output, "{}_count{} {}", name, labels, count)?;
+        }
+
+        let name = "rumpsteak_queued";
+        let help = "Messages sent by a peer but not yet received.";
+        header(output, name, "gauge", help)?;
+        for (&(peer, role, label), counters) in sent() {
+            // Messages are only known to be received if the route of the role
+            // they were sent to is also instrumented.
+            if !registry.routes.contains(&(role, peer)) {
+                continue;
+            }
+
+            let received = registry.counters.get(&(role, peer, label));
+            let received = received.map_or(0, |counters| counters.received);
+            let queued = counters.sent.saturating_sub(received);
+            writeln!(
+                output,
+                "{}{} {}",
+                name,
+                Labels((role, peer, label), None),
+                queued
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+fn header(output: &mut String, name: &str, kind: &str, help: &str) -> fmt::Result {
+    writeln!(output, "# HELP {} {}", name, help)?;
+    writeln!(output, "# TYPE {} {}", name, kind)
+}
+
+/// The labels of a metric, escaped as Prometheus expects, along with the upper
+/// bound of a histogram bucket.
+struct Labels<'a>(Key, Option<&'a str>);
+
+impl fmt::Display for Labels<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (role, peer, label) = self.0;
+        write!(f, "{{role=\"{}\",peer=\"{}\",", Escape(role), Escape(peer))?;
+        write!(f, "label=\"{}\"", Escape(label))?;
+        if let Some(bound) = self.1 {
+            write!(f, ",le=\"{}\"", bound)?;
+        }
+
+        f.write_char('}')
+    }
+}
+
+struct Escape(&'static str);
+
+impl fmt::Display for Escape {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for c in self.0.chars() {
+            match c {
+                '\\' => f.write_str("\\\\")?,
+                '"' => f.write_str("\\\"")?,
+                '\n' => f.write_str("\\n")?,
+                c => f.write_char(c)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A route whose messages are counted by `Metrics`.
+pub struct Instrumented<T> {
+    route: T,
+    metrics: Metrics,
+    role: &'static str,
+    peer: &'static str,
+    since: Option<Instant>,
+}
+
+impl<T> Instrumented<T> {
+    pub fn get_ref(&self) -> &T {
+        &self.route
+    }
+
+    pub fn into_inner(self) -> T {
+        self.route
+    }
+}
+
+impl<M: Labelled, T: Sink<M> + Unpin> Sink<M> for Instrumented<T> {
+    type Error = T::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.get_mut().route).poll_ready(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, message: M) -> Result<(), Self::Error> {
+        let this = self.get_mut();
+        let label = message.name();
+        Pin::new(&mut this.route).start_send(message)?;
+
+        let mut registry = this.metrics.0.lock().unwrap();
+        registry.counters((this.role, this.peer, label)).sent += 1;
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.get_mut().route).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.get_mut().route).poll_close(cx)
+    }
+}
+
+impl<T: Stream + Unpin> Stream for Instrumented<T>
+where
+    T::Item: Labelled,
+{
+    type Item = T::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let message = match Pin::new(&mut this.route).poll_next(cx) {
+            Poll::Ready(message) => message,
+            Poll::Pending => {
+                this.since.get_or_insert_with(Instant::now);
+                return Poll::Pending;
+            }
+        };
+
+        // The time spent blocked is put down to the label which was finally
+        // received, since that is the step of the protocol being waited on.
+        let blocked = this
+            .since
+            .take()
+            .map_or(Duration::ZERO, |since| since.elapsed());
+        if let Some(message) = &message {
+            let mut registry = this.metrics.0.lock().unwrap();
+            let counters = registry.counters((this.role, this.peer, message.name()));
+            counters.received += 1;
+            counters.blocked += blocked;
+
+            let seconds = blocked.as_secs_f64();
+            if let Some(i) = BUCKETS.iter().position(|&bound| seconds <= bound) {
+                counters.buckets[i] += 1;
+            }
+        }
+
+        Poll::Ready(message)
+    }
+}
+
+impl<T: Cancel> Cancel for Instrumented<T> {
+    fn cancel(&mut self) {
+        self.route.cancel();
+    }
+
+    fn ended(&mut self) -> Ended {
+        self.route.ended()
+    }
+}
+
+/// A codec whose frames are counted by `Metrics`.
+#[cfg(feature = "net")]
+#[derive(Clone)]
+pub struct InstrumentedCodec<C> {
+    codec: C,
+    metrics: Metrics,
+    role: &'static str,
+    peer: &'static str,
+}
+
+#[cfg(feature = "net")]
+impl<C> InstrumentedCodec<C> {
+    pub fn get_ref(&self) -> &C {
+        &self.codec
+    }
+
+    pub fn into_inner(self) -> C {
+        self.codec
+    }
+}
+
+#[cfg(feature = "net")]
+impl<M: Labelled, C: Codec<M>> Codec<M> for InstrumentedCodec<C> {
+    type Error = C::Error;
+
+    fn encode(&mut self, message: &M, buffer: &mut Vec<u8>) -> Result<(), Self::Error> {
+        let start = buffer.len();
+        self.codec.encode(message, buffer)?;
+
+        let mut registry = self.metrics.0.lock().unwrap();
+        let counters = registry.counters((self.role, self.peer, message.name()));
+        counters.sent_bytes += (buffer.len() - start) as u64;
+        Ok(())
+    }
+
+    fn decode(&mut self, frame: &[u8]) -> Result<M, Self::Error> {
+        let message = self.codec.decode(frame)?;
+
+        let mut registry = self.metrics.0.lock().unwrap();
+        let counters = registry.counters((self.role, self.peer, message.name()));
+        counters.received_bytes += frame.len() as u64;
+        Ok(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Message;
+    use futures::{channel::mpsc, executor, SinkExt};
+    use std::{any::Any, boxed::Box};
+    #[cfg(feature = "net")]
+    use std::{io, vec};
+
+    #[test]
+    fn boxed() {
+        let metrics = Metrics::new();
+        let (sender, _receiver) = mpsc::unbounded::<Box<dyn Any + Send>>();
+        let mut route = metrics.route("A", "B", sender);
+
+        executor::block_on(async {
+            route.send(Message::upcast(1u32)).await.unwrap();
+            route.send(Message::upcast(1u64)).await.unwrap();
+            route.send(Message::upcast(2u64)).await.unwrap();
+        });
+
+        assert_eq!(metrics.sent("A", "B", "u32"), 1);
+        assert_eq!(metrics.sent("A", "B", "u64"), 2);
+    }
+
+    /// A message whose only label is its raw bytes.
+    #[cfg(feature = "net")]
+    struct Raw(Vec<u8>);
+
+    #[cfg(feature = "net")]
+    impl Labelled for Raw {
+        type Discriminant = u32;
+
+        fn discriminant(&self) -> u32 {
+            0
+        }
+
+        fn name(&self) -> &'static str {
+            "Raw"
+        }
+    }
+
+    #[cfg(feature = "net")]
+    struct Identity;
+
+    #[cfg(feature = "net")]
+    impl Codec<Raw> for Identity {
+        type Error = io::Error;
+
+        fn encode(&mut self, message: &Raw, buffer: &mut Vec<u8>) -> Result<(), Self::Error> {
+            buffer.extend_from_slice(&message.0);
+            Ok(())
+        }
+
+        fn decode(&mut self, frame: &[u8]) -> Result<Raw, Self::Error> {
+            Ok(Raw(frame.to_vec()))
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "net")]
+    fn bytes() {
+        let metrics = Metrics::new();
+        let mut codec = metrics.codec("A", "B", Identity);
+
+        // Only the bytes of the frame are counted, not those already in the
+        // buffer.
+        let mut buffer = vec![0];
+        codec.encode(&Raw(vec![1, 2, 3]), &mut buffer).unwrap();
+        codec.decode(&[4, 5]).unwrap();
+
+        assert_eq!(metrics.sent_bytes("A", "B", "Raw"), 3);
+        assert_eq!(metrics.received_bytes("A", "B", "Raw"), 2);
+        assert_eq!(metrics.sent("A", "B", "Raw"), 0);
+
+        let export = metrics.export();
+        assert!(
+            export.contains("rumpsteak_sent_bytes_total{role=\"A\",peer=\"B\",label=\"Raw\"} 3")
+        );
+        assert!(export
+            .contains("rumpsteak_received_bytes_total{role=\"A\",peer=\"B\",label=\"Raw\"} 2"));
+    }
+}
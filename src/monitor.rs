@@ -0,0 +1,361 @@
+#![cfg(feature = "monitor")]
+
+use crate::channel::{Cancel, Ended};
+use futures::{Sink, Stream};
+use rumpsteak_fsm::{Action, Fsm, Message, StateIndex, Transition};
+use std::{
+    any::type_name,
+    boxed::Box,
+    fmt::{self, Debug, Display, Formatter},
+    marker,
+    pin::Pin,
+    string::{String, ToString},
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    vec::Vec,
+};
+use thiserror::Error;
+
+/// A message which the FSM does not allow from the state the monitor is in.
+#[derive(Clone, Debug, Error)]
+#[error(
+    "transition {transition} is not allowed from state {state}, expected {}",
+    Expected(expected)
+)]
+pub struct Violation {
+    state: usize,
+    transition: String,
+    expected: Vec<String>,
+}
+
+impl Violation {
+    /// The index of the FSM state in which the violation happened.
+    pub fn state(&self) -> usize {
+        self.state
+    }
+
+    /// The offending transition.
+    pub fn transition(&self) -> &str {
+        &self.transition
+    }
+
+    /// The transitions which the FSM allows from the state.
+    pub fn expected(&self) -> &[String] {
+        &self.expected
+    }
+}
+
+struct Expected<'a>(&'a [String]);
+
+impl Display for Expected<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            [] => write!(f, "no more transitions"),
+            [transition] => write!(f, "{}", transition),
+            [first, transitions @ ..] => {
+                write!(f, "one of {}", first)?;
+                for transition in transitions {
+                    write!(f, ", {}", transition)?;
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+/// A received message which the FSM does not allow, which is given back along
+/// with the violation. Receiving it fails with `ReceiveError::Route`, whose
+/// error can be downcast to this type.
+#[derive(Error)]
+#[error("rejected a received message: {violation}")]
+pub struct Rejected<M> {
+    violation: Violation,
+    message: M,
+}
+
+impl<M> Rejected<M> {
+    /// The violation which receiving the message caused.
+    pub fn violation(&self) -> &Violation {
+        &self.violation
+    }
+
+    /// The message which was received.
+    pub fn message(&self) -> &M {
+        &self.message
+    }
+
+    /// Gives back the message which was received.
+    pub fn into_message(self) -> M {
+        self.message
+    }
+}
+
+impl<M> Debug for Rejected<M> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Rejected")
+            .field("violation", &self.violation)
+            .field("message", &type_name::<M>())
+            .finish()
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum MonitorError<E> {
+    #[error(transparent)]
+    Violation(Violation),
+    #[error(transparent)]
+    Route(E),
+}
+
+type Label<M, N> = Box<dyn Fn(&M) -> N + marker::Send>;
+
+struct Inner<R, N, E, M> {
+    fsm: Fsm<R, N, E>,
+    state: StateIndex,
+    violation: Option<Violation>,
+    label: Label<M, N>,
+}
+
+impl<R, N, E, M> Inner<R, N, E, M>
+where
+    R: Clone + Display + Eq,
+    N: Display + Eq,
+    E: Display,
+{
+    fn step(&mut self, role: &R, action: Action, message: &M) -> Result<(), Violation> {
+        if let Some(violation) = &self.violation {
+            return Err(violation.clone());
+        }
+
+        let label = (self.label)(message);
+        let mut transitions = self.fsm.transitions_from(self.state);
+        let next = transitions.find(|(_, transition)| {
+            transition.role == role
+                && transition.action == action
+                && *transition.message.label() == label
+        });
+
+        if let Some((next, _)) = next {
+            self.state = next;
+            return Ok(());
+        }
+
+        let transition =
+            Transition::<_, _, E>::new(role.clone(), action, Message::from_label(label));
+        let expected = self.fsm.transitions_from(self.state);
+        let violation = Violation {
+            state: self.state.index(),
+            transition: transition.to_string(),
+            expected: expected
+                .map(|(_, transition)| transition.to_string())
+                .collect(),
+        };
+
+        self.violation = Some(violation.clone());
+        Err(violation)
+    }
+}
+
+/// Checks the messages crossing a role's routes against its FSM at runtime,
+/// which is useful when a peer is implemented outside of Rumpsteak where
+/// session types cannot guarantee its behaviour. The monitor is shared between
+/// all of the role's routes, which are wrapped using `Monitor::route`.
+pub struct Monitor<R, N, E, M> {
+    inner: Arc<Mutex<Inner<R, N, E, M>>>,
+}
+
+impl<R, N, E, M> Clone for Monitor<R, N, E, M> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<R, N, E, M> Monitor<R, N, E, M> {
+    /// Creates a monitor starting from the initial state of `fsm`, where
+    /// `label` gives the FSM label of each message sent or received.
+    pub fn new(fsm: Fsm<R, N, E>, label: impl Fn(&M) -> N + marker::Send + 'static) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                fsm,
+                state: Default::default(),
+                violation: None,
+                label: Box::new(label),
+            })),
+        }
+    }
+
+    /// Wraps the route to `role` so that its messages are checked.
+    pub fn route<T>(&self, role: R, route: T) -> Monitored<T, M>
+    where
+        R: Clone + Display + Eq + marker::Send + 'static,
+        N: Display + Eq + marker::Send + 'static,
+        E: Display + marker::Send + 'static,
+        M: 'static,
+    {
+        let peer = Peer {
+            role,
+            inner: self.inner.clone(),
+        };
+
+        Monitored {
+            route,
+            peer: Box::new(peer),
+            rejected: None,
+            violation: None,
+        }
+    }
+
+    /// The current state of the monitor in its FSM.
+    pub fn state(&self) -> StateIndex {
+        self.inner.lock().unwrap().state
+    }
+
+    /// The first violation which was found, if any. After a violation, every
+    /// further message is rejected.
+    pub fn violation(&self) -> Option<Violation> {
+        self.inner.lock().unwrap().violation.clone()
+    }
+
+    /// Whether the FSM has reached a state with no outgoing transitions.
+    pub fn is_finished(&self) -> bool {
+        let inner = self.inner.lock().unwrap();
+        let mut transitions = inner.fsm.transitions_from(inner.state);
+        inner.violation.is_none() && transitions.next().is_none()
+    }
+}
+
+trait Step<M>: marker::Send {
+    fn step(&self, action: Action, message: &M) -> Result<(), Violation>;
+}
+
+struct Peer<R, N, E, M> {
+    role: R,
+    inner: Arc<Mutex<Inner<R, N, E, M>>>,
+}
+
+impl<R, N, E, M> Step<M> for Peer<R, N, E, M>
+where
+    R: Clone + Display + Eq + marker::Send,
+    N: Display + Eq + marker::Send,
+    E: Display + marker::Send,
+{
+    fn step(&self, action: Action, message: &M) -> Result<(), Violation> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.step(&self.role, action, message)
+    }
+}
+
+/// A route whose messages are checked by a `Monitor`.
+///
+/// Sending a message which is not allowed fails with
+/// `MonitorError::Violation`. Receiving a message which is not allowed instead
+/// ends the stream, since a stream cannot yield errors, so that receiving
+/// fails with `ReceiveError::Route` holding the `Rejected` message. The stream
+/// stays ended from then on, so that nothing is received past the violation.
+pub struct Monitored<T, M> {
+    route: T,
+    peer: Box<dyn Step<M>>,
+    rejected: Option<Box<Rejected<M>>>,
+    violation: Option<Violation>,
+}
+
+impl<T: Unpin, M> Monitored<T, M> {
+    fn route(self: Pin<&mut Self>) -> Pin<&mut T> {
+        Pin::new(&mut self.get_mut().route)
+    }
+}
+
+impl<T: Cancel, M: marker::Send + Sync + 'static> Cancel for Monitored<T, M> {
+    fn cancel(&mut self) {
+        self.route.cancel();
+    }
+
+    fn ended(&mut self) -> Ended {
+        match (self.rejected.take(), &self.violation) {
+            (Some(rejected), _) => Ended::Failed(rejected),
+            (None, Some(violation)) => Ended::Failed(Box::new(violation.clone())),
+            (None, None) => self.route.ended(),
+        }
+    }
+}
+
+impl<T: Sink<M> + Unpin, M> Sink<M> for Monitored<T, M> {
+    type Error = MonitorError<T::Error>;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        T::poll_ready(self.route(), cx).map_err(MonitorError::Route)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: M) -> Result<(), Self::Error> {
+        let step = self.peer.step(Action::Output, &item);
+        step.map_err(MonitorError::Violation)?;
+        T::start_send(self.route(), item).map_err(MonitorError::Route)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        T::poll_flush(self.route(), cx).map_err(MonitorError::Route)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        T::poll_close(self.route(), cx).map_err(MonitorError::Route)
+    }
+}
+
+impl<T: Stream<Item = M> + Unpin, M> Stream for Monitored<T, M> {
+    type Item = M;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.violation.is_some() {
+            return Poll::Ready(None);
+        }
+
+        let item = match T::poll_next(self.as_mut().route(), cx) {
+            Poll::Ready(Some(item)) => item,
+            poll => return poll,
+        };
+
+        Poll::Ready(match self.peer.step(Action::Input, &item) {
+            Ok(()) => Some(item),
+            Err(violation) => {
+                let message = item;
+                self.violation = Some(violation.clone());
+                self.rejected = Some(Box::new(Rejected { violation, message }));
+                None
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::convert::Infallible;
+    use futures::{channel::mpsc, executor, StreamExt};
+
+    #[test]
+    fn fused() {
+        // An FSM for `C` which receives `A` from `S` and then ends.
+        let mut fsm = Fsm::<_, _, Infallible>::new("C");
+        let (start, end) = (fsm.add_state(), fsm.add_state());
+        let transition = Transition::new("S", Action::Input, Message::from_label("A"));
+        fsm.add_transition(start, end, transition).unwrap();
+
+        let monitor = Monitor::new(fsm, |&label: &u32| if label == 0 { "A" } else { "B" });
+        let (sender, receiver) = mpsc::unbounded();
+        let mut route = monitor.route("S", receiver);
+
+        sender.unbounded_send(1).unwrap();
+        sender.unbounded_send(0).unwrap();
+        assert_eq!(executor::block_on(route.next()), None);
+        assert!(matches!(route.ended(), Ended::Failed(error) if error.is::<Rejected<u32>>()));
+
+        // The stream stays ended without receiving what came after the
+        // violation.
+        assert_eq!(executor::block_on(route.next()), None);
+        assert!(matches!(route.ended(), Ended::Failed(error) if error.is::<Violation>()));
+        assert_eq!(route.route.try_recv().unwrap(), 0);
+    }
+}
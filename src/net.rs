@@ -0,0 +1,269 @@
+#![cfg(feature = "net")]
+
+pub mod mux;
+pub mod tcp;
+#[cfg(unix)]
+pub mod unix;
+
+use crate::channel::{Cancel, Ended};
+use bytes::Bytes;
+use futures::{Sink, Stream};
+use std::{
+    boxed::Box,
+    error, io,
+    marker::PhantomData,
+    pin::Pin,
+    task::{Context, Poll},
+    vec::Vec,
+};
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_util::codec::{self, LengthDelimitedCodec};
+
+#[cfg(any(feature = "bincode", feature = "ciborium", feature = "serde_json"))]
+use serde::{de::DeserializeOwned, Serialize};
+
+/// The error from a route over a byte stream, which is either from the stream
+/// itself or from encoding or decoding a message.
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("failed to encode or decode message")]
+    Codec(#[source] Box<dyn error::Error + Send + Sync>),
+    #[error("route was cancelled")]
+    Cancelled,
+}
+
+/// Converts messages to and from the bytes of a single frame.
+pub trait Codec<M> {
+    type Error: error::Error + Send + Sync + 'static;
+
+    fn encode(&mut self, message: &M, buffer: &mut Vec<u8>) -> Result<(), Self::Error>;
+
+    fn decode(&mut self, frame: &[u8]) -> Result<M, Self::Error>;
+}
+
+/// Encodes messages with `bincode` through their `serde` implementations.
+#[cfg(feature = "bincode")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Bincode;
+
+#[cfg(feature = "bincode")]
+impl<M: Serialize + DeserializeOwned> Codec<M> for Bincode {
+    type Error = bincode::Error;
+
+    fn encode(&mut self, message: &M, buffer: &mut Vec<u8>) -> Result<(), Self::Error> {
+        bincode::serialize_into(buffer, message)
+    }
+
+    fn decode(&mut self, frame: &[u8]) -> Result<M, Self::Error> {
+        bincode::deserialize(frame)
+    }
+}
+
+/// Encodes messages as JSON through their `serde` implementations.
+#[cfg(feature = "serde_json")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Json;
+
+#[cfg(feature = "serde_json")]
+impl<M: Serialize + DeserializeOwned> Codec<M> for Json {
+    type Error = serde_json::Error;
+
+    fn encode(&mut self, message: &M, buffer: &mut Vec<u8>) -> Result<(), Self::Error> {
+        serde_json::to_writer(buffer, message)
+    }
+
+    fn decode(&mut self, frame: &[u8]) -> Result<M, Self::Error> {
+        serde_json::from_slice(frame)
+    }
+}
+
+/// Encodes messages as CBOR through their `serde` implementations.
+#[cfg(feature = "ciborium")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Cbor;
+
+/// The error from encoding or decoding a message as CBOR.
+#[cfg(feature = "ciborium")]
+#[derive(Debug, Error)]
+pub enum CborError {
+    #[error(transparent)]
+    Encode(#[from] ciborium::ser::Error<io::Error>),
+    #[error(transparent)]
+    Decode(#[from] ciborium::de::Error<io::Error>),
+}
+
+#[cfg(feature = "ciborium")]
+impl<M: Serialize + DeserializeOwned> Codec<M> for Cbor {
+    type Error = CborError;
+
+    fn encode(&mut self, message: &M, buffer: &mut Vec<u8>) -> Result<(), Self::Error> {
+        Ok(ciborium::ser::into_writer(message, buffer)?)
+    }
+
+    fn decode(&mut self, frame: &[u8]) -> Result<M, Self::Error> {
+        Ok(ciborium::de::from_reader(frame)?)
+    }
+}
+
+/// A route which carries messages of type `M` over the byte stream `T`, where
+/// each message is encoded by `C` into a frame prefixed with its length.
+///
+/// The route both sends and receives, so it can be used directly as the field
+/// of a role without wrapping it in `Bidirectional`.
+pub struct Framed<T, M, C> {
+    inner: Option<codec::Framed<T, LengthDelimitedCodec>>,
+    codec: C,
+    error: Option<Error>,
+    phantom: PhantomData<fn(M) -> M>,
+}
+
+impl<T: AsyncRead + AsyncWrite, M, C: Codec<M>> Framed<T, M, C> {
+    pub fn new(io: T, codec: C) -> Self {
+        Self {
+            inner: Some(codec::Framed::new(io, LengthDelimitedCodec::new())),
+            codec,
+            error: None,
+            phantom: PhantomData,
+        }
+    }
+
+    pub fn get_ref(&self) -> Option<&T> {
+        self.inner.as_ref().map(codec::Framed::get_ref)
+    }
+}
+
+impl<T: Unpin, M, C> Framed<T, M, C> {
+    fn inner(&mut self) -> Result<Pin<&mut codec::Framed<T, LengthDelimitedCodec>>, Error> {
+        self.inner.as_mut().map(Pin::new).ok_or(Error::Cancelled)
+    }
+}
+
+impl<T: AsyncWrite + Unpin, M, C: Codec<M> + Unpin> Sink<M> for Framed<T, M, C> {
+    type Error = Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Sink::<Bytes>::poll_ready(self.get_mut().inner()?, cx).map_err(From::from)
+    }
+
+    fn start_send(self: Pin<&mut Self>, message: M) -> Result<(), Self::Error> {
+        let this = self.get_mut();
+        let mut frame = Vec::new();
+        this.codec
+            .encode(&message, &mut frame)
+            .map_err(|error| Error::Codec(error.into()))?;
+        this.inner()?
+            .start_send(Bytes::from(frame))
+            .map_err(From::from)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Sink::<Bytes>::poll_flush(self.get_mut().inner()?, cx).map_err(From::from)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Sink::<Bytes>::poll_close(self.get_mut().inner()?, cx).map_err(From::from)
+    }
+}
+
+/// Any error while receiving ends the stream, and is kept so that receiving
+/// fails with `ReceiveError::Route` holding it, while a stream closed by the
+/// peer fails with `ReceiveError::EmptyStream`. The error also closes the
+/// route, since the frames after one which failed to decode cannot be trusted
+/// to line up with the messages the peer sent.
+impl<T: AsyncRead + Unpin, M, C: Codec<M> + Unpin> Stream for Framed<T, M, C> {
+    type Item = M;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let inner = match &mut this.inner {
+            Some(inner) => Pin::new(inner),
+            None => return Poll::Ready(None),
+        };
+
+        let result = match futures::ready!(inner.poll_next(cx)) {
+            Some(Ok(frame)) => this
+                .codec
+                .decode(&frame)
+                .map_err(|error| Error::Codec(error.into())),
+            Some(Err(error)) => Err(error.into()),
+            None => return Poll::Ready(None),
+        };
+
+        Poll::Ready(match result {
+            Ok(message) => Some(message),
+            Err(error) => {
+                this.inner = None;
+                this.error = Some(error);
+                None
+            }
+        })
+    }
+}
+
+/// Cancelling closes the byte stream, which the peer cannot tell apart from
+/// the connection being closed, so it sees the end of its stream.
+impl<T, M, C> Cancel for Framed<T, M, C> {
+    fn cancel(&mut self) {
+        self.inner = None;
+    }
+
+    fn ended(&mut self) -> Ended {
+        match self.error.take() {
+            Some(error) => Ended::Failed(Box::new(error)),
+            None => Ended::Closed,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{executor, SinkExt, StreamExt};
+    use tokio::io;
+
+    /// Sends each byte as a frame of its own, where the frame `0` fails to
+    /// decode.
+    struct Byte;
+
+    impl Codec<u8> for Byte {
+        type Error = io::Error;
+
+        fn encode(&mut self, &message: &u8, buffer: &mut Vec<u8>) -> Result<(), Self::Error> {
+            buffer.push(message);
+            Ok(())
+        }
+
+        fn decode(&mut self, frame: &[u8]) -> Result<u8, Self::Error> {
+            match frame {
+                [0] => Err(io::ErrorKind::InvalidData.into()),
+                &[message] => Ok(message),
+                _ => Err(io::ErrorKind::UnexpectedEof.into()),
+            }
+        }
+    }
+
+    #[test]
+    fn decode_error_closes() {
+        let (left, right) = io::duplex(64);
+        let (mut sender, mut receiver) = (Framed::new(left, Byte), Framed::new(right, Byte));
+
+        executor::block_on(async {
+            for message in [1, 0, 2] {
+                sender.feed(message).await.unwrap();
+            }
+
+            sender.flush().await.unwrap();
+            assert_eq!(receiver.next().await, Some(1));
+            assert_eq!(receiver.next().await, None);
+            assert!(matches!(receiver.ended(), Ended::Failed(error) if error.is::<Error>()));
+
+            // The frame after the one which failed is never received.
+            assert_eq!(receiver.next().await, None);
+            assert!(matches!(receiver.ended(), Ended::Closed));
+            assert!(receiver.get_ref().is_none());
+        });
+    }
+}
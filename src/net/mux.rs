@@ -0,0 +1,339 @@
+use super::{Codec, Error};
+use crate::channel::{Cancel, Ended};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use futures::{
+    channel::mpsc::{self, UnboundedReceiver, UnboundedSender},
+    future::{try_join, try_join_all},
+    Sink, SinkExt, Stream, StreamExt,
+};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+    vec::Vec,
+};
+use tokio::io::{AsyncRead, AsyncWrite, ReadHalf, WriteHalf};
+use tokio_util::codec::{FramedRead, FramedWrite, LengthDelimitedCodec};
+
+/// Identifies a role among those sharing connections through a hub.
+pub type Id = u32;
+
+/// The identity which a hub gives itself when greeting each role.
+pub const HUB: Id = Id::MAX;
+
+const MESSAGE: u8 = 0;
+const CLOSE: u8 = 1;
+
+/// A message from a virtual route waiting to be written to the connection,
+/// where `None` tells the peer that the route was cancelled.
+type Outgoing<M> = (Id, Option<M>);
+
+/// Builds the virtual routes of a role which all share a single connection,
+/// either directly to a peer or to a hub which forwards frames between roles.
+///
+/// Each frame is tagged with the roles it is from and to, and since the frames
+/// of a connection stay in order, so do the messages between each pair of
+/// roles, which asynchronous subtyping relies on. A frame which is not for
+/// this role is never delivered, and fails `run` instead.
+///
+/// The queues of the virtual routes are unbounded. A single task reads the
+/// frames of every route, so if a bounded queue were full, the frames for
+/// every other route would wait behind it. A role which is waiting on another
+/// route would then deadlock. The outgoing queue is unbounded so that
+/// cancelling a route, which cannot wait, can always queue its frame.
+pub struct Mux<M> {
+    local: Id,
+    sender: UnboundedSender<Outgoing<M>>,
+    receiver: UnboundedReceiver<Outgoing<M>>,
+    routes: HashMap<Id, UnboundedSender<Option<M>>>,
+}
+
+impl<M> Mux<M> {
+    pub fn new(local: Id) -> Self {
+        let (sender, receiver) = mpsc::unbounded();
+        Self {
+            local,
+            sender,
+            receiver,
+            routes: HashMap::new(),
+        }
+    }
+
+    /// Creates the virtual route to `peer`.
+    pub fn route(&mut self, peer: Id) -> MuxRoute<M> {
+        assert_ne!(peer, self.local, "cannot route to self");
+        let (sender, receiver) = mpsc::unbounded();
+        let duplicate = self.routes.insert(peer, sender).is_some();
+        assert!(!duplicate, "duplicate route to {}", peer);
+
+        MuxRoute {
+            peer,
+            sender: Some(self.sender.clone()),
+            receiver,
+            cancelled: false,
+        }
+    }
+
+    /// Drives the connection `io`, writing the messages sent on the virtual
+    /// routes and passing on the messages received for them. This completes
+    /// once every route has been dropped and the other end has closed the
+    /// connection.
+    pub async fn run<T: AsyncRead + AsyncWrite, C: Codec<M>>(
+        self,
+        io: T,
+        codec: C,
+    ) -> Result<(), Error> {
+        let Self {
+            local,
+            sender,
+            mut receiver,
+            routes,
+        } = self;
+        drop(sender);
+
+        let (mut read, mut write) = split(io);
+        write.send(hello(local)).await?;
+        greeting(&mut read).await?;
+
+        // Frames are encoded and decoded within the same task and the codec is
+        // never borrowed across an await, so it can be shared.
+        let codec = RefCell::new(codec);
+
+        let writer = async {
+            while let Some((peer, message)) = receiver.next().await {
+                let mut frame = BytesMut::new();
+                frame.put_u32(local);
+                frame.put_u32(peer);
+                match message {
+                    Some(message) => {
+                        let mut payload = Vec::new();
+                        let result = codec.borrow_mut().encode(&message, &mut payload);
+                        result.map_err(|error| Error::Codec(error.into()))?;
+
+                        frame.put_u8(MESSAGE);
+                        frame.put_slice(&payload);
+                    }
+                    None => frame.put_u8(CLOSE),
+                }
+
+                write.send(frame.freeze()).await?;
+            }
+
+            SinkExt::<Bytes>::close(&mut write).await?;
+            Ok(())
+        };
+
+        let reader = async {
+            while let Some(frame) = read.next().await {
+                let mut frame = frame?;
+                let (source, destination) = header(&frame)?;
+                if destination != local {
+                    let message =
+                        format_args!("frame for role {} reached role {}", destination, local);
+                    return Err(invalid(message));
+                }
+
+                frame.advance(8);
+                let route = routes
+                    .get(&source)
+                    .ok_or_else(|| invalid(format_args!("frame from unknown role {}", source)))?;
+
+                let message = match frame.get_u8() {
+                    MESSAGE => {
+                        let result = codec.borrow_mut().decode(&frame);
+                        Some(result.map_err(|error| Error::Codec(error.into()))?)
+                    }
+                    CLOSE => None,
+                    kind => return Err(invalid(format_args!("unknown frame kind {}", kind))),
+                };
+
+                // The route may have been dropped already, in which case the
+                // message is no longer needed.
+                let _ = route.unbounded_send(message);
+            }
+
+            Ok(())
+        };
+
+        try_join(writer, reader).await?;
+        Ok(())
+    }
+}
+
+/// A virtual route to a peer over a connection shared by a `Mux`.
+pub struct MuxRoute<M> {
+    peer: Id,
+    sender: Option<UnboundedSender<Outgoing<M>>>,
+    receiver: UnboundedReceiver<Option<M>>,
+    cancelled: bool,
+}
+
+impl<M> MuxRoute<M> {
+    fn sender(&self) -> Result<&UnboundedSender<Outgoing<M>>, Error> {
+        self.sender.as_ref().ok_or(Error::Cancelled)
+    }
+}
+
+impl<M> Sink<M> for MuxRoute<M> {
+    type Error = Error;
+
+    fn poll_ready(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(self.sender().map(|_| ()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, message: M) -> Result<(), Self::Error> {
+        let result = self.sender()?.unbounded_send((self.peer, Some(message)));
+        result.map_err(|_| io::Error::from(io::ErrorKind::NotConnected).into())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<M> Stream for MuxRoute<M> {
+    type Item = M;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if this.cancelled {
+            return Poll::Ready(None);
+        }
+
+        Poll::Ready(match futures::ready!(this.receiver.poll_next_unpin(cx)) {
+            Some(Some(message)) => Some(message),
+            Some(None) => {
+                this.cancelled = true;
+                None
+            }
+            None => None,
+        })
+    }
+}
+
+/// Cancelling sends a frame which ends the stream of the peer's route, while
+/// the connection stays open for the other routes which share it.
+impl<M> Cancel for MuxRoute<M> {
+    fn cancel(&mut self) {
+        if let Some(sender) = self.sender.take() {
+            let _ = sender.unbounded_send((self.peer, None));
+        }
+    }
+
+    fn ended(&mut self) -> Ended {
+        match self.cancelled {
+            true => Ended::Cancelled,
+            false => Ended::Closed,
+        }
+    }
+}
+
+/// Forwards frames between roles which each have a single connection to the
+/// hub, until every role has closed its connection.
+pub async fn hub<T: AsyncRead + AsyncWrite>(
+    connections: impl IntoIterator<Item = T>,
+) -> Result<(), Error> {
+    let mut reads = Vec::new();
+    let mut writes = Vec::new();
+    let mut senders = HashMap::new();
+    for io in connections {
+        let (mut read, mut write) = split(io);
+        write.send(hello(HUB)).await?;
+        let id = greeting(&mut read).await?;
+
+        let (sender, receiver) = mpsc::unbounded::<Bytes>();
+        if senders.insert(id, sender).is_some() {
+            return Err(invalid(format_args!("duplicate role {}", id)));
+        }
+
+        reads.push((id, read));
+        writes.push((write, receiver));
+    }
+
+    // Each writer finishes once every reader which could forward to it has.
+    let readers = reads
+        .into_iter()
+        .map(|(id, mut read)| {
+            let senders = senders.clone();
+            async move {
+                while let Some(frame) = read.next().await {
+                    let frame = frame?;
+                    let (source, destination) = header(&frame)?;
+                    if source != id {
+                        return Err(invalid(format_args!("role {} sent as {}", id, source)));
+                    }
+
+                    let sender = senders.get(&destination).ok_or_else(|| {
+                        invalid(format_args!("frame to unknown role {}", destination))
+                    })?;
+                    let _ = sender.unbounded_send(frame.freeze());
+                }
+
+                Ok::<_, Error>(())
+            }
+        })
+        .collect::<Vec<_>>();
+    drop(senders);
+
+    let writers = writes
+        .into_iter()
+        .map(|(mut write, mut receiver)| async move {
+            while let Some(frame) = receiver.next().await {
+                write.send(frame).await?;
+            }
+
+            SinkExt::<Bytes>::close(&mut write).await?;
+            Ok::<_, Error>(())
+        });
+
+    try_join(try_join_all(readers), try_join_all(writers)).await?;
+    Ok(())
+}
+
+type Read<T> = FramedRead<ReadHalf<T>, LengthDelimitedCodec>;
+
+type Write<T> = FramedWrite<WriteHalf<T>, LengthDelimitedCodec>;
+
+fn split<T: AsyncRead + AsyncWrite>(io: T) -> (Read<T>, Write<T>) {
+    let (read, write) = tokio::io::split(io);
+    let read = FramedRead::new(read, LengthDelimitedCodec::new());
+    let write = FramedWrite::new(write, LengthDelimitedCodec::new());
+    (read, write)
+}
+
+/// The first frame on each connection, which gives the identity of its end.
+fn hello(id: Id) -> Bytes {
+    Bytes::copy_from_slice(&id.to_be_bytes())
+}
+
+async fn greeting<T: AsyncRead>(read: &mut Read<T>) -> Result<Id, Error> {
+    let mut frame = match read.next().await {
+        Some(frame) => frame?,
+        None => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+    };
+
+    if frame.len() != 4 {
+        return Err(invalid(format_args!("expected greeting")));
+    }
+
+    Ok(frame.get_u32())
+}
+
+/// The roles which a frame is from and to.
+fn header(mut frame: &[u8]) -> Result<(Id, Id), Error> {
+    if frame.len() < 9 {
+        return Err(invalid(format_args!("frame is too short")));
+    }
+
+    Ok((frame.get_u32(), frame.get_u32()))
+}
+
+fn invalid(message: std::fmt::Arguments<'_>) -> Error {
+    io::Error::new(io::ErrorKind::InvalidData, std::format!("{}", message)).into()
+}
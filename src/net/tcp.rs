@@ -0,0 +1,31 @@
+use super::{Codec, Framed};
+use std::io;
+use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+
+/// A route to a peer in another process, or on another host, over TCP.
+pub type TcpRoute<M, C> = Framed<TcpStream, M, C>;
+
+/// Connects to a peer which is listening on `addr`.
+pub async fn connect<M, C: Codec<M>>(
+    addr: impl ToSocketAddrs,
+    codec: C,
+) -> io::Result<TcpRoute<M, C>> {
+    let stream = TcpStream::connect(addr).await?;
+    route(stream, codec)
+}
+
+/// Accepts the next peer which connects to `listener`.
+pub async fn accept<M, C: Codec<M>>(
+    listener: &TcpListener,
+    codec: C,
+) -> io::Result<TcpRoute<M, C>> {
+    let (stream, _) = listener.accept().await?;
+    route(stream, codec)
+}
+
+/// Messages are often small and sent one at a time while the peer waits for
+/// them, so Nagle's algorithm is turned off.
+fn route<M, C: Codec<M>>(stream: TcpStream, codec: C) -> io::Result<TcpRoute<M, C>> {
+    stream.set_nodelay(true)?;
+    Ok(Framed::new(stream, codec))
+}
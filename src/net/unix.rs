@@ -0,0 +1,45 @@
+use super::{Codec, Framed};
+use std::{io, os::unix::net, path::Path};
+use tokio::net::{UnixListener, UnixStream};
+
+/// A route to a peer in another process on the same host, over a Unix domain
+/// socket.
+pub type UnixRoute<M, C> = Framed<UnixStream, M, C>;
+
+/// Connects to a peer which is listening on the socket at `path`.
+pub async fn connect<M, C: Codec<M>>(
+    path: impl AsRef<Path>,
+    codec: C,
+) -> io::Result<UnixRoute<M, C>> {
+    let stream = UnixStream::connect(path).await?;
+    Ok(Framed::new(stream, codec))
+}
+
+/// Accepts the next peer which connects to `listener`.
+pub async fn accept<M, C: Codec<M>>(
+    listener: &UnixListener,
+    codec: C,
+) -> io::Result<UnixRoute<M, C>> {
+    let (stream, _) = listener.accept().await?;
+    Ok(Framed::new(stream, codec))
+}
+
+/// Creates a route from a standard library socket, such as one half of a
+/// socketpair which was inherited from a parent process.
+pub fn from_std<M, C: Codec<M>>(stream: net::UnixStream, codec: C) -> io::Result<UnixRoute<M, C>> {
+    stream.set_nonblocking(true)?;
+    let stream = UnixStream::from_std(stream)?;
+    Ok(Framed::new(stream, codec))
+}
+
+/// Creates a pair of routes over a socketpair, which is useful for roles that
+/// are split into separate processes once spawned. This must be called from
+/// within a Tokio runtime, so a socketpair created before the runtime should
+/// instead be made with `std::os::unix::net::UnixStream::pair` and each end
+/// passed to `from_std` later.
+pub fn pair<M, C: Codec<M> + Clone>(codec: C) -> io::Result<(UnixRoute<M, C>, UnixRoute<M, C>)> {
+    let (left, right) = UnixStream::pair()?;
+    let left = Framed::new(left, codec.clone());
+    let right = Framed::new(right, codec);
+    Ok((left, right))
+}
@@ -0,0 +1,500 @@
+#![cfg(feature = "record")]
+
+use crate::channel::{Cancel, Ended};
+use futures::{Sink, Stream};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    borrow::ToOwned,
+    boxed::Box,
+    collections::VecDeque,
+    fmt::{self, Display, Formatter},
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::Path,
+    pin::Pin,
+    string::String,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Instant,
+};
+use thiserror::Error;
+
+/// Whether a recorded message was sent to or received from the peer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+/// A message which crossed one of a role's routes, as stored on a line of a
+/// recording.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Entry<M> {
+    /// The position of the message among all those recorded for the role.
+    pub index: u64,
+    /// The microseconds since recording started.
+    pub time: u64,
+    pub peer: String,
+    pub direction: Direction,
+    pub message: M,
+}
+
+struct Writer {
+    writer: Box<dyn Write + Send>,
+    start: Instant,
+    index: u64,
+    error: Option<io::Error>,
+}
+
+impl Writer {
+    fn write<M: Serialize>(&mut self, peer: &str, direction: Direction, message: &M) {
+        if self.error.is_some() {
+            return;
+        }
+
+        let entry = Entry {
+            index: self.index,
+            time: self.start.elapsed().as_micros() as u64,
+            peer: peer.to_owned(),
+            direction,
+            message,
+        };
+        self.index += 1;
+
+        let result = serde_json::to_writer(&mut self.writer, &entry)
+            .map_err(io::Error::from)
+            .and_then(|_| self.writer.write_all(b"\n"));
+        self.error = result.err();
+    }
+}
+
+/// Records every message crossing the routes of a role as lines of JSON, in
+/// the order they were sent or received.
+///
+/// Routes are wrapped with `route` so that all of the routes of a role share a
+/// single recording. Failing to write does not affect the role, but is kept
+/// and returned by `flush`.
+#[derive(Clone)]
+pub struct Recorder(Arc<Mutex<Writer>>);
+
+impl Recorder {
+    pub fn new(writer: impl Write + Send + 'static) -> Self {
+        Self(Arc::new(Mutex::new(Writer {
+            writer: Box::new(writer),
+            start: Instant::now(),
+            index: 0,
+            error: None,
+        })))
+    }
+
+    /// Records to a new file at `path`, replacing any which already exists.
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = File::create(path)?;
+        Ok(Self::new(BufWriter::new(file)))
+    }
+
+    /// Wraps the route to `peer` so that its messages are recorded.
+    pub fn route<T>(&self, peer: impl Into<String>, route: T) -> Recorded<T> {
+        Recorded {
+            route,
+            peer: peer.into(),
+            recorder: self.clone(),
+        }
+    }
+
+    /// Writes out any buffered entries, failing if any entry so far could not
+    /// be written.
+    pub fn flush(&self) -> io::Result<()> {
+        let mut writer = self.0.lock().unwrap();
+        if let Some(error) = writer.error.take() {
+            return Err(error);
+        }
+
+        writer.writer.flush()
+    }
+
+    fn write<M: Serialize>(&self, peer: &str, direction: Direction, message: &M) {
+        self.0.lock().unwrap().write(peer, direction, message);
+    }
+}
+
+/// A route whose messages are recorded by a `Recorder`.
+pub struct Recorded<T> {
+    route: T,
+    peer: String,
+    recorder: Recorder,
+}
+
+impl<T> Recorded<T> {
+    pub fn get_ref(&self) -> &T {
+        &self.route
+    }
+
+    pub fn into_inner(self) -> T {
+        self.route
+    }
+}
+
+impl<M: Serialize, T: Sink<M> + Unpin> Sink<M> for Recorded<T> {
+    type Error = T::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.get_mut().route).poll_ready(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, message: M) -> Result<(), Self::Error> {
+        let this = self.get_mut();
+        let recorded = serde_json::to_value(&message);
+        Pin::new(&mut this.route).start_send(message)?;
+
+        // Messages are recorded only once the route has taken them, so those
+        // which failed to send are not replayed.
+        match recorded {
+            Ok(message) => this.recorder.write(&this.peer, Direction::Sent, &message),
+            Err(error) => this.recorder.0.lock().unwrap().error = Some(error.into()),
+        }
+
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.get_mut().route).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.get_mut().route).poll_close(cx)
+    }
+}
+
+impl<T: Stream + Unpin> Stream for Recorded<T>
+where
+    T::Item: Serialize,
+{
+    type Item = T::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let message = futures::ready!(Pin::new(&mut this.route).poll_next(cx));
+        if let Some(message) = &message {
+            this.recorder
+                .write(&this.peer, Direction::Received, message);
+        }
+
+        Poll::Ready(message)
+    }
+}
+
+impl<T: Cancel> Cancel for Recorded<T> {
+    fn cancel(&mut self) {
+        self.route.cancel();
+    }
+
+    fn ended(&mut self) -> Ended {
+        self.route.ended()
+    }
+}
+
+/// The first point at which a replayed role did not behave as it did in the
+/// recording.
+#[derive(Clone, Debug, Error)]
+#[error(
+    "diverged from recording at entry {index}: expected {}, but {actual}",
+    Expected(expected)
+)]
+pub struct Divergence {
+    /// The index of the entry which the role diverged at, or the number of
+    /// entries if the role carried on after the recording ended.
+    pub index: u64,
+    /// The entry which was recorded at that point, if any.
+    pub expected: Option<Box<Entry<Value>>>,
+    /// What the role did instead.
+    pub actual: Actual,
+}
+
+/// What a replayed role did where it diverged from the recording.
+#[derive(Clone, Debug)]
+pub enum Actual {
+    /// Sent a message to `peer`, which is `None` if it could not be encoded.
+    Sent {
+        peer: String,
+        message: Option<Value>,
+    },
+    /// Received from `peer`.
+    Received { peer: String },
+    /// Stopped before the end of the recording.
+    Stopped,
+}
+
+impl Display for Actual {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Sent {
+                peer,
+                message: Some(message),
+            } => write!(f, "sent {} to {}", message, peer),
+            Self::Sent {
+                peer,
+                message: None,
+            } => write!(f, "sent a message to {}", peer),
+            Self::Received { peer } => write!(f, "received from {}", peer),
+            Self::Stopped => write!(f, "stopped"),
+        }
+    }
+}
+
+struct Expected<'a>(&'a Option<Box<Entry<Value>>>);
+
+impl Display for Expected<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(entry) => match entry.direction {
+                Direction::Sent => write!(f, "to send {} to {}", entry.message, entry.peer),
+                Direction::Received => {
+                    write!(f, "to receive {} from {}", entry.message, entry.peer)
+                }
+            },
+            None => write!(f, "no more messages"),
+        }
+    }
+}
+
+struct Replaying<M> {
+    /// The entries which have not been replayed yet, along with the decoded
+    /// message of each received entry.
+    entries: VecDeque<(Entry<Value>, Option<M>)>,
+    len: u64,
+    divergence: Option<Divergence>,
+}
+
+impl<M> Replaying<M> {
+    /// The next entry if it is `direction` with `peer`.
+    fn next(&self, peer: &str, direction: Direction) -> Option<&(Entry<Value>, Option<M>)> {
+        let next = self.entries.front()?;
+        let matches = next.0.peer == peer && next.0.direction == direction;
+        matches.then_some(next)
+    }
+
+    fn diverge(&self, actual: Actual) -> Divergence {
+        let expected = self
+            .entries
+            .front()
+            .map(|(entry, _)| Box::new(entry.clone()));
+        Divergence {
+            index: expected.as_ref().map_or(self.len, |entry| entry.index),
+            expected,
+            actual,
+        }
+    }
+}
+
+/// Feeds a recording back to a single role in place of its peers, checking
+/// that the role sends and receives in the same order as it did when recorded.
+///
+/// The entries of the recording are replayed as a single sequence across every
+/// route. A route gives the role the next entry only if it was received from
+/// that route's peer. Receiving anything else fails with the divergence, so a
+/// role which receives from the wrong peer first is caught. The role is
+/// stopped at the first message it sends which differs from the recording, and
+/// `verify` then reports where it diverged.
+pub struct Replay<M>(Arc<Mutex<Replaying<M>>>);
+
+impl<M: DeserializeOwned> Replay<M> {
+    /// Loads the recording from lines of JSON written by a `Recorder`.
+    pub fn new(reader: impl BufRead) -> io::Result<Self> {
+        let mut entries = VecDeque::new();
+        let mut len = 0;
+        for line in reader.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let entry = serde_json::from_str::<Entry<Value>>(&line)?;
+            let message = match entry.direction {
+                Direction::Sent => None,
+                Direction::Received => Some(serde_json::from_value(entry.message.clone())?),
+            };
+
+            len = len.max(entry.index + 1);
+            entries.push_back((entry, message));
+        }
+
+        Ok(Self(Arc::new(Mutex::new(Replaying {
+            entries,
+            len,
+            divergence: None,
+        }))))
+    }
+
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<M> Replay<M> {
+    /// Creates the route which replays the messages of `peer`.
+    pub fn route(&self, peer: impl Into<String>) -> Replayed<M> {
+        Replayed {
+            peer: peer.into(),
+            replay: self.0.clone(),
+            divergence: None,
+        }
+    }
+
+    /// Checks, once the role has finished, that it sent and received every
+    /// recorded message, giving the earliest point at which it diverged
+    /// otherwise.
+    pub fn verify(&self) -> Result<(), Divergence> {
+        let replaying = self.0.lock().unwrap();
+        if let Some(divergence) = &replaying.divergence {
+            return Err(divergence.clone());
+        }
+
+        match replaying.entries.is_empty() {
+            true => Ok(()),
+            false => Err(replaying.diverge(Actual::Stopped)),
+        }
+    }
+}
+
+impl<M> Clone for Replay<M> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+/// A route which replays the recorded messages of a peer.
+pub struct Replayed<M> {
+    peer: String,
+    replay: Arc<Mutex<Replaying<M>>>,
+    divergence: Option<Divergence>,
+}
+
+impl<M: Serialize> Sink<M> for Replayed<M> {
+    type Error = Divergence;
+
+    fn poll_ready(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let replaying = self.replay.lock().unwrap();
+        Poll::Ready(replaying.divergence.clone().map_or(Ok(()), Err))
+    }
+
+    fn start_send(self: Pin<&mut Self>, message: M) -> Result<(), Self::Error> {
+        let mut replaying = self.replay.lock().unwrap();
+        if let Some(divergence) = &replaying.divergence {
+            return Err(divergence.clone());
+        }
+
+        let message = serde_json::to_value(&message).ok();
+        let next = replaying.next(&self.peer, Direction::Sent);
+        if next.map(|(entry, _)| Some(&entry.message)) == Some(message.as_ref()) {
+            replaying.entries.pop_front();
+            return Ok(());
+        }
+
+        let peer = self.peer.clone();
+        let divergence = replaying.diverge(Actual::Sent { peer, message });
+        replaying.divergence = Some(divergence.clone());
+        Err(divergence)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// The stream ends if the next entry is not a message received from the peer.
+/// This is kept only on the route rather than stopping the role, since a
+/// `Race` polls every route and only the one with the next entry should win.
+impl<M> Stream for Replayed<M> {
+    type Item = M;
+
+    fn poll_next(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let mut replaying = this.replay.lock().unwrap();
+        if replaying.divergence.is_some() || replaying.entries.is_empty() {
+            return Poll::Ready(None);
+        }
+
+        if replaying.next(&this.peer, Direction::Received).is_none() {
+            let peer = this.peer.clone();
+            this.divergence = Some(replaying.diverge(Actual::Received { peer }));
+            return Poll::Ready(None);
+        }
+
+        let (_, message) = replaying.entries.pop_front().unwrap();
+        Poll::Ready(message)
+    }
+}
+
+/// A stream which ended since the recorded messages ran out is not treated as
+/// a cancellation, since the peer never really took part. Receiving out of
+/// order fails with the divergence instead.
+impl<M> Cancel for Replayed<M> {
+    fn ended(&mut self) -> Ended {
+        let divergence = self.divergence.take();
+        let divergence = divergence.or_else(|| self.replay.lock().unwrap().divergence.clone());
+        match divergence {
+            Some(divergence) => Ended::Failed(Box::new(divergence)),
+            None => Ended::Closed,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{executor, SinkExt, StreamExt};
+
+    const RECORDING: &str = r#"
+        {"index":0,"time":0,"peer":"A","direction":"received","message":1}
+        {"index":1,"time":0,"peer":"B","direction":"received","message":2}
+        {"index":2,"time":0,"peer":"A","direction":"sent","message":3}
+    "#;
+
+    #[test]
+    fn replay_in_order() {
+        let replay = Replay::<i32>::new(RECORDING.as_bytes()).unwrap();
+        let (mut a, mut b) = (replay.route("A"), replay.route("B"));
+        executor::block_on(async {
+            assert_eq!(a.next().await, Some(1));
+            assert_eq!(b.next().await, Some(2));
+            a.send(3).await.unwrap();
+        });
+
+        replay.verify().unwrap();
+    }
+
+    #[test]
+    fn receive_from_wrong_peer() {
+        let replay = Replay::<i32>::new(RECORDING.as_bytes()).unwrap();
+        let mut b = replay.route("B");
+        assert_eq!(executor::block_on(b.next()), None);
+
+        let divergence = match b.ended() {
+            Ended::Failed(error) => error.downcast::<Divergence>().unwrap(),
+            ended => panic!("expected a divergence, but {:?}", ended),
+        };
+        assert_eq!(divergence.index, 0);
+        assert!(matches!(divergence.actual, Actual::Received { ref peer } if peer == "B"));
+
+        // The route which was received from out of order does not stop the
+        // rest of the role, which may still be racing the right route.
+        let mut a = replay.route("A");
+        assert_eq!(executor::block_on(a.next()), Some(1));
+    }
+
+    #[test]
+    fn send_before_receive() {
+        let replay = Replay::<i32>::new(RECORDING.as_bytes()).unwrap();
+        let mut a = replay.route("A");
+        let divergence = executor::block_on(a.send(3)).unwrap_err();
+        assert_eq!(divergence.index, 0);
+        assert_eq!(replay.verify().unwrap_err().index, 0);
+    }
+}
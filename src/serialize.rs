@@ -1,6 +1,9 @@
 #![cfg(feature = "serialize")]
 
-use crate::{Branch, End, FromState, Receive, Role, Select, Send};
+use crate::{
+    delegate::Delegate, Branch, Elapse, End, FromState, Gather, Race, Receive, Role, Scatter,
+    Select, Send, Timeout,
+};
 use rumpsteak_fsm::{Action, Fsm, Message, StateIndex, Transition};
 use std::{
     any::{type_name, TypeId},
@@ -17,7 +20,7 @@ pub struct Type {
 }
 
 impl Type {
-    fn new<T: 'static>() -> Self {
+    pub fn new<T: 'static>() -> Self {
         Self {
             id: TypeId::of::<T>(),
             name: type_name::<T>(),
@@ -25,6 +28,10 @@ impl Type {
     }
 }
 
+/// Marker for the label of a timeout transition, which is modelled as an input
+/// from the role being waited on so that subtyping checks it like any other.
+struct Elapsed;
+
 impl Display for Type {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         write!(f, "{}", self.name)
@@ -105,6 +112,28 @@ impl ChoicesSerializer<'_> {
         self.serializer.previous = Some((self.state, transition));
         S::serialize(&mut self.serializer);
     }
+
+    fn serialize_timeout<S: Serialize>(&mut self) {
+        let label = Type {
+            id: TypeId::of::<Elapsed>(),
+            name: "timeout",
+        };
+
+        let transition = Transition::new(self.role, self.action, Message::from_label(label));
+        self.serializer.previous = Some((self.state, transition));
+        S::serialize(self.serializer);
+    }
+}
+
+/// Serializes the choices of a `Race`, where each choice is received from its
+/// own role.
+pub struct RaceChoicesSerializer<'a>(ChoicesSerializer<'a>);
+
+impl RaceChoicesSerializer<'_> {
+    pub fn serialize_choice<R: 'static, L: 'static, S: Serialize>(&mut self) {
+        self.0.role = Type::new::<R>();
+        self.0.serialize_choice::<L, S>();
+    }
 }
 
 pub trait Serialize: 'static {
@@ -115,6 +144,10 @@ pub trait SerializeChoices: 'static {
     fn serialize_choices(s: ChoicesSerializer<'_>);
 }
 
+pub trait SerializeRaceChoices: 'static {
+    fn serialize_choices(s: RaceChoicesSerializer<'_>);
+}
+
 impl<R: Role + 'static> Serialize for End<'static, R> {
     fn serialize(s: &mut Serializer) {
         s.serialize_end::<Self>();
@@ -143,6 +176,30 @@ where
     }
 }
 
+// A family is serialized as a single role, since its size is only known at
+// runtime, so sending to or receiving from every member is one transition.
+impl<Q: Role + 'static, R: 'static, L: 'static, S> Serialize for Scatter<'static, Q, R, L, S>
+where
+    S: FromState<'static, Role = Q> + Serialize,
+{
+    fn serialize(s: &mut Serializer) {
+        if let Some(mut s) = s.serialize_choices::<Self, R>(Action::Output) {
+            s.serialize_choice::<L, S>();
+        }
+    }
+}
+
+impl<Q: Role + 'static, R: 'static, L: 'static, S> Serialize for Gather<'static, Q, R, L, S>
+where
+    S: FromState<'static, Role = Q> + Serialize,
+{
+    fn serialize(s: &mut Serializer) {
+        if let Some(mut s) = s.serialize_choices::<Self, R>(Action::Input) {
+            s.serialize_choice::<L, S>();
+        }
+    }
+}
+
 impl<Q: Role + 'static, R: 'static, C: SerializeChoices> Serialize for Select<'static, Q, R, C> {
     fn serialize(s: &mut Serializer) {
         if let Some(s) = s.serialize_choices::<Self, R>(Action::Output) {
@@ -159,6 +216,38 @@ impl<Q: Role + 'static, R: 'static, C: SerializeChoices> Serialize for Branch<'s
     }
 }
 
+impl<Q: Role + 'static, C: SerializeRaceChoices> Serialize for Race<'static, Q, C> {
+    fn serialize(s: &mut Serializer) {
+        // Each choice sets its own role, so the role given here is never used.
+        if let Some(s) = s.serialize_choices::<Self, Q>(Action::Input) {
+            C::serialize_choices(RaceChoicesSerializer(s));
+        }
+    }
+}
+
+impl<Q: Role + 'static, R: 'static, C> Serialize for Timeout<'static, Q, R, C>
+where
+    C: SerializeChoices + Elapse<'static>,
+    <C as Elapse<'static>>::Session: FromState<'static, Role = Q> + Serialize,
+{
+    fn serialize(s: &mut Serializer) {
+        if let Some(mut s) = s.serialize_choices::<Self, R>(Action::Input) {
+            s.serialize_timeout::<<C as Elapse<'static>>::Session>();
+            C::serialize_choices(s);
+        }
+    }
+}
+
+/// A delegation is labelled by its type, which names the delegated session, so
+/// the FSMs of the roles which send and receive it only agree if both hand off
+/// exactly the same session. The delegated session itself is serialized here,
+/// so that the protocol which the receiver continues can be checked too.
+impl<R: Role + 'static, S: FromState<'static, Role = R> + Serialize> Delegate<R, S> {
+    pub fn serialize() -> Fsm<Type, Type, Infallible> {
+        serialize::<S>()
+    }
+}
+
 pub fn serialize<S: FromState<'static> + Serialize>() -> Fsm<Type, Type, Infallible> {
     let mut serializer = Serializer {
         fsm: Fsm::new(Type::new::<S::Role>()),
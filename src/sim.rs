@@ -0,0 +1,429 @@
+#![cfg(feature = "std")]
+
+use crate::channel::{Cancel, Ended, Pair};
+use futures::{Sink, Stream};
+use std::{
+    boxed::Box,
+    cell::{Cell, RefCell},
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    future::Future,
+    pin::Pin,
+    rc::Rc,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Wake, Waker},
+    thread_local,
+    vec::Vec,
+};
+use thiserror::Error;
+
+thread_local! {
+    static CURRENT: RefCell<Option<Rc<Shared>>> = const { RefCell::new(None) };
+}
+
+/// The error from a simulation in which some roles could never finish, since
+/// no messages were in flight to wake them.
+#[derive(Debug, Error)]
+#[error("simulation with seed {seed} deadlocked at time {time} with {blocked} roles blocked")]
+pub struct Deadlock {
+    pub seed: u64,
+    pub time: u64,
+    pub blocked: usize,
+}
+
+/// The error from sending on a simulated channel.
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("receiver is disconnected")]
+    Disconnected,
+    #[error("simulated channel was used outside of a simulation")]
+    Outside,
+}
+
+/// A deterministic network and scheduler for testing protocols, where every
+/// choice is drawn from a generator seeded by `seed`.
+///
+/// Each message is delayed by a random number of ticks of virtual time, but
+/// never overtakes an earlier message on the same channel, and roles which are
+/// ready to run are polled in a random order. A run which fails can therefore
+/// be replayed exactly by simulating again with the same seed.
+pub struct Simulation<'a> {
+    shared: Rc<Shared>,
+    tasks: Vec<Option<Pin<Box<dyn Future<Output = ()> + 'a>>>>,
+}
+
+struct Shared {
+    seed: u64,
+    rng: Cell<u64>,
+    max_delay: Cell<u64>,
+    time: Cell<u64>,
+    timers: RefCell<BTreeMap<(u64, u64), Waker>>,
+    sequence: Cell<u64>,
+}
+
+impl Shared {
+    fn current() -> Rc<Self> {
+        Self::try_current().expect("must be called within a simulation")
+    }
+
+    fn try_current() -> Option<Rc<Self>> {
+        CURRENT.with(|current| current.borrow().clone())
+    }
+
+    /// Draws the next number from a SplitMix64 generator.
+    fn next(&self) -> u64 {
+        let state = self.rng.get().wrapping_add(0x9e37_79b9_7f4a_7c15);
+        self.rng.set(state);
+
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn delay(&self) -> u64 {
+        self.next() % (self.max_delay.get() + 1)
+    }
+
+    fn wake_at(&self, time: u64, waker: Waker) {
+        let sequence = self.sequence.get();
+        self.sequence.set(sequence + 1);
+        self.timers.borrow_mut().insert((time, sequence), waker);
+    }
+}
+
+impl<'a> Simulation<'a> {
+    pub fn new(seed: u64) -> Self {
+        let shared = Shared {
+            seed,
+            rng: Cell::new(seed),
+            max_delay: Cell::new(10),
+            time: Cell::new(0),
+            timers: RefCell::new(BTreeMap::new()),
+            sequence: Cell::new(0),
+        };
+
+        Self {
+            shared: Rc::new(shared),
+            tasks: Vec::new(),
+        }
+    }
+
+    /// Sets the most ticks by which a message can be delayed.
+    pub fn max_delay(self, max_delay: u64) -> Self {
+        self.shared.max_delay.set(max_delay);
+        self
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.shared.seed
+    }
+
+    /// Runs `f` with this simulation as the current one, so that channels
+    /// paired within it, for example by `Roles::default`, are simulated. A
+    /// channel paired outside of any simulation joins the one it is first sent
+    /// on from instead, while sending on it outside of one fails with
+    /// `Error::Outside`.
+    pub fn enter<T>(&self, f: impl FnOnce() -> T) -> T {
+        enter(&self.shared, f)
+    }
+
+    /// Adds a role to be run by the simulation.
+    pub fn spawn(&mut self, future: impl Future<Output = ()> + 'a) {
+        self.tasks.push(Some(Box::pin(future)));
+    }
+
+    /// Runs every role until they have all finished, returning the virtual
+    /// time which the simulation ended at.
+    pub fn run(mut self) -> Result<u64, Deadlock> {
+        let ready = Arc::new(Mutex::new((0..self.tasks.len()).collect::<BTreeSet<_>>()));
+        let wakers = (0..self.tasks.len())
+            .map(|index| {
+                let ready = ready.clone();
+                Waker::from(Arc::new(TaskWaker { index, ready }))
+            })
+            .collect::<Vec<_>>();
+
+        let shared = self.shared.clone();
+        let mut remaining = self.tasks.len();
+        while remaining > 0 {
+            let index = {
+                let mut ready = ready.lock().unwrap();
+                match ready.len() {
+                    0 => None,
+                    len => {
+                        let nth = (shared.next() % len as u64) as usize;
+                        let index = *ready.iter().nth(nth).unwrap();
+                        ready.remove(&index);
+                        Some(index)
+                    }
+                }
+            };
+
+            let index = match index {
+                Some(index) => index,
+                None => {
+                    // Nothing can run until the next message is delivered, so
+                    // virtual time skips forward to when it arrives.
+                    let mut timers = shared.timers.borrow_mut();
+                    let &(time, _) = match timers.keys().next() {
+                        Some(key) => key,
+                        None => break,
+                    };
+
+                    shared.time.set(time);
+                    let later = timers.split_off(&(time + 1, 0));
+                    let due = std::mem::replace(&mut *timers, later);
+                    due.into_values().for_each(Waker::wake);
+                    continue;
+                }
+            };
+
+            if let Some(task) = &mut self.tasks[index] {
+                let mut cx = Context::from_waker(&wakers[index]);
+                let poll = enter(&shared, || task.as_mut().poll(&mut cx));
+                if poll.is_ready() {
+                    self.tasks[index] = None;
+                    remaining -= 1;
+                }
+            }
+        }
+
+        match remaining {
+            0 => Ok(shared.time.get()),
+            blocked => Err(Deadlock {
+                seed: shared.seed,
+                time: shared.time.get(),
+                blocked,
+            }),
+        }
+    }
+}
+
+fn enter<T>(shared: &Rc<Shared>, f: impl FnOnce() -> T) -> T {
+    struct Guard(Option<Rc<Shared>>);
+
+    impl Drop for Guard {
+        fn drop(&mut self) {
+            CURRENT.with(|current| *current.borrow_mut() = self.0.take());
+        }
+    }
+
+    let previous = CURRENT.with(|current| current.replace(Some(shared.clone())));
+    let _guard = Guard(previous);
+    f()
+}
+
+struct TaskWaker {
+    index: usize,
+    ready: Arc<Mutex<BTreeSet<usize>>>,
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.ready.lock().unwrap().insert(self.index);
+    }
+}
+
+/// The virtual time of the current simulation.
+///
+/// # Panics
+///
+/// Panics if called outside of a simulation.
+pub fn now() -> u64 {
+    Shared::current().time.get()
+}
+
+/// Waits for `ticks` of virtual time in the current simulation, which can be
+/// used as the deadline of a `Timeout`.
+///
+/// # Panics
+///
+/// Panics if called outside of a simulation.
+pub fn sleep(ticks: u64) -> impl Future<Output = ()> {
+    let shared = Shared::current();
+    let deadline = shared.time.get() + ticks;
+    futures::future::poll_fn(move |cx| {
+        if shared.time.get() >= deadline {
+            return Poll::Ready(());
+        }
+
+        shared.wake_at(deadline, cx.waker().clone());
+        Poll::Pending
+    })
+}
+
+struct Link<T> {
+    /// The simulation which delivers the messages, which is only missing
+    /// before the first message is sent if the link was paired outside of
+    /// one.
+    shared: Option<Rc<Shared>>,
+    queue: VecDeque<(u64, T)>,
+    waker: Option<Waker>,
+    sender: bool,
+    receiver: bool,
+    cancelled: bool,
+}
+
+/// The sending half of a simulated channel.
+pub struct Sender<T>(Rc<RefCell<Link<T>>>);
+
+/// The receiving half of a simulated channel.
+pub struct Receiver<T>(Rc<RefCell<Link<T>>>);
+
+impl<T> Pair<Receiver<T>> for Sender<T> {
+    fn pair() -> (Self, Receiver<T>) {
+        let link = Link {
+            shared: Shared::try_current(),
+            queue: VecDeque::new(),
+            waker: None,
+            sender: true,
+            receiver: true,
+            cancelled: false,
+        };
+
+        let link = Rc::new(RefCell::new(link));
+        (Sender(link.clone()), Receiver(link))
+    }
+}
+
+impl<T> Pair<Sender<T>> for Receiver<T> {
+    fn pair() -> (Self, Sender<T>) {
+        let (sender, receiver) = Pair::pair();
+        (receiver, sender)
+    }
+}
+
+impl<T> Sink<T> for Sender<T> {
+    type Error = Error;
+
+    fn poll_ready(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: T) -> Result<(), Self::Error> {
+        let mut link = self.0.borrow_mut();
+        if !link.sender || !link.receiver {
+            return Err(Error::Disconnected);
+        }
+
+        if link.shared.is_none() {
+            link.shared = Shared::try_current();
+        }
+
+        let shared = link.shared.as_ref().ok_or(Error::Outside)?;
+
+        // Messages are delivered in order, so one is never due before the
+        // message sent ahead of it.
+        let time = shared.time.get() + shared.delay();
+        let time = link.queue.back().map_or(time, |&(last, _)| time.max(last));
+        link.queue.push_back((time, item));
+        if let Some(waker) = link.waker.take() {
+            waker.wake();
+        }
+
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<T> Stream for Receiver<T> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut link = self.0.borrow_mut();
+        let time = match link.queue.front() {
+            Some(&(time, _)) => time,
+            None if link.sender && link.receiver => {
+                link.waker = Some(cx.waker().clone());
+                return Poll::Pending;
+            }
+            None => return Poll::Ready(None),
+        };
+
+        // A message is only queued once the link has joined a simulation.
+        if let Some(shared) = link
+            .shared
+            .as_ref()
+            .filter(|shared| time > shared.time.get())
+        {
+            shared.wake_at(time, cx.waker().clone());
+            return Poll::Pending;
+        }
+
+        Poll::Ready(link.queue.pop_front().map(|(_, item)| item))
+    }
+}
+
+impl<T> Sender<T> {
+    fn close(&mut self) {
+        let mut link = self.0.borrow_mut();
+        link.sender = false;
+        if let Some(waker) = link.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+/// Messages already in flight are still delivered after the sender cancels.
+impl<T> Cancel for Sender<T> {
+    fn cancel(&mut self) {
+        self.0.borrow_mut().cancelled = true;
+        self.close();
+    }
+}
+
+/// The stream ends once the sender has been cancelled or dropped, which the
+/// receiver can tell apart since both share the link.
+impl<T> Cancel for Receiver<T> {
+    fn cancel(&mut self) {
+        self.0.borrow_mut().receiver = false;
+    }
+
+    fn ended(&mut self) -> Ended {
+        match self.0.borrow().cancelled {
+            true => Ended::Cancelled,
+            false => Ended::Closed,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{executor, SinkExt, StreamExt};
+
+    #[test]
+    fn paired_outside() {
+        let (mut sender, mut receiver) = Sender::<u32>::pair();
+        let send = executor::block_on(sender.send(1));
+        assert!(matches!(send, Err(Error::Outside)));
+
+        let mut simulation = Simulation::new(0);
+        simulation.spawn(async move { sender.send(2).await.unwrap() });
+        simulation.spawn(async move { assert_eq!(receiver.next().await, Some(2)) });
+        simulation.run().unwrap();
+    }
+}
@@ -0,0 +1,6 @@
+#[test]
+fn protocol() {
+    let cases = trybuild::TestCases::new();
+    cases.compile_fail("tests/ui/protocol/*.rs");
+    cases.pass("tests/ui/protocol/pass/*.rs");
+}
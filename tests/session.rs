@@ -0,0 +1,116 @@
+use futures::{
+    channel::mpsc::{UnboundedReceiver, UnboundedSender},
+    executor, future, try_join,
+};
+use rumpsteak::{
+    channel::Bidirectional, session, try_session, End, Message, Receive, Role, Roles, Send as Emit,
+    Timed, Timeout as Deadline,
+};
+use std::{error::Error, result};
+
+type Result<T> = result::Result<T, Box<dyn Error>>;
+
+type Channel = Bidirectional<UnboundedSender<Label>, UnboundedReceiver<Label>>;
+
+#[derive(Roles)]
+struct Roles(C, S);
+
+#[derive(Role)]
+#[message(Label)]
+struct C(#[route(S)] Channel);
+
+#[derive(Role)]
+#[message(Label)]
+struct S(#[route(C)] Channel);
+
+#[derive(Message)]
+enum Label {
+    Request(Request),
+    Response(Response),
+    #[key(Deposit)]
+    Deposit(u64),
+}
+
+struct Request(u64);
+struct Response(u64);
+
+/// The key of a deposit, which carries a bare `u64`.
+struct Deposit;
+
+/// An alias of a session type is augmented like the session type itself,
+/// since its continuation is still its last argument.
+type Wait<'q, Q, C> = Deadline<'q, Q, S, C>;
+
+#[session]
+type Client = Emit<S, Request, Wait<ClientChoice>>;
+
+#[session]
+#[timeout(Receive<S, Response, End>)]
+enum ClientChoice {
+    Response(Response, End),
+}
+
+#[session]
+type Server = Receive<C, Request, Emit<C, Response, End>>;
+
+async fn client(role: &mut C) -> Result<u64> {
+    try_session(role, |s: Client<'_, _>| async {
+        let s = s.send(Request(1)).await?;
+        let (Response(x), s) = match s.branch(future::ready(())).await? {
+            Timed::Received(ClientChoice::Response(response, s)) => (response, s),
+            Timed::Elapsed(s) => s.receive().await?,
+        };
+
+        Ok((x, s))
+    })
+    .await
+}
+
+async fn server(role: &mut S) -> Result<()> {
+    try_session(role, |s: Server<'_, _>| async {
+        let (Request(x), s) = s.receive().await?;
+        Ok(((), s.send(Response(x + 1)).await?))
+    })
+    .await
+}
+
+/// A key names the label in place of its type, so a renamed send can be given
+/// one without changing where its continuation is.
+type Pay<'q, Q, N> = Emit<'q, Q, S, Deposit, N>;
+
+#[session]
+type Payer = Pay<Emit<S, Deposit, End>>;
+
+#[session]
+type Payee = Receive<C, Deposit, Receive<C, Deposit, End>>;
+
+async fn payer(role: &mut C) -> Result<()> {
+    try_session(role, |s: Payer<'_, _>| async {
+        let s = s.send(1).await?;
+        Ok(((), s.send(2).await?))
+    })
+    .await
+}
+
+async fn payee(role: &mut S) -> Result<u64> {
+    try_session(role, |s: Payee<'_, _>| async {
+        let (x, s) = s.receive().await?;
+        let (y, s) = s.receive().await?;
+        Ok((x + y, s))
+    })
+    .await
+}
+
+#[test]
+fn keyed() {
+    let Roles(mut c, mut s) = Roles::default();
+    let (_, x) = executor::block_on(async { try_join!(payer(&mut c), payee(&mut s)) }).unwrap();
+    assert_eq!(x, 3);
+}
+
+#[test]
+fn renamed() {
+    let Roles(mut c, mut s) = Roles::default();
+    let (x, _) = executor::block_on(async { try_join!(client(&mut c), server(&mut s)) }).unwrap();
+    assert_eq!(x, 2);
+}
@@ -0,0 +1,9 @@
+use rumpsteak::protocol;
+
+protocol! {
+    global protocol Auth(role C, role S) {
+        Label(x: i32) from C to S;
+    }
+}
+
+fn main() {}
@@ -0,0 +1,15 @@
+use rumpsteak::protocol;
+
+// L acts differently in each branch, but S never tells it which was chosen.
+protocol! {
+    global protocol Auth(role C, role S, role L) {
+        choice at S {
+            Success() from S to C;
+            Granted() from L to C;
+        } or {
+            Failure() from S to C;
+        }
+    }
+}
+
+fn main() {}
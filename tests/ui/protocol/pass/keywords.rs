@@ -0,0 +1,13 @@
+use rumpsteak::protocol;
+
+// Names which are keywords once in snake case are emitted as raw identifiers.
+protocol! {
+    global protocol Loop(role Ref, role Type) {
+        Match(x: i32) from Ref to Type;
+    }
+}
+
+fn main() {
+    let r#loop::Roles { r#ref, r#type } = r#loop::Roles::default();
+    let _: (r#loop::Ref, r#loop::Type) = (r#ref, r#type);
+}
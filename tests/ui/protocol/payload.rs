@@ -0,0 +1,10 @@
+use rumpsteak::protocol;
+
+protocol! {
+    global protocol Auth(role C, role S) {
+        Password(x: i32) from C to S;
+        Password(x: u32) from C to S;
+    }
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use rumpsteak::protocol;
+
+protocol! {
+    global protocol Auth(role C, role S, role AuthC2) {
+        Password(x: i32) from C to S;
+    }
+}
+
+fn main() {}
@@ -0,0 +1,13 @@
+use rumpsteak::protocol;
+
+protocol! {
+    global protocol Auth(role C, role S) {
+        rec Login {
+            Password(x: i32) from C to S;
+            continue Login;
+            Success() from S to C;
+        }
+    }
+}
+
+fn main() {}